@@ -1,7 +1,76 @@
+use crate::models::TransactionStatus;
+use crate::payment::{PaymentError, PaymentProcessor};
+use crate::payment::query::TransactionQuery;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
 use warp::Filter;
+use warp::http::StatusCode;
 use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
 
-pub async fn run_api_server() {
+// Tham số query của GET /api/transactions; currency là ký hiệu, ví dụ "ETH" hoặc "RETAIL"
+#[derive(Debug, Default, Deserialize)]
+pub struct TransactionSearchParams {
+    pub address: Option<String>,
+    pub currency: Option<String>,
+    pub status: Option<TransactionStatus>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl TransactionSearchParams {
+    // Ký hiệu tiền tệ không khớp loại tiền nào mà processor hỗ trợ là lỗi, không phải trang rỗng
+    pub fn into_query(self, processor: &PaymentProcessor) -> Result<TransactionQuery, PaymentError> {
+        let currency = match self.currency {
+            Some(symbol) => Some(processor.find_currency(&symbol).ok_or(PaymentError::UnknownCurrency(symbol))?),
+            None => None,
+        };
+        Ok(TransactionQuery {
+            address: self.address,
+            currency,
+            status: self.status,
+            since: self.since,
+            until: self.until,
+            min_amount: self.min_amount,
+            max_amount: self.max_amount,
+            cursor: self.cursor,
+            limit: self.limit,
+        })
+    }
+}
+
+pub fn transaction_routes(
+    processor: Arc<Mutex<PaymentProcessor>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("api" / "transactions")
+        .and(warp::get())
+        .and(warp::query::<TransactionSearchParams>())
+        .and(warp::any().map(move || processor.clone()))
+        .and_then(search_transactions)
+}
+
+async fn search_transactions(
+    params: TransactionSearchParams,
+    processor: Arc<Mutex<PaymentProcessor>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let processor = processor.lock().expect("payment processor lock poisoned");
+    let result = params.into_query(&processor)
+        .and_then(|query| processor.search_transactions(&query));
+
+    Ok(match result {
+        Ok(page) => warp::reply::with_status(warp::reply::json(&page), StatusCode::OK),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+            StatusCode::BAD_REQUEST,
+        ),
+    })
+}
+
+pub async fn run_api_server(processor: Arc<Mutex<PaymentProcessor>>) {
     println!("🚀 Starting RetailChain API Server...");
     
     // Simple routes
@@ -30,6 +99,7 @@ pub async fn run_api_server() {
     let routes = hello
         .or(blockchain)
         .or(products)
+        .or(transaction_routes(processor))
         .with(warp::cors().allow_any_origin());
 
     println!("🌐 Server running at: http://localhost:8080");
@@ -37,6 +107,7 @@ pub async fn run_api_server() {
     println!("   http://localhost:8080/hello");
     println!("   http://localhost:8080/api/blockchain"); 
     println!("   http://localhost:8080/api/products");
+    println!("   http://localhost:8080/api/transactions?address=&currency=&status=&since=&until=&min_amount=&max_amount=&cursor=&limit=");
     
     warp::serve(routes)
         .run(([127, 0, 0, 1], 8080))
@@ -1,5 +1,6 @@
-use crate::models::{Block, Transaction};
+use crate::models::{Block, ChainAnchor, Transaction};
 use chrono::Utc;
+use uuid::Uuid;
 use sha2::{Sha256, Digest};
 use serde_json;
 use hex;
@@ -7,6 +8,7 @@ use hex;
 pub struct Blockchain {
     pub chain: Vec<Block>,
     pending_transactions: Vec<Transaction>,
+    pending_anchors: Vec<ChainAnchor>,
     difficulty: u64,
 }
 
@@ -15,6 +17,7 @@ impl Blockchain {
         let mut blockchain = Self {
             chain: Vec::new(),
             pending_transactions: Vec::new(),
+            pending_anchors: Vec::new(),
             difficulty: 2,
         };
 
@@ -31,6 +34,7 @@ impl Blockchain {
             previous_hash: String::from("0"),
             hash: String::new(),
             nonce: 0,
+            anchors: Vec::new(),
         };
 
         let hash = self.calculate_hash(&genesis_block);
@@ -45,8 +49,39 @@ impl Blockchain {
         println!("📝 Transaction added to pending pool");
     }
 
+    // Neo mã băm của `payload` vào block kế tiếp được đào
+    pub fn add_anchor(&mut self, reference: String, payload: &serde_json::Value) -> ChainAnchor {
+        let anchor = ChainAnchor {
+            id: Uuid::new_v4(),
+            reference,
+            digest: Self::digest(payload),
+            timestamp: Utc::now(),
+        };
+        self.pending_anchors.push(anchor.clone());
+        println!("⚓ Anchor for {} added to pending pool", anchor.reference);
+        anchor
+    }
+
+    // Block chứa neo của bản ghi, kể cả khi neo vẫn đang chờ đào (block = None)
+    pub fn find_anchor(&self, reference: &str) -> Option<(Option<&Block>, &ChainAnchor)> {
+        self.chain.iter()
+            .flat_map(|block| block.anchors.iter().map(move |anchor| (Some(block), anchor)))
+            .chain(self.pending_anchors.iter().map(|anchor| (None, anchor)))
+            .find(|(_, anchor)| anchor.reference == reference)
+    }
+
+    // Nội dung khớp với neo đã ghi nếu mã băm trùng nhau
+    pub fn verify_anchor(&self, reference: &str, payload: &serde_json::Value) -> bool {
+        self.find_anchor(reference)
+            .is_some_and(|(_, anchor)| anchor.digest == Self::digest(payload))
+    }
+
+    pub fn digest(payload: &serde_json::Value) -> String {
+        hex::encode(Sha256::digest(payload.to_string().as_bytes()))
+    }
+
     pub fn mine_block(&mut self) -> Result<Block, BlockchainError> {
-        if self.pending_transactions.is_empty() {
+        if self.pending_transactions.is_empty() && self.pending_anchors.is_empty() {
             return Err(BlockchainError::NoTransactions);
         }
 
@@ -58,11 +93,13 @@ impl Blockchain {
             previous_hash: last_block.hash.clone(),
             hash: String::new(),
             nonce: 0,
+            anchors: self.pending_anchors.clone(),
         };
 
         self.proof_of_work(&mut new_block);
         self.chain.push(new_block.clone());
         self.pending_transactions.clear();
+        self.pending_anchors.clear();
 
         println!("⛏️  Block #{} mined with {} transactions", 
                  new_block.index, new_block.transactions.len());
@@ -82,13 +119,17 @@ impl Blockchain {
     }
 
     fn calculate_hash(&self, block: &Block) -> String {
-        let block_data = serde_json::json!({
+        let mut block_data = serde_json::json!({
             "index": block.index,
             "timestamp": block.timestamp.to_rfc3339(),
             "transactions": serde_json::to_value(&block.transactions).unwrap(),
             "previous_hash": block.previous_hash,
             "nonce": block.nonce,
         });
+        // Block không có neo giữ nguyên mã băm như trước khi có neo
+        if !block.anchors.is_empty() {
+            block_data["anchors"] = serde_json::to_value(&block.anchors).unwrap();
+        }
 
         let mut hasher = Sha256::new();
         hasher.update(block_data.to_string().as_bytes());
@@ -138,7 +179,7 @@ impl Blockchain {
 
 #[derive(Debug, thiserror::Error)]
 pub enum BlockchainError {
-    #[error("No transactions to mine")]
+    #[error("No transactions or anchors to mine")]
     NoTransactions,
     #[allow(dead_code)]
     #[error("Invalid blockchain")]
@@ -0,0 +1,33 @@
+use super::InventoryError;
+
+// Chuẩn hóa mã vạch về dạng EAN-13: UPC-A (12 số) chính là EAN-13 có số 0 ở đầu,
+// nên quét mã nào cũng tìm được cùng một sản phẩm
+pub fn normalize_barcode(barcode: &str) -> Result<String, InventoryError> {
+    let barcode = barcode.trim();
+    let invalid = || InventoryError::InvalidBarcode(barcode.to_string());
+
+    if !barcode.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let ean13 = match barcode.len() {
+        12 => format!("0{}", barcode),
+        13 => barcode.to_string(),
+        _ => return Err(invalid()),
+    };
+
+    let digits: Vec<u32> = ean13.chars().filter_map(|c| c.to_digit(10)).collect();
+    if check_digit(&digits[..12]) != digits[12] {
+        return Err(invalid());
+    }
+    Ok(ean13)
+}
+
+// Chữ số kiểm tra GTIN: tính từ phải sang, vị trí lẻ nhân 3, vị trí chẵn nhân 1
+pub fn check_digit(digits: &[u32]) -> u32 {
+    let sum: u32 = digits.iter()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| if index % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    (10 - sum % 10) % 10
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Người thực hiện cho các thay đổi do hệ thống tự tạo, ví dụ tồn kho ban đầu
+pub const SYSTEM_USER: &str = "system";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MovementReason {
+    Sale,
+    Receipt,
+    Adjustment,
+    Shrinkage,
+    Return,
+    Transfer,
+}
+
+impl MovementReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MovementReason::Sale => "Sale",
+            MovementReason::Receipt => "Receipt",
+            MovementReason::Adjustment => "Adjustment",
+            MovementReason::Shrinkage => "Shrinkage",
+            MovementReason::Return => "Return",
+            MovementReason::Transfer => "Transfer",
+        }
+    }
+
+    // Bán và hao hụt chỉ làm giảm tồn kho, nhập hàng và trả hàng chỉ làm tăng; điều chỉnh và chuyển kho theo cả hai chiều
+    pub fn allows_change(&self, quantity_change: i64) -> bool {
+        match self {
+            MovementReason::Sale | MovementReason::Shrinkage => quantity_change < 0,
+            MovementReason::Receipt | MovementReason::Return => quantity_change > 0,
+            MovementReason::Adjustment | MovementReason::Transfer => quantity_change != 0,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Sale" => Some(MovementReason::Sale),
+            "Receipt" => Some(MovementReason::Receipt),
+            "Adjustment" => Some(MovementReason::Adjustment),
+            "Shrinkage" => Some(MovementReason::Shrinkage),
+            "Return" => Some(MovementReason::Return),
+            "Transfer" => Some(MovementReason::Transfer),
+            _ => None,
+        }
+    }
+}
+
+// Một dòng sổ kho, chỉ ghi thêm, không bao giờ sửa hay xóa.
+// Tồn kho tại một địa điểm bằng tổng `quantity_change` của các dòng tại địa điểm đó
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StockMovement {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub location_id: String,
+    pub quantity_change: i64,
+    pub reason: MovementReason,
+    pub user: String,
+    pub timestamp: DateTime<Utc>,
+    // Mã tham chiếu: đơn hàng, giao dịch hoàn tiền, phiếu chuyển kho...
+    pub reference: Option<String>,
+    // Lô bị thay đổi; không có với hàng không theo lô
+    #[serde(default)]
+    pub lot_number: Option<String>,
+    // Số serial của thiết bị bị thay đổi, nếu có
+    #[serde(default)]
+    pub serial_number: Option<String>,
+    // Giá vốn đơn vị: giá nhập với dòng tăng, giá vốn xuất với dòng giảm. Không có với chuyển kho
+    #[serde(default)]
+    pub unit_cost: Option<f64>,
+    // Giá bán đơn vị tại thời điểm bán hoặc nhận hàng trả lại
+    #[serde(default)]
+    pub unit_price: Option<f64>,
+}
+
+impl StockMovement {
+    // Tổng giá vốn của dòng, luôn dương
+    pub fn total_cost(&self) -> f64 {
+        self.unit_cost.unwrap_or(0.0) * self.quantity_change.unsigned_abs() as f64
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Địa điểm mặc định: số lượng ban đầu của sản phẩm và dữ liệu cũ trước khi có nhiều địa điểm
+pub const DEFAULT_LOCATION: &str = "MAIN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LocationKind {
+    Store,
+    Warehouse,
+    // Hàng đang trên đường vận chuyển giữa hai địa điểm
+    InTransit,
+}
+
+impl LocationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LocationKind::Store => "Store",
+            LocationKind::Warehouse => "Warehouse",
+            LocationKind::InTransit => "InTransit",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Store" => Some(LocationKind::Store),
+            "Warehouse" => Some(LocationKind::Warehouse),
+            "InTransit" => Some(LocationKind::InTransit),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub id: String,
+    pub name: String,
+    pub kind: LocationKind,
+    // Ngưỡng sắp hết hàng riêng; nếu không có thì dùng ngưỡng chung của InventoryManager
+    pub low_stock_threshold: Option<u32>,
+}
+
+impl Location {
+    pub fn new(id: String, name: String, kind: LocationKind) -> Self {
+        Self {
+            id,
+            name,
+            kind,
+            low_stock_threshold: None,
+        }
+    }
+
+    pub fn with_low_stock_threshold(mut self, threshold: u32) -> Self {
+        self.low_stock_threshold = Some(threshold);
+        self
+    }
+
+    pub fn default_store() -> Self {
+        Self::new(DEFAULT_LOCATION.to_string(), "Main store".to_string(), LocationKind::Store)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StockLevel {
+    pub product_id: Uuid,
+    pub location_id: String,
+    pub quantity: u32,
+}
@@ -0,0 +1,60 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Thông tin lô khi nhập hàng
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LotInfo {
+    pub lot_number: String,
+    pub manufactured_on: NaiveDate,
+    pub expires_on: Option<NaiveDate>,
+    // Giá nhập đơn vị; không có thì dùng giá vốn bình quân hiện tại
+    pub unit_cost: Option<f64>,
+}
+
+impl LotInfo {
+    pub fn new(lot_number: String, manufactured_on: NaiveDate) -> Self {
+        Self {
+            lot_number,
+            manufactured_on,
+            expires_on: None,
+            unit_cost: None,
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_on: NaiveDate) -> Self {
+        self.expires_on = Some(expires_on);
+        self
+    }
+
+    pub fn with_unit_cost(mut self, unit_cost: f64) -> Self {
+        self.unit_cost = Some(unit_cost);
+        self
+    }
+}
+
+// Số lượng còn lại của một lô tại một địa điểm. Cùng một số lô có thể nằm ở nhiều địa điểm
+// sau khi chuyển kho; tổng các lô tại một địa điểm không vượt quá tồn kho ở đó, phần còn lại
+// là hàng không theo lô
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lot {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub location_id: String,
+    pub lot_number: String,
+    pub manufactured_on: NaiveDate,
+    pub expires_on: Option<NaiveDate>,
+    pub quantity: u32,
+}
+
+impl Lot {
+    // Vẫn bán được trong ngày hết hạn
+    pub fn is_expired(&self, today: NaiveDate) -> bool {
+        self.expires_on.is_some_and(|expires_on| expires_on < today)
+    }
+
+    // Thứ tự FEFO: hết hạn sớm trước, lô không có hạn dùng sau cùng
+    pub(crate) fn fefo_key(&self) -> (bool, Option<NaiveDate>, NaiveDate, &str) {
+        (self.expires_on.is_none(), self.expires_on, self.manufactured_on, &self.lot_number)
+    }
+}
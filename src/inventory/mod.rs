@@ -1,20 +1,86 @@
-use crate::models::Product;
+pub mod barcode;
+pub mod ledger;
+pub mod location;
+pub mod lot;
+pub mod reorder;
+pub mod reservation;
+pub mod serial;
+pub mod sqlite;
+pub mod storage;
+pub mod valuation;
+
+use crate::blockchain::Blockchain;
+use crate::models::{Product, SupplyChainAction};
+use crate::supply_chain::{SupplyChainError, SupplyChainManager};
+use barcode::normalize_barcode;
+use ledger::{MovementReason, StockMovement, SYSTEM_USER};
+use location::{Location, DEFAULT_LOCATION};
+use lot::{Lot, LotInfo};
+use reorder::{ReorderPolicy, SuggestedOrderLine, SuggestedPurchaseOrder};
+use reservation::Reservation;
+use serial::{normalize_serial, SerializedUnit, UnitStatus};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use storage::{InMemoryStore, InventoryStore, StorageError, StoreChange};
+use valuation::{CostLayers, GrossMargin, OpeningStock, ValuationLine, ValuationMethod, ValuationReport};
 use uuid::Uuid;
 use std::collections::HashMap;
 
+// `products` là bộ đệm của kho lưu trữ: mọi thay đổi được ghi xuống store trước,
+// chỉ khi ghi thành công mới cập nhật bộ đệm và các chỉ mục.
+// `Product.quantity` luôn là tổng tồn kho của sản phẩm ở mọi địa điểm
 pub struct InventoryManager {
     products: HashMap<Uuid, Product>,
+    locations: HashMap<String, Location>,
+    // Sản phẩm -> địa điểm -> số lượng
+    stock_levels: HashMap<Uuid, HashMap<String, u32>>,
+    // SKU đã chuẩn hóa (chữ hoa) -> sản phẩm
+    sku_index: HashMap<String, Uuid>,
+    // Mã vạch EAN-13 -> sản phẩm
+    barcode_index: HashMap<String, Uuid>,
+    // Điểm đặt hàng lại riêng; sản phẩm không có thì dùng `low_stock_threshold`
+    reorder_policies: HashMap<Uuid, ReorderPolicy>,
+    // Hàng đang giữ cho đơn chờ thanh toán. Chỉ giữ trong bộ nhớ vì mỗi lần giữ chỉ kéo dài
+    // vài phút; giữ chỗ đã hết hạn không còn được tính dù chưa dọn
+    reservations: HashMap<Uuid, Reservation>,
+    lots: HashMap<Uuid, Lot>,
+    units: HashMap<Uuid, SerializedUnit>,
+    // Số serial đã chuẩn hóa -> thiết bị
+    serial_index: HashMap<String, Uuid>,
+    // Lớp giá vốn hiện tại của từng sản phẩm, dựng lại từ sổ kho khi nạp
+    cost_layers: HashMap<Uuid, CostLayers>,
+    valuation_method: ValuationMethod,
     low_stock_threshold: u32,
+    store: Box<dyn InventoryStore>,
 }
 
 impl InventoryManager {
     pub fn new(low_stock_threshold: u32) -> Self {
-        Self {
+        Self::with_store(low_stock_threshold, Box::new(InMemoryStore::new()))
+            .expect("in-memory store always loads")
+    }
+
+    pub fn with_store(low_stock_threshold: u32, store: Box<dyn InventoryStore>) -> Result<Self, InventoryError> {
+        let mut manager = Self {
             products: HashMap::new(),
+            locations: HashMap::new(),
+            stock_levels: HashMap::new(),
+            sku_index: HashMap::new(),
+            barcode_index: HashMap::new(),
+            reorder_policies: HashMap::new(),
+            reservations: HashMap::new(),
+            lots: HashMap::new(),
+            units: HashMap::new(),
+            serial_index: HashMap::new(),
+            cost_layers: HashMap::new(),
+            valuation_method: ValuationMethod::Fifo,
             low_stock_threshold,
-        }
+            store,
+        };
+        manager.reload()?;
+        Ok(manager)
     }
 
+    // Số lượng ban đầu được nhập vào địa điểm mặc định
     pub fn add_product(
         &mut self,
         name: String,
@@ -23,7 +89,26 @@ impl InventoryManager {
         price: f64,
         quantity: u32,
         manufacturer: String,
-    ) -> Product {
+    ) -> Result<Product, InventoryError> {
+        self.add_product_with_opening_stock(name, sku, description, price, manufacturer, OpeningStock::new(quantity, 0.0))
+    }
+
+    // Như `add_product` nhưng tồn kho ban đầu có giá vốn, để giá trị tồn kho và lãi gộp của
+    // những lần bán đầu tiên đúng ngay từ đầu
+    pub fn add_product_with_opening_stock(
+        &mut self,
+        name: String,
+        sku: String,
+        description: String,
+        price: f64,
+        manufacturer: String,
+        opening: OpeningStock,
+    ) -> Result<Product, InventoryError> {
+        if !opening.unit_cost.is_finite() || opening.unit_cost < 0.0 {
+            return Err(InventoryError::InvalidUnitCost);
+        }
+        let quantity = opening.quantity;
+        let sku = self.check_sku_available(&sku, None)?;
         let product = Product {
             id: Uuid::new_v4(),
             sku,
@@ -32,52 +117,1541 @@ impl InventoryManager {
             price,
             quantity,
             manufacturer,
+            barcodes: Vec::new(),
             created_at: chrono::Utc::now(),
         };
 
+        let mut changes = vec![StoreChange::InsertProduct(product.clone())];
+        if quantity > 0 {
+            changes.push(StoreChange::SetStockLevel {
+                product_id: product.id,
+                location_id: DEFAULT_LOCATION.to_string(),
+                expected: 0,
+                quantity,
+            });
+            changes.push(StoreChange::AppendMovement(StockMovement {
+                id: Uuid::new_v4(),
+                product_id: product.id,
+                location_id: DEFAULT_LOCATION.to_string(),
+                quantity_change: i64::from(quantity),
+                reason: MovementReason::Receipt,
+                user: SYSTEM_USER.to_string(),
+                timestamp: product.created_at,
+                reference: Some("initial stock".to_string()),
+                lot_number: None,
+                serial_number: None,
+                unit_cost: Some(opening.unit_cost),
+                unit_price: None,
+            }));
+        }
+        self.store.apply(&changes)?;
+
+        if quantity > 0 {
+            self.stock_levels.entry(product.id).or_default().insert(DEFAULT_LOCATION.to_string(), quantity);
+        }
+        let mut layers = CostLayers::default();
+        layers.receive(quantity, opening.unit_cost, self.valuation_method);
+        self.cost_layers.insert(product.id, layers);
+        self.sku_index.insert(normalize_sku(&product.sku), product.id);
         self.products.insert(product.id, product.clone());
-        product
+        Ok(product)
+    }
+
+    pub fn add_location(&mut self, location: Location) -> Result<(), InventoryError> {
+        if location.id.trim().is_empty() {
+            return Err(InventoryError::InvalidLocation);
+        }
+        if self.locations.contains_key(&location.id) {
+            return Err(InventoryError::DuplicateLocation(location.id));
+        }
+
+        self.store.apply(&[StoreChange::InsertLocation(location.clone())])?;
+        self.locations.insert(location.id.clone(), location);
+        Ok(())
     }
 
-    pub fn update_stock(&mut self, product_id: Uuid, new_quantity: u32) -> Result<(), InventoryError> {
+    pub fn update_sku(&mut self, product_id: Uuid, sku: String) -> Result<(), InventoryError> {
+        let sku = self.check_sku_available(&sku, Some(product_id))?;
         let product = self.products.get_mut(&product_id)
             .ok_or(InventoryError::ProductNotFound)?;
 
-        product.quantity = new_quantity;
+        self.store.apply(&[StoreChange::UpdateSku { product_id, sku: sku.clone() }])?;
+        self.sku_index.remove(&normalize_sku(&product.sku));
+        self.sku_index.insert(normalize_sku(&sku), product_id);
+        product.sku = sku;
         Ok(())
     }
 
-    pub fn sell_product(&mut self, product_id: Uuid, quantity: u32) -> Result<(), InventoryError> {
+    // Trả về mã vạch đã chuẩn hóa về EAN-13
+    pub fn add_barcode(&mut self, product_id: Uuid, barcode: &str) -> Result<String, InventoryError> {
+        let barcode = normalize_barcode(barcode)?;
+        if let Some(owner) = self.barcode_index.get(&barcode) {
+            if *owner == product_id {
+                return Ok(barcode);
+            }
+            return Err(InventoryError::DuplicateBarcode(barcode));
+        }
+        let product = self.products.get_mut(&product_id)
+            .ok_or(InventoryError::ProductNotFound)?;
+
+        self.store.apply(&[StoreChange::AddBarcode { product_id, barcode: barcode.clone() }])?;
+        self.barcode_index.insert(barcode.clone(), product_id);
+        product.barcodes.push(barcode.clone());
+        Ok(barcode)
+    }
+
+    pub fn remove_barcode(&mut self, product_id: Uuid, barcode: &str) -> Result<(), InventoryError> {
+        let barcode = normalize_barcode(barcode)?;
+        if self.barcode_index.get(&barcode) != Some(&product_id) {
+            return Err(InventoryError::BarcodeNotFound(barcode));
+        }
         let product = self.products.get_mut(&product_id)
             .ok_or(InventoryError::ProductNotFound)?;
 
-        if product.quantity < quantity {
+        self.store.apply(&[StoreChange::RemoveBarcode { product_id, barcode: barcode.clone() }])?;
+        self.barcode_index.remove(&barcode);
+        product.barcodes.retain(|existing| existing != &barcode);
+        Ok(())
+    }
+
+    // SKU so sánh không phân biệt hoa thường; trả về SKU đã bỏ khoảng trắng thừa
+    fn check_sku_available(&self, sku: &str, product_id: Option<Uuid>) -> Result<String, InventoryError> {
+        let sku = sku.trim();
+        if sku.is_empty() {
+            return Err(InventoryError::InvalidSku);
+        }
+        match self.sku_index.get(&normalize_sku(sku)) {
+            Some(owner) if Some(*owner) != product_id => Err(InventoryError::DuplicateSku(sku.to_string())),
+            _ => Ok(sku.to_string()),
+        }
+    }
+
+    // Kiểm kê: đặt lại số lượng tại địa điểm, chênh lệch được ghi thành một dòng Adjustment
+    pub fn update_stock(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        new_quantity: u32,
+        user: &str,
+    ) -> Result<(), InventoryError> {
+        let current = self.get_stock_at(product_id, location_id);
+        let change = i64::from(new_quantity) - i64::from(current);
+        if change == 0 {
+            return Ok(());
+        }
+        self.record_stock_movement(product_id, location_id, change, MovementReason::Adjustment, user, None)?;
+        Ok(())
+    }
+
+    // Chỉ bán được phần hàng chưa bị giữ cho đơn khác. `reference` là mã đơn hàng, được ghi vào sổ kho
+    // Trả về các dòng sổ kho đã ghi, mỗi dòng mang giá vốn và giá bán để tính lãi gộp
+    pub fn sell_product(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        quantity: u32,
+        unit_price: f64,
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if quantity == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        check_unit_price(unit_price)?;
+        self.ensure_available(product_id, location_id, quantity)?;
+        let deltas = self.pick_stock(product_id, location_id, quantity, true)?
+            .into_iter()
+            .map(|delta| delta.priced(unit_price))
+            .collect();
+        self.apply_stock_deltas(product_id, deltas, MovementReason::Sale, user, reference, None)
+    }
+
+    // `unit_cost` là giá nhập; không có thì tính theo giá vốn bình quân hiện tại
+    pub fn restock_product(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        quantity: u32,
+        unit_cost: Option<f64>,
+        user: &str,
+    ) -> Result<(), InventoryError> {
+        match unit_cost {
+            Some(unit_cost) => self.receive_stock(product_id, location_id, quantity, unit_cost, user, None)?,
+            None => self.record_stock_movement(product_id, location_id, i64::from(quantity), MovementReason::Receipt, user, None)?,
+        };
+        Ok(())
+    }
+
+    // Nhập lại hàng khách trả của nhiều sản phẩm trong một lần ghi: hoặc nhập hết, hoặc không gì cả.
+    // Cùng một sản phẩm xuất hiện nhiều lần được cộng dồn
+    pub fn restock_returns(
+        &mut self,
+        location_id: &str,
+        items: &[(Uuid, u32)],
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+        let mut merged: Vec<(Uuid, u32)> = Vec::new();
+        for &(product_id, quantity) in items {
+            if quantity == 0 {
+                return Err(InventoryError::InvalidQuantity);
+            }
+            if !self.products.contains_key(&product_id) {
+                return Err(InventoryError::ProductNotFound);
+            }
+            match merged.iter_mut().find(|(id, _)| *id == product_id) {
+                Some((_, total)) => *total = total.checked_add(quantity).ok_or(InventoryError::QuantityOverflow)?,
+                None => merged.push((product_id, quantity)),
+            }
+        }
+
+        let staged = merged.into_iter()
+            .map(|(product_id, quantity)| {
+                let deltas = vec![StockDelta::untracked(location_id, i64::from(quantity))];
+                self.stage_stock_deltas(product_id, deltas, MovementReason::Return, user, reference.clone(), None)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.commit_staged_stock(staged)
+    }
+
+    // Ghi một thay đổi tồn kho bất kỳ (ví dụ hao hụt, hàng trả lại) kèm lý do và người thực hiện.
+    // Phần giảm được lấy theo FEFO nên có thể thành nhiều dòng sổ kho, mỗi lô một dòng;
+    // phần tăng không gắn lô, dùng `receive_lot` để nhập hàng theo lô
+    pub fn record_stock_movement(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        quantity_change: i64,
+        reason: MovementReason,
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if quantity_change == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        if reason == MovementReason::Transfer {
+            return Err(InventoryError::InvalidTransfer);
+        }
+        if !reason.allows_change(quantity_change) {
+            return Err(InventoryError::InvalidMovementDirection(reason, quantity_change));
+        }
+        // Dòng bán phải có giá bán của người bán, nên chỉ ghi qua `sell_product`
+        if reason == MovementReason::Sale {
+            return Err(InventoryError::SaleWithoutPrice);
+        }
+
+        let deltas = if quantity_change < 0 {
+            let quantity = u32::try_from(-quantity_change).map_err(|_| InventoryError::InsufficientStock)?;
+            self.pick_stock(product_id, location_id, quantity, false)?
+        } else {
+            vec![StockDelta::untracked(location_id, quantity_change)]
+        };
+        self.apply_stock_deltas(product_id, deltas, reason, user, reference, None)
+    }
+
+    // Nhập hàng có giá nhập, ví dụ khi nhận hàng theo đơn mua. Nhập qua `record_stock_movement`
+    // hay `restock_product` không có giá nhập thì tính theo giá vốn bình quân hiện tại
+    pub fn receive_stock(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        quantity: u32,
+        unit_cost: f64,
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if quantity == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        if !unit_cost.is_finite() || unit_cost < 0.0 {
+            return Err(InventoryError::InvalidUnitCost);
+        }
+        let deltas = vec![StockDelta::untracked(location_id, i64::from(quantity))];
+        self.apply_stock_deltas(product_id, deltas, MovementReason::Receipt, user, reference, Some(unit_cost))
+    }
+
+    // Nhập nhiều sản phẩm có giá nhập trong một lần ghi, ví dụ một phiếu nhận hàng theo đơn mua:
+    // hoặc tất cả vào kho, hoặc không dòng nào. Sản phẩm lặp lại được gộp, giá nhập lấy bình quân
+    pub fn receive_stock_lines(
+        &mut self,
+        location_id: &str,
+        lines: &[(Uuid, u32, f64)],
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+        let mut merged: Vec<(Uuid, u32, f64)> = Vec::new();
+        for &(product_id, quantity, unit_cost) in lines {
+            if quantity == 0 {
+                return Err(InventoryError::InvalidQuantity);
+            }
+            if !unit_cost.is_finite() || unit_cost < 0.0 {
+                return Err(InventoryError::InvalidUnitCost);
+            }
+            if !self.products.contains_key(&product_id) {
+                return Err(InventoryError::ProductNotFound);
+            }
+            match merged.iter_mut().find(|(id, _, _)| *id == product_id) {
+                Some((_, total, cost)) => {
+                    let combined = total.checked_add(quantity).ok_or(InventoryError::QuantityOverflow)?;
+                    *cost = (*cost * f64::from(*total) + unit_cost * f64::from(quantity)) / f64::from(combined);
+                    *total = combined;
+                }
+                None => merged.push((product_id, quantity, unit_cost)),
+            }
+        }
+
+        let staged = merged.into_iter()
+            .map(|(product_id, quantity, unit_cost)| {
+                let deltas = vec![StockDelta::untracked(location_id, i64::from(quantity))];
+                self.stage_stock_deltas(product_id, deltas, MovementReason::Receipt, user, reference.clone(), Some(unit_cost))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.commit_staged_stock(staged)
+    }
+
+    // Nhập hàng theo lô; lô đã có tại địa điểm thì cộng thêm vào lô đó
+    pub fn receive_lot(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        info: LotInfo,
+        quantity: u32,
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<Lot, InventoryError> {
+        if quantity == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        let lot_number = info.lot_number.trim().to_string();
+        if lot_number.is_empty() {
+            return Err(InventoryError::InvalidLot("lot number is empty".to_string()));
+        }
+        if info.unit_cost.is_some_and(|unit_cost| !unit_cost.is_finite() || unit_cost < 0.0) {
+            return Err(InventoryError::InvalidUnitCost);
+        }
+        if info.expires_on.is_some_and(|expires_on| expires_on < info.manufactured_on) {
+            return Err(InventoryError::InvalidLot(format!("lot {} expires before it was manufactured", lot_number)));
+        }
+        let mut same_number = self.lots.values()
+            .filter(|lot| lot.product_id == product_id && lot.lot_number == lot_number);
+        if same_number.any(|lot| lot.manufactured_on != info.manufactured_on || lot.expires_on != info.expires_on) {
+            return Err(InventoryError::InvalidLot(format!("lot {} is already recorded with different dates", lot_number)));
+        }
+
+        let lot = match self.find_lot(product_id, location_id, &lot_number) {
+            Some(existing) => Lot {
+                quantity: existing.quantity.checked_add(quantity).ok_or(InventoryError::QuantityOverflow)?,
+                ..existing.clone()
+            },
+            None => Lot {
+                id: Uuid::new_v4(),
+                product_id,
+                location_id: location_id.to_string(),
+                lot_number,
+                manufactured_on: info.manufactured_on,
+                expires_on: info.expires_on,
+                quantity,
+            },
+        };
+        let delta = StockDelta {
+            location_id: location_id.to_string(),
+            change: i64::from(quantity),
+            lot: Some(lot.clone()),
+            unit: None,
+            unit_price: None,
+        };
+        self.apply_stock_deltas(product_id, vec![delta], MovementReason::Receipt, user, reference, info.unit_cost)?;
+        Ok(lot)
+    }
+
+    // Chuyển hàng giữa hai địa điểm trong một lần ghi: không bao giờ chỉ trừ bên này mà không cộng bên kia
+    pub fn transfer_stock(
+        &mut self,
+        product_id: Uuid,
+        from_location: &str,
+        to_location: &str,
+        quantity: u32,
+        user: &str,
+    ) -> Result<(), InventoryError> {
+        if from_location == to_location || quantity == 0 {
+            return Err(InventoryError::InvalidTransfer);
+        }
+        self.ensure_available(product_id, from_location, quantity)?;
+
+        if !self.locations.contains_key(to_location) {
+            return Err(InventoryError::LocationNotFound(to_location.to_string()));
+        }
+
+        // Lô đi theo hàng: mỗi phần lấy từ một lô ở nơi đi được cộng vào lô cùng số ở nơi đến
+        let outgoing = self.pick_stock(product_id, from_location, quantity, false)?;
+        let mut deltas = Vec::new();
+        for delta in outgoing {
+            let incoming_lot = delta.lot.as_ref().map(|lot| {
+                let moved = delta.taken();
+                match self.find_lot(product_id, to_location, &lot.lot_number) {
+                    Some(existing) => Lot { quantity: existing.quantity + moved, ..existing.clone() },
+                    None => Lot {
+                        id: Uuid::new_v4(),
+                        location_id: to_location.to_string(),
+                        quantity: moved,
+                        ..lot.clone()
+                    },
+                }
+            });
+            let incoming = StockDelta {
+                location_id: to_location.to_string(),
+                change: -delta.change,
+                lot: incoming_lot,
+                unit: None,
+                unit_price: None,
+            };
+            deltas.push(delta);
+            deltas.push(incoming);
+        }
+
+        // Các dòng sổ kho của cùng một lần chuyển dùng chung một mã tham chiếu
+        let reference = format!("transfer:{}", Uuid::new_v4());
+        self.apply_stock_deltas(product_id, deltas, MovementReason::Transfer, user, Some(reference), None)?;
+        Ok(())
+    }
+
+    // Nhập các thiết bị theo số serial: mỗi thiết bị thêm một đơn vị tồn kho và có lịch sử
+    // chuỗi cung ứng riêng, bắt đầu bằng `Received`
+    pub fn receive_serialized_units(
+        &mut self,
+        product_id: Uuid,
+        location_id: &str,
+        serial_numbers: &[&str],
+        unit_cost: f64,
+        user: &str,
+        supply_chain: &mut SupplyChainManager,
+    ) -> Result<Vec<SerializedUnit>, InventoryError> {
+        if serial_numbers.is_empty() {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        if !unit_cost.is_finite() || unit_cost < 0.0 {
+            return Err(InventoryError::InvalidUnitCost);
+        }
+        // Kiểm tra mọi điều kiện trước khi ghi tồn kho, để phần ghi chuỗi cung ứng phía sau không thể lỗi
+        if !self.products.contains_key(&product_id) {
+            return Err(InventoryError::ProductNotFound);
+        }
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+        if !supply_chain.is_tracked(product_id) {
+            return Err(SupplyChainError::ProductNotFound.into());
+        }
+
+        let received_at = Utc::now();
+        let mut units: Vec<SerializedUnit> = Vec::new();
+        for serial_number in serial_numbers {
+            let serial_number = normalize_serial(serial_number)?;
+            if self.serial_index.contains_key(&serial_number) || units.iter().any(|unit| unit.serial_number == serial_number) {
+                return Err(InventoryError::DuplicateSerial(serial_number));
+            }
+            units.push(SerializedUnit {
+                id: Uuid::new_v4(),
+                product_id,
+                serial_number,
+                location_id: location_id.to_string(),
+                status: UnitStatus::InStock,
+                received_at,
+                sold_at: None,
+                sale_reference: None,
+                sale_anchor: None,
+            });
+        }
+
+        let deltas = units.iter()
+            .map(|unit| StockDelta {
+                location_id: location_id.to_string(),
+                change: 1,
+                lot: None,
+                unit: Some(unit.clone()),
+                unit_price: None,
+            })
+            .collect();
+        self.apply_stock_deltas(product_id, deltas, MovementReason::Receipt, user, None, Some(unit_cost))?;
+
+        for unit in &units {
+            supply_chain.register_unit(unit.id, product_id)?;
+            supply_chain.record_unit_movement(
+                unit.id,
+                location_id.to_string(),
+                user.to_string(),
+                SupplyChainAction::Received,
+                serde_json::json!({"serial_number": unit.serial_number}),
+            )?;
+        }
+        Ok(units)
+    }
+
+    // Bán đúng thiết bị có số serial này, ghi số serial vào sổ kho và neo lần bán lên blockchain
+    pub fn sell_serialized_unit(
+        &mut self,
+        serial_number: &str,
+        unit_price: f64,
+        user: &str,
+        reference: Option<String>,
+        supply_chain: &mut SupplyChainManager,
+        blockchain: &mut Blockchain,
+    ) -> Result<SerializedUnit, InventoryError> {
+        check_unit_price(unit_price)?;
+        let unit = self.in_stock_unit(serial_number)?.clone();
+        self.ensure_available(unit.product_id, &unit.location_id, 1)?;
+        if !supply_chain.is_tracked(unit.product_id) {
+            return Err(SupplyChainError::ProductNotFound.into());
+        }
+
+        let mut sold = SerializedUnit {
+            status: UnitStatus::Sold,
+            sold_at: Some(Utc::now()),
+            sale_reference: reference.clone(),
+            ..unit
+        };
+        let payload = sold.sale_payload();
+        sold.sale_anchor = Some(Blockchain::digest(&payload));
+        let delta = StockDelta {
+            location_id: sold.location_id.clone(),
+            change: -1,
+            lot: None,
+            unit: Some(sold.clone()),
+            unit_price: Some(unit_price),
+        };
+        self.apply_stock_deltas(sold.product_id, vec![delta], MovementReason::Sale, user, reference, None)?;
+
+        blockchain.add_anchor(sold.id.to_string(), &payload);
+        // Thiết bị nạp lại từ kho lưu trữ có thể chưa có trong SupplyChainManager
+        if !supply_chain.is_unit_tracked(sold.id) {
+            supply_chain.register_unit(sold.id, sold.product_id)?;
+        }
+        supply_chain.record_unit_movement(
+            sold.id,
+            sold.location_id.clone(),
+            user.to_string(),
+            SupplyChainAction::Sold,
+            serde_json::json!({
+                "serial_number": sold.serial_number,
+                "sale_reference": sold.sale_reference,
+                "anchor": sold.sale_anchor,
+            }),
+        )?;
+        Ok(sold)
+    }
+
+    pub fn transfer_serialized_unit(
+        &mut self,
+        serial_number: &str,
+        to_location: &str,
+        user: &str,
+        supply_chain: &mut SupplyChainManager,
+    ) -> Result<SerializedUnit, InventoryError> {
+        let unit = self.in_stock_unit(serial_number)?.clone();
+        if unit.location_id == to_location {
+            return Err(InventoryError::InvalidTransfer);
+        }
+        if !self.locations.contains_key(to_location) {
+            return Err(InventoryError::LocationNotFound(to_location.to_string()));
+        }
+        self.ensure_available(unit.product_id, &unit.location_id, 1)?;
+        if !supply_chain.is_tracked(unit.product_id) {
+            return Err(SupplyChainError::ProductNotFound.into());
+        }
+
+        let moved = SerializedUnit { location_id: to_location.to_string(), ..unit.clone() };
+        let deltas = vec![
+            StockDelta { location_id: unit.location_id.clone(), change: -1, lot: None, unit: Some(moved.clone()), unit_price: None },
+            StockDelta { location_id: to_location.to_string(), change: 1, lot: None, unit: Some(moved.clone()), unit_price: None },
+        ];
+        let reference = format!("transfer:{}", Uuid::new_v4());
+        self.apply_stock_deltas(unit.product_id, deltas, MovementReason::Transfer, user, Some(reference), None)?;
+
+        if !supply_chain.is_unit_tracked(moved.id) {
+            supply_chain.register_unit(moved.id, moved.product_id)?;
+        }
+        supply_chain.record_unit_movement(
+            moved.id,
+            unit.location_id.clone(),
+            user.to_string(),
+            SupplyChainAction::Shipped,
+            serde_json::json!({"serial_number": moved.serial_number, "to": to_location}),
+        )?;
+        supply_chain.record_unit_movement(
+            moved.id,
+            to_location.to_string(),
+            user.to_string(),
+            SupplyChainAction::Received,
+            serde_json::json!({"serial_number": moved.serial_number, "from": unit.location_id}),
+        )?;
+        Ok(moved)
+    }
+
+    pub fn get_unit(&self, serial_number: &str) -> Option<&SerializedUnit> {
+        let serial_number = normalize_serial(serial_number).ok()?;
+        self.serial_index.get(&serial_number)
+            .and_then(|unit_id| self.units.get(unit_id))
+    }
+
+    // Các thiết bị của sản phẩm theo số serial, có thể lọc theo trạng thái
+    pub fn get_units(&self, product_id: Uuid, status: Option<UnitStatus>) -> Vec<&SerializedUnit> {
+        let mut units: Vec<&SerializedUnit> = self.units.values()
+            .filter(|unit| unit.product_id == product_id)
+            .filter(|unit| status.is_none_or(|status| unit.status == status))
+            .collect();
+        units.sort_by(|a, b| a.serial_number.cmp(&b.serial_number));
+        units
+    }
+
+    // Loại một thiết bị khỏi kho (mất, hỏng...) theo số serial, ghi sổ kho là hao hụt.
+    // Tồn kho theo serial không giảm được qua `update_stock` hay `record_stock_movement`
+    pub fn write_off_serialized_unit(
+        &mut self,
+        serial_number: &str,
+        user: &str,
+        reference: Option<String>,
+    ) -> Result<SerializedUnit, InventoryError> {
+        let unit = self.in_stock_unit(serial_number)?.clone();
+        self.ensure_available(unit.product_id, &unit.location_id, 1)?;
+
+        let written_off = SerializedUnit { status: UnitStatus::WrittenOff, ..unit };
+        let delta = StockDelta {
+            location_id: written_off.location_id.clone(),
+            change: -1,
+            lot: None,
+            unit: Some(written_off.clone()),
+            unit_price: None,
+        };
+        self.apply_stock_deltas(written_off.product_id, vec![delta], MovementReason::Shrinkage, user, reference, None)?;
+        Ok(written_off)
+    }
+
+    fn in_stock_unit(&self, serial_number: &str) -> Result<&SerializedUnit, InventoryError> {
+        let serial_number = normalize_serial(serial_number)?;
+        let unit = self.serial_index.get(&serial_number)
+            .and_then(|unit_id| self.units.get(unit_id))
+            .ok_or_else(|| InventoryError::SerialNotFound(serial_number.clone()))?;
+        match unit.status {
+            UnitStatus::InStock => Ok(unit),
+            UnitStatus::Sold => Err(InventoryError::UnitAlreadySold(serial_number)),
+            UnitStatus::WrittenOff => Err(InventoryError::UnitWrittenOff(serial_number)),
+        }
+    }
+
+    // Số thiết bị theo serial còn trong kho tại địa điểm
+    fn units_in_stock_at(&self, product_id: Uuid, location_id: &str) -> u32 {
+        self.units.values()
+            .filter(|unit| unit.product_id == product_id && unit.location_id == location_id)
+            .filter(|unit| unit.status == UnitStatus::InStock)
+            .count() as u32
+    }
+
+    // Giữ hàng cho đơn đang chờ thanh toán với giá bán đã chốt trên đơn, tự hết hạn sau `ttl`
+    pub fn reserve_stock(
+        &mut self,
+        order_id: &str,
+        product_id: Uuid,
+        location_id: &str,
+        quantity: u32,
+        unit_price: f64,
+        ttl: Duration,
+    ) -> Result<Reservation, InventoryError> {
+        if quantity == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+        check_unit_price(unit_price)?;
+        if !self.products.contains_key(&product_id) {
+            return Err(InventoryError::ProductNotFound);
+        }
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+        self.ensure_available(product_id, location_id, quantity)?;
+        // Giữ chỗ chỉ tính theo số lượng; thiết bị theo serial phải được bán theo số serial nên không giữ được
+        let serialized = self.units_in_stock_at(product_id, location_id);
+        if quantity > self.get_available_at(product_id, location_id).saturating_sub(serialized) {
+            return Err(InventoryError::SerialNumberRequired);
+        }
+
+        let now = Utc::now();
+        let reservation = Reservation {
+            id: Uuid::new_v4(),
+            order_id: order_id.to_string(),
+            product_id,
+            location_id: location_id.to_string(),
+            quantity,
+            unit_price,
+            created_at: now,
+            expires_at: now + ttl,
+        };
+        self.reservations.insert(reservation.id, reservation.clone());
+        Ok(reservation)
+    }
+
+    // Thanh toán đã xác nhận: bán toàn bộ hàng đang giữ của đơn, ghi sổ kho với mã đơn
+    pub fn commit_reservation(&mut self, order_id: &str, user: &str) -> Result<Vec<StockMovement>, InventoryError> {
+        let now = Utc::now();
+        let mut reservations: Vec<Reservation> = self.reservations.values()
+            .filter(|reservation| reservation.order_id == order_id)
+            .cloned()
+            .collect();
+        if reservations.is_empty() {
+            return Err(InventoryError::ReservationNotFound(order_id.to_string()));
+        }
+        if reservations.iter().any(|reservation| reservation.is_expired(now)) {
+            return Err(InventoryError::ReservationExpired(order_id.to_string()));
+        }
+        reservations.sort_by_key(|reservation| reservation.created_at);
+
+        // Gộp các giữ chỗ theo sản phẩm và địa điểm (số lượng, tổng tiền), rồi bán tất cả trong một lần ghi.
+        // Giá bán là giá bình quân của các giữ chỗ được gộp
+        let mut groups: Vec<(Uuid, String, u32, f64)> = Vec::new();
+        for reservation in &reservations {
+            let revenue = f64::from(reservation.quantity) * reservation.unit_price;
+            let existing = groups.iter_mut().find(|(product_id, location_id, _, _)| {
+                *product_id == reservation.product_id && *location_id == reservation.location_id
+            });
+            match existing {
+                Some((_, _, total, amount)) => {
+                    *total = total.checked_add(reservation.quantity).ok_or(InventoryError::QuantityOverflow)?;
+                    *amount += revenue;
+                }
+                None => groups.push((reservation.product_id, reservation.location_id.clone(), reservation.quantity, revenue)),
+            }
+        }
+        let mut product_ids: Vec<Uuid> = Vec::new();
+        for (product_id, _, _, _) in &groups {
+            if !product_ids.contains(product_id) {
+                product_ids.push(*product_id);
+            }
+        }
+
+        let mut staged = Vec::with_capacity(product_ids.len());
+        for product_id in product_ids {
+            let mut deltas = Vec::new();
+            for (_, location_id, quantity, revenue) in groups.iter().filter(|(id, _, _, _)| *id == product_id) {
+                let unit_price = revenue / f64::from(*quantity);
+                let picked = self.pick_stock(product_id, location_id, *quantity, true)?;
+                deltas.extend(picked.into_iter().map(|delta| delta.priced(unit_price)));
+            }
+            staged.push(self.stage_stock_deltas(product_id, deltas, MovementReason::Sale, user, Some(order_id.to_string()), None)?);
+        }
+        let movements = self.commit_staged_stock(staged)?;
+        for reservation in reservations {
+            self.reservations.remove(&reservation.id);
+        }
+        Ok(movements)
+    }
+
+    // Đơn bị hủy: trả lại hàng đang giữ
+    pub fn release_reservation(&mut self, order_id: &str) -> Result<Vec<Reservation>, InventoryError> {
+        let released = self.remove_reservations(|reservation| reservation.order_id == order_id);
+        if released.is_empty() {
+            return Err(InventoryError::ReservationNotFound(order_id.to_string()));
+        }
+        Ok(released)
+    }
+
+    // Dọn các giữ chỗ đã hết hạn tại thời điểm `now`, trả về các giữ chỗ đã dọn
+    pub fn release_expired_reservations(&mut self, now: DateTime<Utc>) -> Vec<Reservation> {
+        self.remove_reservations(|reservation| reservation.is_expired(now))
+    }
+
+    pub fn get_reservations(&self, order_id: &str) -> Vec<&Reservation> {
+        let mut reservations: Vec<&Reservation> = self.reservations.values()
+            .filter(|reservation| reservation.order_id == order_id)
+            .collect();
+        reservations.sort_by_key(|reservation| reservation.created_at);
+        reservations
+    }
+
+    pub fn get_reserved_at(&self, product_id: Uuid, location_id: &str) -> u32 {
+        let now = Utc::now();
+        self.reservations.values()
+            .filter(|reservation| reservation.product_id == product_id && reservation.location_id == location_id)
+            .filter(|reservation| !reservation.is_expired(now))
+            .map(|reservation| reservation.quantity)
+            .sum()
+    }
+
+    // Có thể bán = tồn kho thực tế - đang giữ
+    pub fn get_available_at(&self, product_id: Uuid, location_id: &str) -> u32 {
+        self.get_stock_at(product_id, location_id)
+            .saturating_sub(self.get_reserved_at(product_id, location_id))
+    }
+
+    pub fn get_available_quantity(&self, product_id: Uuid) -> u32 {
+        self.stock_levels.get(&product_id)
+            .into_iter()
+            .flat_map(|levels| levels.keys())
+            .map(|location_id| self.get_available_at(product_id, location_id))
+            .sum()
+    }
+
+    fn ensure_available(&self, product_id: Uuid, location_id: &str, quantity: u32) -> Result<(), InventoryError> {
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+        if self.products.contains_key(&product_id) && quantity > self.get_available_at(product_id, location_id) {
             return Err(InventoryError::InsufficientStock);
         }
+        Ok(())
+    }
+
+    fn remove_reservations(&mut self, predicate: impl Fn(&Reservation) -> bool) -> Vec<Reservation> {
+        let ids: Vec<Uuid> = self.reservations.values()
+            .filter(|reservation| predicate(reservation))
+            .map(|reservation| reservation.id)
+            .collect();
+        let mut removed: Vec<Reservation> = ids.iter()
+            .filter_map(|id| self.reservations.remove(id))
+            .collect();
+        removed.sort_by_key(|reservation| reservation.created_at);
+        removed
+    }
+
+    // Chọn hàng để xuất tại một địa điểm theo FEFO: lô hết hạn sớm nhất trước, lô không có hạn dùng
+    // sau cùng, rồi tới hàng không theo lô. Khi bán thì bỏ qua các lô đã hết hạn.
+    // Thiết bị theo serial không bao giờ được chọn ở đây
+    fn pick_stock(
+        &self,
+        product_id: Uuid,
+        location_id: &str,
+        quantity: u32,
+        skip_expired: bool,
+    ) -> Result<Vec<StockDelta>, InventoryError> {
+        if !self.products.contains_key(&product_id) {
+            return Err(InventoryError::ProductNotFound);
+        }
+        if !self.locations.contains_key(location_id) {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()));
+        }
+
+        let today = Utc::now().date_naive();
+        let mut lots: Vec<&Lot> = self.lots.values()
+            .filter(|lot| lot.product_id == product_id && lot.location_id == location_id && lot.quantity > 0)
+            .collect();
+        let in_lots: u32 = lots.iter().map(|lot| lot.quantity).sum();
+        // Thiết bị theo serial chỉ xuất được khi chỉ rõ số serial
+        let in_units = self.units_in_stock_at(product_id, location_id);
+        let untracked = self.get_stock_at(product_id, location_id).saturating_sub(in_lots + in_units);
+        lots.retain(|lot| !(skip_expired && lot.is_expired(today)));
+        lots.sort_by(|a, b| a.fefo_key().cmp(&b.fefo_key()));
+
+        let mut remaining = quantity;
+        let mut deltas = Vec::new();
+        for lot in lots {
+            if remaining == 0 {
+                break;
+            }
+            let taken = remaining.min(lot.quantity);
+            remaining -= taken;
+            deltas.push(StockDelta {
+                location_id: location_id.to_string(),
+                change: -i64::from(taken),
+                lot: Some(Lot { quantity: lot.quantity - taken, ..lot.clone() }),
+                unit: None,
+                unit_price: None,
+            });
+        }
+        if remaining > 0 {
+            if remaining > untracked {
+                return Err(match in_units {
+                    0 => InventoryError::InsufficientStock,
+                    _ => InventoryError::SerialNumberRequired,
+                });
+            }
+            deltas.push(StockDelta::untracked(location_id, -i64::from(remaining)));
+        }
+        Ok(deltas)
+    }
 
-        product.quantity -= quantity;
+    fn find_lot(&self, product_id: Uuid, location_id: &str, lot_number: &str) -> Option<&Lot> {
+        self.lots.values()
+            .find(|lot| lot.product_id == product_id && lot.location_id == location_id && lot.lot_number == lot_number)
+    }
+
+    fn apply_stock_deltas(
+        &mut self,
+        product_id: Uuid,
+        deltas: Vec<StockDelta>,
+        reason: MovementReason,
+        user: &str,
+        reference: Option<String>,
+        unit_cost: Option<f64>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        let staged = self.stage_stock_deltas(product_id, deltas, reason, user, reference, unit_cost)?;
+        self.commit_staged_stock(vec![staged])
+    }
+
+    // Tính tồn kho mới ở từng địa điểm, của từng lô và tổng mới cùng các dòng sổ kho
+    // (mỗi thay đổi một dòng), chưa ghi gì xuống kho lưu trữ.
+    // `unit_cost` là giá nhập của phần tăng; không có thì dùng giá vốn bình quân hiện tại,
+    // riêng hàng trả lại dùng giá vốn và giá bán của lần bán gần nhất. Giá bán lấy từ từng thay đổi
+    fn stage_stock_deltas(
+        &self,
+        product_id: Uuid,
+        deltas: Vec<StockDelta>,
+        reason: MovementReason,
+        user: &str,
+        reference: Option<String>,
+        unit_cost: Option<f64>,
+    ) -> Result<StagedStock, InventoryError> {
+        let product = self.products.get(&product_id)
+            .ok_or(InventoryError::ProductNotFound)?;
+        if user.trim().is_empty() {
+            return Err(InventoryError::MissingUser);
+        }
+
+        let timestamp = Utc::now();
+        let mut changes = Vec::new();
+        let mut movements = Vec::new();
+        // Địa điểm -> (số lượng hiện tại, số lượng mới)
+        let mut levels: Vec<(String, u32, i64)> = Vec::new();
+        let mut updated_lots = Vec::new();
+        let mut updated_units: Vec<SerializedUnit> = Vec::new();
+        let mut total = i64::from(product.quantity);
+        let mut layers = self.cost_layers.get(&product_id).cloned().unwrap_or_default();
+        // Hàng trả lại nhập lại theo giá vốn và giá bán của lần bán gần nhất để không làm lệch lãi gộp
+        let last_sale = match reason {
+            MovementReason::Return => self.last_sale(product_id)?,
+            _ => None,
+        };
+        let unit_cost = unit_cost.or(last_sale.as_ref().and_then(|sale| sale.unit_cost));
+        let return_price = last_sale.and_then(|sale| sale.unit_price);
+        for delta in deltas {
+            if !self.locations.contains_key(&delta.location_id) {
+                return Err(InventoryError::LocationNotFound(delta.location_id));
+            }
+            match levels.iter_mut().find(|(location_id, _, _)| *location_id == delta.location_id) {
+                Some((_, _, quantity)) => *quantity += delta.change,
+                None => {
+                    let current = self.get_stock_at(product_id, &delta.location_id);
+                    levels.push((delta.location_id.clone(), current, i64::from(current) + delta.change));
+                }
+            }
+
+            if let Some(lot) = &delta.lot {
+                changes.push(match self.lots.get(&lot.id) {
+                    Some(existing) => StoreChange::UpdateLotQuantity {
+                        lot_id: lot.id,
+                        expected: existing.quantity,
+                        quantity: lot.quantity,
+                    },
+                    None => StoreChange::InsertLot(lot.clone()),
+                });
+            }
+            // Khi chuyển kho, cùng một thiết bị xuất hiện ở cả hai phía nhưng chỉ ghi một lần
+            if let Some(unit) = delta.unit.as_ref().filter(|unit| !updated_units.iter().any(|u| u.id == unit.id)) {
+                changes.push(match self.units.get(&unit.id) {
+                    Some(existing) => StoreChange::UpdateUnit {
+                        expected_status: existing.status,
+                        expected_location: existing.location_id.clone(),
+                        unit: unit.clone(),
+                    },
+                    None => StoreChange::InsertUnit(unit.clone()),
+                });
+                updated_units.push(unit.clone());
+            }
+            movements.push(StockMovement {
+                id: Uuid::new_v4(),
+                product_id,
+                location_id: delta.location_id,
+                quantity_change: delta.change,
+                reason,
+                user: user.to_string(),
+                timestamp,
+                reference: reference.clone(),
+                lot_number: delta.lot.as_ref().map(|lot| lot.lot_number.clone()),
+                serial_number: delta.unit.as_ref().map(|unit| unit.serial_number.clone()),
+                unit_cost: movement_cost(&mut layers, reason, delta.change, unit_cost, self.valuation_method),
+                unit_price: delta.unit_price.or(return_price),
+            });
+            updated_lots.extend(delta.lot);
+            total += delta.change;
+        }
+
+        let mut updated_levels = Vec::new();
+        for (location_id, current, quantity) in levels {
+            if quantity < 0 {
+                return Err(InventoryError::InsufficientStock);
+            }
+            let quantity = u32::try_from(quantity).map_err(|_| InventoryError::QuantityOverflow)?;
+            changes.push(StoreChange::SetStockLevel {
+                product_id,
+                location_id: location_id.clone(),
+                expected: current,
+                quantity,
+            });
+            updated_levels.push((location_id, quantity));
+        }
+        changes.extend(movements.iter().cloned().map(StoreChange::AppendMovement));
+        let total = u32::try_from(total).map_err(|_| InventoryError::QuantityOverflow)?;
+        if total != product.quantity {
+            changes.push(StoreChange::UpdateQuantity {
+                product_id,
+                expected: product.quantity,
+                quantity: total,
+            });
+        }
+
+        Ok(StagedStock {
+            product_id,
+            changes,
+            movements,
+            levels: updated_levels,
+            lots: updated_lots,
+            units: updated_units,
+            quantity: total,
+            cost_layers: layers,
+        })
+    }
+
+    // Ghi các thay đổi đã tính của một hoặc nhiều sản phẩm trong một transaction rồi mới cập nhật
+    // bộ đệm. Mỗi sản phẩm chỉ được có một phần trong `staged`, vì mỗi phần được tính từ bộ đệm hiện tại
+    fn commit_staged_stock(&mut self, mut staged: Vec<StagedStock>) -> Result<Vec<StockMovement>, InventoryError> {
+        let changes: Vec<StoreChange> = staged.iter_mut()
+            .flat_map(|stock| std::mem::take(&mut stock.changes))
+            .collect();
+        self.store.apply(&changes)?;
+
+        let mut movements = Vec::new();
+        for stock in staged {
+            let levels = self.stock_levels.entry(stock.product_id).or_default();
+            for (location_id, quantity) in stock.levels {
+                levels.insert(location_id, quantity);
+            }
+            for lot in stock.lots {
+                self.lots.insert(lot.id, lot);
+            }
+            for unit in stock.units {
+                self.serial_index.insert(unit.serial_number.clone(), unit.id);
+                self.units.insert(unit.id, unit);
+            }
+            if let Some(product) = self.products.get_mut(&stock.product_id) {
+                product.quantity = stock.quantity;
+            }
+            self.cost_layers.insert(stock.product_id, stock.cost_layers);
+            movements.extend(stock.movements);
+        }
+        Ok(movements)
+    }
+
+    // Nạp lại bộ đệm và dựng lại chỉ mục từ kho lưu trữ, ví dụ sau khi gặp lỗi ghi đồng thời
+    pub fn reload(&mut self) -> Result<(), InventoryError> {
+        let products = self.store.load_products()?;
+
+        self.sku_index = products.iter()
+            .map(|product| (normalize_sku(&product.sku), product.id))
+            .collect();
+        self.barcode_index = products.iter()
+            .flat_map(|product| product.barcodes.iter().map(|barcode| (barcode.clone(), product.id)))
+            .collect();
+        self.products = products.into_iter()
+            .map(|product| (product.id, product))
+            .collect();
+
+        self.locations = self.store.load_locations()?
+            .into_iter()
+            .map(|location| (location.id.clone(), location))
+            .collect();
+        self.stock_levels = HashMap::new();
+        for level in self.store.load_stock_levels()? {
+            self.stock_levels.entry(level.product_id).or_default().insert(level.location_id, level.quantity);
+        }
+        self.reorder_policies = self.store.load_reorder_policies()?
+            .into_iter()
+            .map(|policy| (policy.product_id, policy))
+            .collect();
+        self.lots = self.store.load_lots()?
+            .into_iter()
+            .map(|lot| (lot.id, lot))
+            .collect();
+        self.units = self.store.load_units()?
+            .into_iter()
+            .map(|unit| (unit.id, unit))
+            .collect();
+        self.serial_index = self.units.values()
+            .map(|unit| (unit.serial_number.clone(), unit.id))
+            .collect();
+        self.valuation_method = self.store.load_valuation_method()?.unwrap_or(ValuationMethod::Fifo);
+        self.rebuild_cost_layers()
+    }
+
+    fn last_sale(&self, product_id: Uuid) -> Result<Option<StockMovement>, InventoryError> {
+        Ok(self.get_movement_history(product_id, None)?
+            .into_iter()
+            .rev()
+            .find(|movement| movement.reason == MovementReason::Sale))
+    }
+
+    // Lớp giá vốn được dựng lại bằng cách phát lại sổ kho theo phương pháp hiện tại
+    fn rebuild_cost_layers(&mut self) -> Result<(), InventoryError> {
+        let mut cost_layers = HashMap::new();
+        for &product_id in self.products.keys() {
+            let movements = self.get_movement_history(product_id, None)?;
+            cost_layers.insert(product_id, replay_costs(&movements, self.valuation_method));
+        }
+        self.cost_layers = cost_layers;
         Ok(())
     }
 
+    // Đổi phương pháp tính giá xuất kho và lưu lại cho các lần mở sau. Giá vốn đã ghi trên các dòng
+    // bán trước đó giữ nguyên, chỉ giá trị tồn kho hiện tại được tính lại
+    pub fn set_valuation_method(&mut self, method: ValuationMethod) -> Result<(), InventoryError> {
+        self.store.apply(&[StoreChange::SetValuationMethod(method)])?;
+        self.valuation_method = method;
+        self.rebuild_cost_layers()
+    }
+
+    pub fn valuation_method(&self) -> ValuationMethod {
+        self.valuation_method
+    }
+
+    // Giá trị tồn kho hiện tại của một sản phẩm theo giá vốn
+    pub fn get_inventory_value(&self, product_id: Uuid) -> f64 {
+        self.cost_layers.get(&product_id).map_or(0.0, CostLayers::value)
+    }
+
+    pub fn get_average_cost(&self, product_id: Uuid) -> f64 {
+        self.cost_layers.get(&product_id).map_or(0.0, CostLayers::average_cost)
+    }
+
+    // Báo cáo giá trị tồn kho tại thời điểm `at`, phát lại sổ kho đến thời điểm đó
+    pub fn valuation_at(&self, at: DateTime<Utc>) -> Result<ValuationReport, InventoryError> {
+        let mut lines = Vec::new();
+        for product in self.products.values().filter(|product| product.created_at <= at) {
+            let movements: Vec<StockMovement> = self.get_movement_history(product.id, None)?
+                .into_iter()
+                .filter(|movement| movement.timestamp <= at)
+                .collect();
+            let layers = replay_costs(&movements, self.valuation_method);
+            if layers.quantity() > 0 {
+                lines.push(ValuationLine {
+                    product_id: product.id,
+                    sku: product.sku.clone(),
+                    quantity: layers.quantity(),
+                    value: layers.value(),
+                });
+            }
+        }
+        lines.sort_by(|a, b| a.sku.cmp(&b.sku));
+        Ok(ValuationReport {
+            at,
+            method: self.valuation_method,
+            total_value: lines.iter().map(|line| line.value).sum(),
+            lines,
+        })
+    }
+
+    // Lãi gộp của sản phẩm trong khoảng [since, until), từ giá bán và giá vốn ghi trên sổ kho
+    pub fn gross_margin(
+        &self,
+        product_id: Uuid,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<GrossMargin, InventoryError> {
+        let product = self.products.get(&product_id)
+            .ok_or(InventoryError::ProductNotFound)?;
+        let mut units_sold = 0;
+        let mut revenue = 0.0;
+        let mut cost_of_goods_sold = 0.0;
+        let sales = self.get_movement_history(product_id, None)?
+            .into_iter()
+            .filter(|movement| movement.timestamp >= since && movement.timestamp < until)
+            .filter(|movement| matches!(movement.reason, MovementReason::Sale | MovementReason::Return));
+        for movement in sales {
+            // Dòng bán có số lượng âm, hàng trả lại có số lượng dương
+            let sold = -movement.quantity_change;
+            units_sold += sold;
+            revenue += sold as f64 * movement.unit_price.unwrap_or(product.price);
+            cost_of_goods_sold += sold as f64 * movement.unit_cost.unwrap_or(0.0);
+        }
+        let gross_profit = revenue - cost_of_goods_sold;
+        Ok(GrossMargin {
+            product_id,
+            sku: product.sku.clone(),
+            units_sold,
+            revenue,
+            cost_of_goods_sold,
+            gross_profit,
+            margin_percent: (revenue != 0.0).then(|| gross_profit / revenue * 100.0),
+        })
+    }
+
+    // Lãi gộp của mọi sản phẩm có bán trong khoảng, xếp theo lãi gộp giảm dần
+    pub fn gross_margin_report(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<GrossMargin>, InventoryError> {
+        let mut report = Vec::new();
+        for &product_id in self.products.keys() {
+            let margin = self.gross_margin(product_id, since, until)?;
+            if margin.units_sold != 0 {
+                report.push(margin);
+            }
+        }
+        report.sort_by(|a, b| b.gross_profit.total_cmp(&a.gross_profit));
+        Ok(report)
+    }
+
+    // Đánh giá trên tổng tồn kho ở mọi địa điểm, theo điểm đặt hàng lại của từng sản phẩm nếu có
     pub fn get_low_stock_products(&self) -> Vec<&Product> {
         self.products.values()
-            .filter(|product| product.quantity <= self.low_stock_threshold)
+            .filter(|product| product.quantity <= self.reorder_point(product.id))
             .collect()
     }
 
+    pub fn set_reorder_policy(&mut self, policy: ReorderPolicy) -> Result<(), InventoryError> {
+        if !self.products.contains_key(&policy.product_id) {
+            return Err(InventoryError::ProductNotFound);
+        }
+        if policy.reorder_quantity == 0 {
+            return Err(InventoryError::InvalidQuantity);
+        }
+
+        let policy = ReorderPolicy {
+            supplier: policy.supplier
+                .map(|supplier| supplier.trim().to_string())
+                .filter(|supplier| !supplier.is_empty()),
+            ..policy
+        };
+        self.store.apply(&[StoreChange::SetReorderPolicy(policy.clone())])?;
+        self.reorder_policies.insert(policy.product_id, policy);
+        Ok(())
+    }
+
+    pub fn get_reorder_policy(&self, product_id: Uuid) -> Option<&ReorderPolicy> {
+        self.reorder_policies.get(&product_id)
+    }
+
+    pub fn reorder_point(&self, product_id: Uuid) -> u32 {
+        self.reorder_policies.get(&product_id)
+            .map(|policy| policy.reorder_point)
+            .unwrap_or(self.low_stock_threshold)
+    }
+
+    // Nhà cung cấp của sản phẩm: lấy từ điểm đặt hàng lại, mặc định là nhà sản xuất
+    pub fn get_supplier(&self, product_id: Uuid) -> Option<&str> {
+        let product = self.products.get(&product_id)?;
+        Some(self.reorder_policies.get(&product_id)
+            .and_then(|policy| policy.supplier.as_deref())
+            .unwrap_or(&product.manufacturer))
+    }
+
+    // Gom các sản phẩm đã chạm điểm đặt hàng lại thành đơn đề xuất theo nhà cung cấp. `on_order` là số
+    // lượng còn chờ về của các đơn mua đã gửi (xem `PurchaseOrderManager::open_quantities`) và được cộng
+    // vào tồn kho khi so với điểm đặt lại, để không đề xuất đặt lại hàng đang về.
+    // Sản phẩm chưa có điểm đặt hàng lại dùng ngưỡng tồn kho thấp chung và được đề xuất đặt vừa đủ để
+    // vượt ngưỡng đó
+    pub fn suggest_purchase_orders(&self, on_order: &HashMap<Uuid, u32>) -> Vec<SuggestedPurchaseOrder> {
+        let mut by_supplier: HashMap<String, Vec<SuggestedOrderLine>> = HashMap::new();
+        for product in self.products.values() {
+            let incoming = on_order.get(&product.id).copied().unwrap_or(0);
+            let position = product.quantity.saturating_add(incoming);
+            let policy = self.reorder_policies.get(&product.id);
+            let reorder_point = policy.map(|policy| policy.reorder_point).unwrap_or(self.low_stock_threshold);
+            if position > reorder_point {
+                continue;
+            }
+
+            let quantity = match policy {
+                Some(policy) => policy.reorder_quantity,
+                None => reorder_point - position + 1,
+            };
+            let supplier = policy.and_then(|policy| policy.supplier.clone())
+                .unwrap_or_else(|| product.manufacturer.clone());
+            by_supplier.entry(supplier).or_default().push(SuggestedOrderLine {
+                product_id: product.id,
+                sku: product.sku.clone(),
+                name: product.name.clone(),
+                on_hand: product.quantity,
+                on_order: incoming,
+                reorder_point,
+                quantity,
+            });
+        }
+
+        let mut orders: Vec<SuggestedPurchaseOrder> = by_supplier.into_iter()
+            .map(|(supplier, mut lines)| {
+                lines.sort_by(|a, b| a.sku.cmp(&b.sku));
+                SuggestedPurchaseOrder { supplier, lines }
+            })
+            .collect();
+        orders.sort_by(|a, b| a.supplier.cmp(&b.supplier));
+        orders
+    }
+
+    // Chỉ xét các sản phẩm từng có hàng ở địa điểm này, kèm số lượng tại đó
+    pub fn get_low_stock_products_at(&self, location_id: &str) -> Result<Vec<(&Product, u32)>, InventoryError> {
+        let location = self.locations.get(location_id)
+            .ok_or_else(|| InventoryError::LocationNotFound(location_id.to_string()))?;
+        let threshold = location.low_stock_threshold.unwrap_or(self.low_stock_threshold);
+
+        Ok(self.stock_levels.iter()
+            .filter_map(|(product_id, levels)| {
+                let quantity = *levels.get(location_id)?;
+                let product = self.products.get(product_id)?;
+                (quantity <= threshold).then_some((product, quantity))
+            })
+            .collect())
+    }
+
+    pub fn get_stock_at(&self, product_id: Uuid, location_id: &str) -> u32 {
+        self.stock_levels.get(&product_id)
+            .and_then(|levels| levels.get(location_id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Tồn kho của sản phẩm theo từng địa điểm, sắp xếp theo mã địa điểm
+    pub fn get_stock_levels(&self, product_id: Uuid) -> Vec<(&Location, u32)> {
+        let mut levels: Vec<(&Location, u32)> = self.stock_levels.get(&product_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|(location_id, quantity)| Some((self.locations.get(location_id)?, *quantity)))
+            .collect();
+        levels.sort_by(|a, b| a.0.id.cmp(&b.0.id));
+        levels
+    }
+
+    // Lịch sử thay đổi tồn kho của sản phẩm theo thứ tự thời gian, có thể lọc theo địa điểm
+    pub fn get_movement_history(
+        &self,
+        product_id: Uuid,
+        location_id: Option<&str>,
+    ) -> Result<Vec<StockMovement>, InventoryError> {
+        if !self.products.contains_key(&product_id) {
+            return Err(InventoryError::ProductNotFound);
+        }
+
+        let mut movements: Vec<StockMovement> = self.store.load_movements(product_id)?
+            .into_iter()
+            .filter(|movement| location_id.is_none_or(|location_id| movement.location_id == location_id))
+            .collect();
+        movements.sort_by_key(|movement| movement.timestamp);
+        Ok(movements)
+    }
+
+    // Tính lại tồn kho chỉ từ sổ kho, để đối chiếu với số lượng đang lưu
+    pub fn quantity_from_ledger(&self, product_id: Uuid, location_id: Option<&str>) -> Result<i64, InventoryError> {
+        Ok(self.get_movement_history(product_id, location_id)?
+            .iter()
+            .map(|movement| movement.quantity_change)
+            .sum())
+    }
+
+    // Các lô còn hàng của sản phẩm theo thứ tự FEFO
+    pub fn get_lots(&self, product_id: Uuid) -> Vec<&Lot> {
+        let mut lots: Vec<&Lot> = self.lots.values()
+            .filter(|lot| lot.product_id == product_id && lot.quantity > 0)
+            .collect();
+        lots.sort_by(|a, b| a.fefo_key().cmp(&b.fefo_key()).then_with(|| a.location_id.cmp(&b.location_id)));
+        lots
+    }
+
+    // Các lô còn hàng hết hạn trong vòng `within_days` ngày tính từ `today`, kể cả lô đã hết hạn
+    pub fn get_expiring_lots(&self, today: NaiveDate, within_days: u32) -> Vec<&Lot> {
+        let until = today + Duration::days(i64::from(within_days));
+        let mut lots: Vec<&Lot> = self.lots.values()
+            .filter(|lot| lot.quantity > 0 && lot.expires_on.is_some_and(|expires_on| expires_on <= until))
+            .collect();
+        lots.sort_by(|a, b| a.fefo_key().cmp(&b.fefo_key()).then_with(|| a.location_id.cmp(&b.location_id)));
+        lots
+    }
+
+    // Thu hồi: các lần bán có lấy hàng từ lô này, `reference` của dòng sổ kho là mã đơn nếu có
+    pub fn find_sales_by_lot(&self, product_id: Uuid, lot_number: &str) -> Result<Vec<StockMovement>, InventoryError> {
+        if !self.lots.values().any(|lot| lot.product_id == product_id && lot.lot_number == lot_number) {
+            return Err(InventoryError::LotNotFound(lot_number.to_string()));
+        }
+
+        Ok(self.get_movement_history(product_id, None)?
+            .into_iter()
+            .filter(|movement| movement.reason == MovementReason::Sale)
+            .filter(|movement| movement.lot_number.as_deref() == Some(lot_number))
+            .collect())
+    }
+
+    pub fn get_location(&self, location_id: &str) -> Option<&Location> {
+        self.locations.get(location_id)
+    }
+
+    pub fn get_locations(&self) -> Vec<&Location> {
+        let mut locations: Vec<&Location> = self.locations.values().collect();
+        locations.sort_by(|a, b| a.id.cmp(&b.id));
+        locations
+    }
+
     pub fn get_product(&self, product_id: Uuid) -> Option<&Product> {
         self.products.get(&product_id)
     }
 
+    pub fn get_product_by_sku(&self, sku: &str) -> Option<&Product> {
+        self.sku_index.get(&normalize_sku(sku))
+            .and_then(|product_id| self.products.get(product_id))
+    }
+
+    // Mã vạch sai định dạng hoặc sai chữ số kiểm tra thì không khớp sản phẩm nào
+    pub fn get_product_by_barcode(&self, barcode: &str) -> Option<&Product> {
+        let barcode = normalize_barcode(barcode).ok()?;
+        self.barcode_index.get(&barcode)
+            .and_then(|product_id| self.products.get(product_id))
+    }
+
     pub fn get_all_products(&self) -> Vec<&Product> {
         self.products.values().collect()
     }
 }
 
+// Một thay đổi tồn kho tại một địa điểm; `lot` là trạng thái mới của lô bị thay đổi, nếu có
+struct StockDelta {
+    location_id: String,
+    change: i64,
+    lot: Option<Lot>,
+    // Trạng thái mới của thiết bị theo serial bị thay đổi, nếu có
+    unit: Option<SerializedUnit>,
+    // Giá bán đơn vị do người bán đưa vào, ghi trên dòng bán
+    unit_price: Option<f64>,
+}
+
+impl StockDelta {
+    fn untracked(location_id: &str, change: i64) -> Self {
+        Self {
+            location_id: location_id.to_string(),
+            change,
+            lot: None,
+            unit: None,
+            unit_price: None,
+        }
+    }
+
+    fn priced(self, unit_price: f64) -> Self {
+        Self { unit_price: Some(unit_price), ..self }
+    }
+
+    // Số lượng lấy ra với thay đổi giảm
+    fn taken(&self) -> u32 {
+        u32::try_from(-self.change).unwrap_or(0)
+    }
+}
+
+// Thay đổi tồn kho của một sản phẩm đã tính xong, chờ ghi cùng các sản phẩm khác
+struct StagedStock {
+    product_id: Uuid,
+    changes: Vec<StoreChange>,
+    movements: Vec<StockMovement>,
+    levels: Vec<(String, u32)>,
+    lots: Vec<Lot>,
+    units: Vec<SerializedUnit>,
+    quantity: u32,
+    cost_layers: CostLayers,
+}
+
+// Giá vốn đơn vị của một dòng sổ kho, đồng thời cập nhật các lớp giá vốn.
+// Chuyển kho không làm đổi giá trị tồn kho nên không có giá vốn
+fn movement_cost(
+    layers: &mut CostLayers,
+    reason: MovementReason,
+    change: i64,
+    receipt_cost: Option<f64>,
+    method: ValuationMethod,
+) -> Option<f64> {
+    if reason == MovementReason::Transfer {
+        return None;
+    }
+    let quantity = u32::try_from(change.unsigned_abs()).unwrap_or(u32::MAX);
+    if change > 0 {
+        let unit_cost = receipt_cost.unwrap_or_else(|| layers.average_cost());
+        layers.receive(quantity, unit_cost, method);
+        Some(unit_cost)
+    } else {
+        Some(layers.issue(quantity) / f64::from(quantity))
+    }
+}
+
+fn replay_costs(movements: &[StockMovement], method: ValuationMethod) -> CostLayers {
+    let mut layers = CostLayers::default();
+    for movement in movements {
+        movement_cost(&mut layers, movement.reason, movement.quantity_change, movement.unit_cost, method);
+    }
+    layers
+}
+
+fn check_unit_price(unit_price: f64) -> Result<(), InventoryError> {
+    if !unit_price.is_finite() || unit_price < 0.0 {
+        return Err(InventoryError::InvalidUnitPrice);
+    }
+    Ok(())
+}
+
+fn normalize_sku(sku: &str) -> String {
+    sku.trim().to_ascii_uppercase()
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum InventoryError {
     #[error("Product not found")]
     ProductNotFound,
     #[error("Insufficient stock")]
     InsufficientStock,
+    #[error("No reservation for order {0}")]
+    ReservationNotFound(String),
+    #[error("Reservation for order {0} has expired")]
+    ReservationExpired(String),
+    #[error("Invalid serial number: {0}")]
+    InvalidSerial(String),
+    #[error("Serial number already exists: {0}")]
+    DuplicateSerial(String),
+    #[error("Serial number not found: {0}")]
+    SerialNotFound(String),
+    #[error("Unit {0} has already been sold")]
+    UnitAlreadySold(String),
+    #[error("Serialized units must be sold, moved, reserved or written off by serial number")]
+    SerialNumberRequired,
+    #[error("Unit {0} has been written off")]
+    UnitWrittenOff(String),
+    #[error("Supply chain error: {0}")]
+    SupplyChain(#[from] SupplyChainError),
+    #[error("Invalid lot: {0}")]
+    InvalidLot(String),
+    #[error("Lot not found: {0}")]
+    LotNotFound(String),
+    #[error("Quantity must not be zero")]
+    InvalidQuantity,
+    #[error("Unit cost must be a non-negative amount")]
+    InvalidUnitCost,
+    #[error("Unit price must be a non-negative amount")]
+    InvalidUnitPrice,
+    #[error("Sales must be recorded with a unit price")]
+    SaleWithoutPrice,
+    #[error("Stock movements must record the user making the change")]
+    MissingUser,
+    #[error("Stock quantity overflow")]
+    QuantityOverflow,
+    #[error("Location {0} not found")]
+    LocationNotFound(String),
+    #[error("Location id must not be empty")]
+    InvalidLocation,
+    #[error("Location {0} already exists")]
+    DuplicateLocation(String),
+    #[error("{0:?} movement cannot change stock by {1}")]
+    InvalidMovementDirection(MovementReason, i64),
+    #[error("Transfer must move a positive quantity between two different locations")]
+    InvalidTransfer,
+    #[error("SKU must not be empty")]
+    InvalidSku,
+    #[error("SKU {0} is already used by another product")]
+    DuplicateSku(String),
+    #[error("Invalid EAN-13/UPC-A barcode {0}")]
+    InvalidBarcode(String),
+    #[error("Barcode {0} is already assigned to another product")]
+    DuplicateBarcode(String),
+    #[error("Barcode {0} is not assigned to this product")]
+    BarcodeNotFound(String),
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
 }
\ No newline at end of file
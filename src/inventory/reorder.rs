@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Điểm đặt hàng lại của một sản phẩm: khi tổng tồn kho <= `reorder_point` thì đề xuất
+// đặt thêm `reorder_quantity`. Không có nhà cung cấp thì dùng `Product.manufacturer`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReorderPolicy {
+    pub product_id: Uuid,
+    pub reorder_point: u32,
+    pub reorder_quantity: u32,
+    pub supplier: Option<String>,
+}
+
+impl ReorderPolicy {
+    pub fn new(product_id: Uuid, reorder_point: u32, reorder_quantity: u32) -> Self {
+        Self {
+            product_id,
+            reorder_point,
+            reorder_quantity,
+            supplier: None,
+        }
+    }
+
+    pub fn with_supplier(mut self, supplier: String) -> Self {
+        self.supplier = Some(supplier);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedOrderLine {
+    pub product_id: Uuid,
+    pub sku: String,
+    pub name: String,
+    pub on_hand: u32,
+    // Số lượng đang về theo các đơn mua đã gửi
+    pub on_order: u32,
+    pub reorder_point: u32,
+    pub quantity: u32,
+}
+
+// Đơn đặt hàng đề xuất cho một nhà cung cấp, các dòng sắp xếp theo SKU
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedPurchaseOrder {
+    pub supplier: String,
+    pub lines: Vec<SuggestedOrderLine>,
+}
+
+impl SuggestedPurchaseOrder {
+    pub fn total_units(&self) -> u32 {
+        self.lines.iter().map(|line| line.quantity).sum()
+    }
+}
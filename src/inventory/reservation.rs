@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Giữ hàng cho một đơn đang chờ thanh toán. `order_id` là mã tham chiếu của đơn,
+// trùng với `PaymentRequest.reference` khi thanh toán bằng yêu cầu thanh toán
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub id: Uuid,
+    pub order_id: String,
+    pub product_id: Uuid,
+    pub location_id: String,
+    pub quantity: u32,
+    // Giá bán đơn vị đã chốt trên đơn, ghi vào dòng bán khi thanh toán xong
+    pub unit_price: f64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Reservation {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
@@ -0,0 +1,93 @@
+use super::InventoryError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnitStatus {
+    InStock,
+    Sold,
+    // Mất, hỏng hoặc bị loại khỏi kho khi kiểm kê
+    WrittenOff,
+}
+
+impl UnitStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnitStatus::InStock => "InStock",
+            UnitStatus::Sold => "Sold",
+            UnitStatus::WrittenOff => "WrittenOff",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "InStock" => Some(UnitStatus::InStock),
+            "Sold" => Some(UnitStatus::Sold),
+            "WrittenOff" => Some(UnitStatus::WrittenOff),
+            _ => None,
+        }
+    }
+}
+
+// Một thiết bị cụ thể theo số serial/IMEI. `id` là định danh của thiết bị trên chuỗi cung ứng
+// và blockchain; mỗi thiết bị còn hàng chiếm một đơn vị tồn kho tại `location_id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedUnit {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub serial_number: String,
+    pub location_id: String,
+    pub status: UnitStatus,
+    pub received_at: DateTime<Utc>,
+    pub sold_at: Option<DateTime<Utc>>,
+    // Mã đơn hàng của lần bán
+    pub sale_reference: Option<String>,
+    // Mã băm của lần bán đã neo lên blockchain
+    pub sale_anchor: Option<String>,
+}
+
+impl SerializedUnit {
+    // Nội dung lần bán được neo lên blockchain; dùng lại để kiểm tra với neo đã ghi
+    pub fn sale_payload(&self) -> serde_json::Value {
+        serde_json::json!({
+            "unit_id": self.id.to_string(),
+            "product_id": self.product_id.to_string(),
+            "serial_number": self.serial_number,
+            "location": self.location_id,
+            "sold_at": self.sold_at.map(|sold_at| sold_at.to_rfc3339()),
+            "sale_reference": self.sale_reference,
+        })
+    }
+}
+
+// Số serial so sánh không phân biệt hoa thường. IMEI (15 chữ số) phải đúng chữ số kiểm tra Luhn
+pub fn normalize_serial(serial: &str) -> Result<String, InventoryError> {
+    let serial = serial.trim().to_ascii_uppercase();
+    let invalid = || InventoryError::InvalidSerial(serial.clone());
+
+    if serial.is_empty() || serial.len() > 64 || !serial.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(invalid());
+    }
+    if serial.len() == 15 && serial.chars().all(|c| c.is_ascii_digit()) && !luhn_valid(&serial) {
+        return Err(invalid());
+    }
+    Ok(serial)
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let sum: u32 = digits.chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(index, digit)| {
+            if index % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                digit
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
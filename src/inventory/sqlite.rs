@@ -0,0 +1,726 @@
+use super::ledger::{MovementReason, StockMovement};
+use super::location::{Location, LocationKind, StockLevel};
+use super::lot::Lot;
+use super::reorder::ReorderPolicy;
+use super::serial::{SerializedUnit, UnitStatus};
+use super::storage::{InventoryStore, StorageError, StoreChange};
+use super::valuation::ValuationMethod;
+use crate::models::Product;
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use uuid::Uuid;
+
+// Các migration theo thứ tự; phiên bản schema là chỉ số + 1. Chỉ được thêm vào cuối, không sửa migration cũ
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE products (
+        id TEXT PRIMARY KEY NOT NULL,
+        sku TEXT NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        price REAL NOT NULL,
+        quantity INTEGER NOT NULL CHECK (quantity >= 0),
+        manufacturer TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );",
+    "CREATE UNIQUE INDEX products_sku_unique ON products (sku COLLATE NOCASE);
+    CREATE TABLE product_barcodes (
+        barcode TEXT PRIMARY KEY NOT NULL,
+        product_id TEXT NOT NULL REFERENCES products (id) ON DELETE CASCADE
+    );
+    CREATE INDEX product_barcodes_product ON product_barcodes (product_id);",
+    // Tồn kho theo địa điểm; số lượng cũ của sản phẩm được chuyển vào địa điểm mặc định
+    "CREATE TABLE locations (
+        id TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        low_stock_threshold INTEGER
+    );
+    INSERT INTO locations (id, name, kind) VALUES ('MAIN', 'Main store', 'Store');
+    CREATE TABLE stock_levels (
+        product_id TEXT NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+        location_id TEXT NOT NULL REFERENCES locations (id),
+        quantity INTEGER NOT NULL CHECK (quantity >= 0),
+        PRIMARY KEY (product_id, location_id)
+    );
+    INSERT INTO stock_levels (product_id, location_id, quantity)
+        SELECT id, 'MAIN', quantity FROM products WHERE quantity > 0;",
+    // Sổ kho chỉ ghi thêm; tồn kho hiện có được ghi thành một dòng Adjustment đầu kỳ
+    "CREATE TABLE stock_movements (
+        id TEXT PRIMARY KEY NOT NULL,
+        product_id TEXT NOT NULL REFERENCES products (id),
+        location_id TEXT NOT NULL REFERENCES locations (id),
+        quantity_change INTEGER NOT NULL CHECK (quantity_change <> 0),
+        reason TEXT NOT NULL,
+        user TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        reference TEXT
+    );
+    CREATE INDEX stock_movements_product ON stock_movements (product_id, timestamp);
+    CREATE TRIGGER stock_movements_no_update BEFORE UPDATE ON stock_movements
+        BEGIN SELECT RAISE(ABORT, 'stock movements are immutable'); END;
+    CREATE TRIGGER stock_movements_no_delete BEFORE DELETE ON stock_movements
+        BEGIN SELECT RAISE(ABORT, 'stock movements are immutable'); END;
+    INSERT INTO stock_movements (id, product_id, location_id, quantity_change, reason, user, timestamp, reference)
+        SELECT lower(hex(randomblob(16))), product_id, location_id, quantity, 'Adjustment', 'migration',
+               strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), 'opening balance'
+        FROM stock_levels WHERE quantity > 0;",
+    "CREATE TABLE reorder_policies (
+        product_id TEXT PRIMARY KEY NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+        reorder_point INTEGER NOT NULL CHECK (reorder_point >= 0),
+        reorder_quantity INTEGER NOT NULL CHECK (reorder_quantity > 0),
+        supplier TEXT
+    );",
+    "CREATE TABLE lots (
+        id TEXT PRIMARY KEY NOT NULL,
+        product_id TEXT NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+        location_id TEXT NOT NULL REFERENCES locations (id),
+        lot_number TEXT NOT NULL,
+        manufactured_on TEXT NOT NULL,
+        expires_on TEXT,
+        quantity INTEGER NOT NULL CHECK (quantity >= 0),
+        UNIQUE (product_id, location_id, lot_number)
+    );
+    CREATE INDEX lots_expiry ON lots (expires_on);
+    ALTER TABLE stock_movements ADD COLUMN lot_number TEXT;
+    CREATE INDEX stock_movements_lot ON stock_movements (product_id, lot_number);",
+    "CREATE TABLE serialized_units (
+        id TEXT PRIMARY KEY NOT NULL,
+        product_id TEXT NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+        serial_number TEXT NOT NULL UNIQUE,
+        location_id TEXT NOT NULL REFERENCES locations (id),
+        status TEXT NOT NULL,
+        received_at TEXT NOT NULL,
+        sold_at TEXT,
+        sale_reference TEXT,
+        sale_anchor TEXT
+    );
+    CREATE INDEX serialized_units_product ON serialized_units (product_id);
+    ALTER TABLE stock_movements ADD COLUMN serial_number TEXT;",
+    "ALTER TABLE stock_movements ADD COLUMN unit_cost REAL;
+    ALTER TABLE stock_movements ADD COLUMN unit_price REAL;",
+    // Cấu hình của kho hàng, ví dụ phương pháp tính giá xuất kho
+    "CREATE TABLE settings (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );",
+    // Dòng số dư đầu kỳ của migration 4 ghi thời điểm dạng `...Z`, còn mọi dòng khác theo RFC 3339 (`+00:00`).
+    // Sổ kho sắp xếp theo chuỗi nên hai dạng lệch thứ tự trong cùng một giây; đưa về một định dạng
+    "DROP TRIGGER stock_movements_no_update;
+    UPDATE stock_movements SET timestamp = substr(timestamp, 1, 19) || '+00:00' WHERE timestamp LIKE '%Z';
+    CREATE TRIGGER stock_movements_no_update BEFORE UPDATE ON stock_movements
+        BEGIN SELECT RAISE(ABORT, 'stock movements are immutable'); END;",
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let connection = Connection::open(path).map_err(database_error)?;
+        Self::from_connection(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        let connection = Connection::open_in_memory().map_err(database_error)?;
+        Self::from_connection(connection)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self, StorageError> {
+        connection.pragma_update(None, "foreign_keys", true).map_err(database_error)?;
+        migrate(&mut connection)?;
+        Ok(Self { connection })
+    }
+
+    pub fn schema_version(&self) -> Result<u32, StorageError> {
+        current_version(&self.connection)
+    }
+}
+
+impl InventoryStore for SqliteStore {
+    fn load_products(&self) -> Result<Vec<Product>, StorageError> {
+        let mut statement = self.connection
+            .prepare(
+                "SELECT id, sku, name, description, price, quantity, manufacturer, created_at
+                 FROM products ORDER BY created_at",
+            )
+            .map_err(database_error)?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, f64>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, String>(7)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut products = Vec::new();
+        for row in rows {
+            let (id, sku, name, description, price, quantity, manufacturer, created_at) = row.map_err(database_error)?;
+            products.push(Product {
+                id: Uuid::parse_str(&id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                sku,
+                name,
+                description,
+                price,
+                quantity,
+                manufacturer,
+                barcodes: Vec::new(),
+                created_at: DateTime::parse_from_rfc3339(&created_at)
+                    .map_err(|e| StorageError::Corrupted(e.to_string()))?
+                    .with_timezone(&Utc),
+            });
+        }
+
+        let mut statement = self.connection
+            .prepare("SELECT product_id, barcode FROM product_barcodes ORDER BY rowid")
+            .map_err(database_error)?;
+        let barcodes = statement
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .map_err(database_error)?;
+        for row in barcodes {
+            let (product_id, barcode) = row.map_err(database_error)?;
+            let product = products.iter_mut()
+                .find(|product| product.id.to_string() == product_id)
+                .ok_or_else(|| StorageError::Corrupted(format!("barcode {} has no product", barcode)))?;
+            product.barcodes.push(barcode);
+        }
+
+        Ok(products)
+    }
+
+    fn load_locations(&self) -> Result<Vec<Location>, StorageError> {
+        let mut statement = self.connection
+            .prepare("SELECT id, name, kind, low_stock_threshold FROM locations ORDER BY id")
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<u32>>(3)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut locations = Vec::new();
+        for row in rows {
+            let (id, name, kind, low_stock_threshold) = row.map_err(database_error)?;
+            let kind = LocationKind::parse(&kind)
+                .ok_or_else(|| StorageError::Corrupted(format!("unknown location kind {}", kind)))?;
+            locations.push(Location { id, name, kind, low_stock_threshold });
+        }
+        Ok(locations)
+    }
+
+    fn load_stock_levels(&self) -> Result<Vec<StockLevel>, StorageError> {
+        let mut statement = self.connection
+            .prepare("SELECT product_id, location_id, quantity FROM stock_levels")
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, u32>(2)?))
+            })
+            .map_err(database_error)?;
+
+        let mut levels = Vec::new();
+        for row in rows {
+            let (product_id, location_id, quantity) = row.map_err(database_error)?;
+            levels.push(StockLevel {
+                product_id: Uuid::parse_str(&product_id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                location_id,
+                quantity,
+            });
+        }
+        Ok(levels)
+    }
+
+    fn load_movements(&self, product_id: Uuid) -> Result<Vec<StockMovement>, StorageError> {
+        let mut statement = self.connection
+            .prepare(
+                "SELECT id, location_id, quantity_change, reason, user, timestamp, reference, lot_number, serial_number,
+                        unit_cost, unit_price
+                 FROM stock_movements WHERE product_id = ?1 ORDER BY timestamp, rowid",
+            )
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map(params![product_id.to_string()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, Option<f64>>(9)?,
+                    row.get::<_, Option<f64>>(10)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut movements = Vec::new();
+        for row in rows {
+            let (
+                id,
+                location_id,
+                quantity_change,
+                reason,
+                user,
+                timestamp,
+                reference,
+                lot_number,
+                serial_number,
+                unit_cost,
+                unit_price,
+            ) = row.map_err(database_error)?;
+            movements.push(StockMovement {
+                id: Uuid::parse_str(&id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                product_id,
+                location_id,
+                quantity_change,
+                reason: MovementReason::parse(&reason)
+                    .ok_or_else(|| StorageError::Corrupted(format!("unknown movement reason {}", reason)))?,
+                user,
+                timestamp: DateTime::parse_from_rfc3339(&timestamp)
+                    .map_err(|e| StorageError::Corrupted(e.to_string()))?
+                    .with_timezone(&Utc),
+                reference,
+                lot_number,
+                serial_number,
+                unit_cost,
+                unit_price,
+            });
+        }
+        Ok(movements)
+    }
+
+    fn load_reorder_policies(&self) -> Result<Vec<ReorderPolicy>, StorageError> {
+        let mut statement = self.connection
+            .prepare("SELECT product_id, reorder_point, reorder_quantity, supplier FROM reorder_policies")
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut policies = Vec::new();
+        for row in rows {
+            let (product_id, reorder_point, reorder_quantity, supplier) = row.map_err(database_error)?;
+            policies.push(ReorderPolicy {
+                product_id: Uuid::parse_str(&product_id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                reorder_point,
+                reorder_quantity,
+                supplier,
+            });
+        }
+        Ok(policies)
+    }
+
+    fn load_lots(&self) -> Result<Vec<Lot>, StorageError> {
+        let mut statement = self.connection
+            .prepare(
+                "SELECT id, product_id, location_id, lot_number, manufactured_on, expires_on, quantity FROM lots",
+            )
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, u32>(6)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut lots = Vec::new();
+        for row in rows {
+            let (id, product_id, location_id, lot_number, manufactured_on, expires_on, quantity) =
+                row.map_err(database_error)?;
+            lots.push(Lot {
+                id: Uuid::parse_str(&id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                product_id: Uuid::parse_str(&product_id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                location_id,
+                lot_number,
+                manufactured_on: parse_date(&manufactured_on)?,
+                expires_on: expires_on.as_deref().map(parse_date).transpose()?,
+                quantity,
+            });
+        }
+        Ok(lots)
+    }
+
+    fn load_units(&self) -> Result<Vec<SerializedUnit>, StorageError> {
+        let mut statement = self.connection
+            .prepare(
+                "SELECT id, product_id, serial_number, location_id, status, received_at, sold_at, sale_reference, sale_anchor
+                 FROM serialized_units",
+            )
+            .map_err(database_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ))
+            })
+            .map_err(database_error)?;
+
+        let mut units = Vec::new();
+        for row in rows {
+            let (id, product_id, serial_number, location_id, status, received_at, sold_at, sale_reference, sale_anchor) =
+                row.map_err(database_error)?;
+            units.push(SerializedUnit {
+                id: Uuid::parse_str(&id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                product_id: Uuid::parse_str(&product_id).map_err(|e| StorageError::Corrupted(e.to_string()))?,
+                serial_number,
+                location_id,
+                status: UnitStatus::parse(&status)
+                    .ok_or_else(|| StorageError::Corrupted(format!("unknown unit status {}", status)))?,
+                received_at: parse_timestamp(&received_at)?,
+                sold_at: sold_at.as_deref().map(parse_timestamp).transpose()?,
+                sale_reference,
+                sale_anchor,
+            });
+        }
+        Ok(units)
+    }
+
+    fn load_valuation_method(&self) -> Result<Option<ValuationMethod>, StorageError> {
+        let value: Option<String> = self.connection
+            .query_row("SELECT value FROM settings WHERE key = 'valuation_method'", [], |row| row.get(0))
+            .optional()
+            .map_err(database_error)?;
+        value
+            .map(|value| {
+                ValuationMethod::parse(&value)
+                    .ok_or_else(|| StorageError::Corrupted(format!("unknown valuation method {}", value)))
+            })
+            .transpose()
+    }
+
+    // Transaction tự rollback khi bị drop mà chưa commit
+    fn apply(&mut self, changes: &[StoreChange]) -> Result<(), StorageError> {
+        let transaction = self.connection.transaction().map_err(database_error)?;
+
+        for change in changes {
+            match change {
+                StoreChange::InsertProduct(product) => {
+                    let exists = transaction
+                        .query_row("SELECT 1 FROM products WHERE id = ?1", params![product.id.to_string()], |_| Ok(()))
+                        .optional()
+                        .map_err(database_error)?;
+                    if exists.is_some() {
+                        return Err(StorageError::DuplicateProduct(product.id));
+                    }
+
+                    transaction
+                        .execute(
+                            "INSERT INTO products (id, sku, name, description, price, quantity, manufacturer, created_at)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                            params![
+                                product.id.to_string(),
+                                product.sku,
+                                product.name,
+                                product.description,
+                                product.price,
+                                product.quantity,
+                                product.manufacturer,
+                                product.created_at.to_rfc3339(),
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::UpdateQuantity { product_id, expected, quantity } => {
+                    let updated = transaction
+                        .execute(
+                            "UPDATE products SET quantity = ?1 WHERE id = ?2 AND quantity = ?3",
+                            params![quantity, product_id.to_string(), expected],
+                        )
+                        .map_err(database_error)?;
+
+                    if updated == 0 {
+                        let exists = transaction
+                            .query_row("SELECT 1 FROM products WHERE id = ?1", params![product_id.to_string()], |_| Ok(()))
+                            .optional()
+                            .map_err(database_error)?;
+                        return Err(match exists {
+                            Some(()) => StorageError::Conflict(*product_id),
+                            None => StorageError::ProductMissing(*product_id),
+                        });
+                    }
+                }
+                StoreChange::UpdateSku { product_id, sku } => {
+                    let updated = transaction
+                        .execute("UPDATE products SET sku = ?1 WHERE id = ?2", params![sku, product_id.to_string()])
+                        .map_err(database_error)?;
+                    if updated == 0 {
+                        return Err(StorageError::ProductMissing(*product_id));
+                    }
+                }
+                StoreChange::AddBarcode { product_id, barcode } => {
+                    transaction
+                        .execute(
+                            "INSERT INTO product_barcodes (barcode, product_id) VALUES (?1, ?2)",
+                            params![barcode, product_id.to_string()],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::SetStockLevel { product_id, location_id, expected, quantity } => {
+                    let current: u32 = transaction
+                        .query_row(
+                            "SELECT quantity FROM stock_levels WHERE product_id = ?1 AND location_id = ?2",
+                            params![product_id.to_string(), location_id],
+                            |row| row.get(0),
+                        )
+                        .optional()
+                        .map_err(database_error)?
+                        .unwrap_or(0);
+                    if current != *expected {
+                        return Err(StorageError::Conflict(*product_id));
+                    }
+
+                    transaction
+                        .execute(
+                            "INSERT INTO stock_levels (product_id, location_id, quantity) VALUES (?1, ?2, ?3)
+                             ON CONFLICT (product_id, location_id) DO UPDATE SET quantity = excluded.quantity",
+                            params![product_id.to_string(), location_id, quantity],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::InsertLocation(location) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO locations (id, name, kind, low_stock_threshold) VALUES (?1, ?2, ?3, ?4)",
+                            params![location.id, location.name, location.kind.as_str(), location.low_stock_threshold],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::RemoveBarcode { product_id, barcode } => {
+                    transaction
+                        .execute(
+                            "DELETE FROM product_barcodes WHERE barcode = ?1 AND product_id = ?2",
+                            params![barcode, product_id.to_string()],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::AppendMovement(movement) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO stock_movements
+                                (id, product_id, location_id, quantity_change, reason, user, timestamp, reference,
+                                 lot_number, serial_number, unit_cost, unit_price)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                            params![
+                                movement.id.to_string(),
+                                movement.product_id.to_string(),
+                                movement.location_id,
+                                movement.quantity_change,
+                                movement.reason.as_str(),
+                                movement.user,
+                                movement.timestamp.to_rfc3339(),
+                                movement.reference,
+                                movement.lot_number,
+                                movement.serial_number,
+                                movement.unit_cost,
+                                movement.unit_price,
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::InsertLot(lot) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO lots (id, product_id, location_id, lot_number, manufactured_on, expires_on, quantity)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                            params![
+                                lot.id.to_string(),
+                                lot.product_id.to_string(),
+                                lot.location_id,
+                                lot.lot_number,
+                                lot.manufactured_on.to_string(),
+                                lot.expires_on.map(|expires_on| expires_on.to_string()),
+                                lot.quantity,
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::UpdateLotQuantity { lot_id, expected, quantity } => {
+                    let updated = transaction
+                        .execute(
+                            "UPDATE lots SET quantity = ?1 WHERE id = ?2 AND quantity = ?3",
+                            params![quantity, lot_id.to_string(), expected],
+                        )
+                        .map_err(database_error)?;
+                    if updated == 0 {
+                        let product_id: Option<String> = transaction
+                            .query_row("SELECT product_id FROM lots WHERE id = ?1", params![lot_id.to_string()], |row| row.get(0))
+                            .optional()
+                            .map_err(database_error)?;
+                        return Err(match product_id.and_then(|id| Uuid::parse_str(&id).ok()) {
+                            Some(product_id) => StorageError::Conflict(product_id),
+                            None => StorageError::Constraint(format!("lot {} does not exist", lot_id)),
+                        });
+                    }
+                }
+                StoreChange::InsertUnit(unit) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO serialized_units
+                                (id, product_id, serial_number, location_id, status, received_at, sold_at, sale_reference, sale_anchor)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                            params![
+                                unit.id.to_string(),
+                                unit.product_id.to_string(),
+                                unit.serial_number,
+                                unit.location_id,
+                                unit.status.as_str(),
+                                unit.received_at.to_rfc3339(),
+                                unit.sold_at.map(|sold_at| sold_at.to_rfc3339()),
+                                unit.sale_reference,
+                                unit.sale_anchor,
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::UpdateUnit { expected_status, expected_location, unit } => {
+                    let updated = transaction
+                        .execute(
+                            "UPDATE serialized_units
+                             SET location_id = ?1, status = ?2, sold_at = ?3, sale_reference = ?4, sale_anchor = ?5
+                             WHERE id = ?6 AND status = ?7 AND location_id = ?8",
+                            params![
+                                unit.location_id,
+                                unit.status.as_str(),
+                                unit.sold_at.map(|sold_at| sold_at.to_rfc3339()),
+                                unit.sale_reference,
+                                unit.sale_anchor,
+                                unit.id.to_string(),
+                                expected_status.as_str(),
+                                expected_location,
+                            ],
+                        )
+                        .map_err(database_error)?;
+                    if updated == 0 {
+                        return Err(StorageError::Conflict(unit.product_id));
+                    }
+                }
+                StoreChange::SetReorderPolicy(policy) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO reorder_policies (product_id, reorder_point, reorder_quantity, supplier)
+                             VALUES (?1, ?2, ?3, ?4)
+                             ON CONFLICT (product_id) DO UPDATE SET reorder_point = excluded.reorder_point,
+                                 reorder_quantity = excluded.reorder_quantity, supplier = excluded.supplier",
+                            params![
+                                policy.product_id.to_string(),
+                                policy.reorder_point,
+                                policy.reorder_quantity,
+                                policy.supplier,
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                StoreChange::SetValuationMethod(method) => {
+                    transaction
+                        .execute(
+                            "INSERT INTO settings (key, value) VALUES ('valuation_method', ?1)
+                             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                            params![method.as_str()],
+                        )
+                        .map_err(database_error)?;
+                }
+            }
+        }
+
+        transaction.commit().map_err(database_error)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<(), StorageError> {
+    connection
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY NOT NULL,
+                applied_at TEXT NOT NULL
+            );",
+        )
+        .map_err(database_error)?;
+
+    let current = current_version(connection)?;
+    if current as usize > MIGRATIONS.len() {
+        return Err(StorageError::UnsupportedSchema(current));
+    }
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = index as u32 + 1;
+        let migration_error = |e: rusqlite::Error| StorageError::Migration { version, reason: e.to_string() };
+
+        let transaction = connection.transaction().map_err(migration_error)?;
+        transaction.execute_batch(sql).map_err(migration_error)?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+                params![version, Utc::now().to_rfc3339()],
+            )
+            .map_err(migration_error)?;
+        transaction.commit().map_err(migration_error)?;
+    }
+
+    Ok(())
+}
+
+fn current_version(connection: &Connection) -> Result<u32, StorageError> {
+    connection
+        .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", [], |row| row.get(0))
+        .map_err(database_error)
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, StorageError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|e| StorageError::Corrupted(e.to_string()))
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, StorageError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| StorageError::Corrupted(e.to_string()))
+}
+
+fn database_error(error: rusqlite::Error) -> StorageError {
+    match &error {
+        rusqlite::Error::SqliteFailure(failure, _) if failure.code == rusqlite::ErrorCode::ConstraintViolation => {
+            StorageError::Constraint(error.to_string())
+        }
+        _ => StorageError::Database(error.to_string()),
+    }
+}
@@ -0,0 +1,350 @@
+use super::ledger::StockMovement;
+use super::location::{Location, StockLevel};
+use super::lot::Lot;
+use super::reorder::ReorderPolicy;
+use super::serial::{SerializedUnit, UnitStatus};
+use super::valuation::ValuationMethod;
+use crate::models::Product;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+// Một thay đổi cần ghi xuống kho lưu trữ. Các thay đổi trong cùng một lần `apply`
+// được ghi trong một transaction: hoặc tất cả, hoặc không gì cả
+#[derive(Debug, Clone)]
+pub enum StoreChange {
+    InsertProduct(Product),
+    // Chỉ cập nhật nếu số lượng hiện tại vẫn là `expected`, tránh ghi đè thay đổi đồng thời
+    UpdateQuantity { product_id: Uuid, expected: u32, quantity: u32 },
+    // Tồn kho tại một địa điểm, cũng kiểm tra `expected` như trên
+    SetStockLevel { product_id: Uuid, location_id: String, expected: u32, quantity: u32 },
+    InsertLocation(Location),
+    UpdateSku { product_id: Uuid, sku: String },
+    AddBarcode { product_id: Uuid, barcode: String },
+    RemoveBarcode { product_id: Uuid, barcode: String },
+    // Sổ kho chỉ ghi thêm; không có thay đổi nào để sửa hay xóa dòng đã ghi
+    AppendMovement(StockMovement),
+    // Thêm hoặc thay thế điểm đặt hàng lại của sản phẩm
+    SetReorderPolicy(ReorderPolicy),
+    InsertLot(Lot),
+    // Số lượng còn lại của lô, cũng kiểm tra `expected` như tồn kho
+    UpdateLotQuantity { lot_id: Uuid, expected: u32, quantity: u32 },
+    InsertUnit(SerializedUnit),
+    // Chỉ cập nhật nếu thiết bị vẫn ở trạng thái và địa điểm `expected_*`
+    UpdateUnit { expected_status: UnitStatus, expected_location: String, unit: SerializedUnit },
+    // Phương pháp tính giá xuất kho của cả kho hàng
+    SetValuationMethod(ValuationMethod),
+}
+
+pub trait InventoryStore: Send {
+    fn load_products(&self) -> Result<Vec<Product>, StorageError>;
+    fn load_locations(&self) -> Result<Vec<Location>, StorageError>;
+    fn load_stock_levels(&self) -> Result<Vec<StockLevel>, StorageError>;
+    fn load_movements(&self, product_id: Uuid) -> Result<Vec<StockMovement>, StorageError>;
+    fn load_reorder_policies(&self) -> Result<Vec<ReorderPolicy>, StorageError>;
+    fn load_lots(&self) -> Result<Vec<Lot>, StorageError>;
+    fn load_units(&self) -> Result<Vec<SerializedUnit>, StorageError>;
+    // `None` khi chưa từng chọn phương pháp nào
+    fn load_valuation_method(&self) -> Result<Option<ValuationMethod>, StorageError>;
+    fn apply(&mut self, changes: &[StoreChange]) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Clone, Default)]
+struct InMemoryState {
+    products: HashMap<Uuid, Product>,
+    locations: HashMap<String, Location>,
+    stock_levels: HashMap<(Uuid, String), u32>,
+    movements: Vec<StockMovement>,
+    // Mã các dòng sổ kho đã ghi, để kiểm tra trùng mà không phải quét cả sổ
+    movement_ids: HashSet<Uuid>,
+    reorder_policies: HashMap<Uuid, ReorderPolicy>,
+    lots: HashMap<Uuid, Lot>,
+    units: HashMap<Uuid, SerializedUnit>,
+    valuation_method: Option<ValuationMethod>,
+}
+
+// Giống SQLite, kho mới luôn có sẵn địa điểm mặc định
+#[derive(Debug, Clone)]
+pub struct InMemoryStore {
+    state: InMemoryState,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        let mut state = InMemoryState::default();
+        let location = Location::default_store();
+        state.locations.insert(location.id.clone(), location);
+        Self { state }
+    }
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InventoryStore for InMemoryStore {
+    fn load_products(&self) -> Result<Vec<Product>, StorageError> {
+        Ok(self.state.products.values().cloned().collect())
+    }
+
+    fn load_locations(&self) -> Result<Vec<Location>, StorageError> {
+        Ok(self.state.locations.values().cloned().collect())
+    }
+
+    fn load_stock_levels(&self) -> Result<Vec<StockLevel>, StorageError> {
+        Ok(self.state.stock_levels.iter()
+            .map(|((product_id, location_id), quantity)| StockLevel {
+                product_id: *product_id,
+                location_id: location_id.clone(),
+                quantity: *quantity,
+            })
+            .collect())
+    }
+
+    fn load_movements(&self, product_id: Uuid) -> Result<Vec<StockMovement>, StorageError> {
+        Ok(self.state.movements.iter()
+            .filter(|movement| movement.product_id == product_id)
+            .cloned()
+            .collect())
+    }
+
+    fn load_reorder_policies(&self) -> Result<Vec<ReorderPolicy>, StorageError> {
+        Ok(self.state.reorder_policies.values().cloned().collect())
+    }
+
+    fn load_lots(&self) -> Result<Vec<Lot>, StorageError> {
+        Ok(self.state.lots.values().cloned().collect())
+    }
+
+    fn load_units(&self) -> Result<Vec<SerializedUnit>, StorageError> {
+        Ok(self.state.units.values().cloned().collect())
+    }
+
+    fn load_valuation_method(&self) -> Result<Option<ValuationMethod>, StorageError> {
+        Ok(self.state.valuation_method)
+    }
+
+    // Ghi thẳng vào trạng thái và giữ lại giá trị cũ của từng mục bị đổi; lỗi giữa chừng thì
+    // hoàn tác theo thứ tự ngược lại, nên không để lại thay đổi dở dang
+    fn apply(&mut self, changes: &[StoreChange]) -> Result<(), StorageError> {
+        let mut undo = Vec::with_capacity(changes.len());
+        for change in changes {
+            if let Err(e) = self.state.apply_change(change, &mut undo) {
+                self.state.rollback(undo);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Giá trị trước thay đổi của một mục; `None` nghĩa là mục chưa tồn tại
+#[derive(Debug)]
+enum Undo {
+    Product(Uuid, Option<Product>),
+    Location(String),
+    StockLevel((Uuid, String), Option<u32>),
+    Movement,
+    ReorderPolicy(Uuid, Option<ReorderPolicy>),
+    Lot(Uuid, Option<Lot>),
+    Unit(Uuid, Option<SerializedUnit>),
+    ValuationMethod(Option<ValuationMethod>),
+}
+
+impl InMemoryState {
+    fn apply_change(&mut self, change: &StoreChange, undo: &mut Vec<Undo>) -> Result<(), StorageError> {
+        let products = &mut self.products;
+
+        match change {
+            StoreChange::InsertProduct(product) => {
+                if products.contains_key(&product.id) {
+                    return Err(StorageError::DuplicateProduct(product.id));
+                }
+                ensure_unique_sku(products, product.id, &product.sku)?;
+                undo.push(Undo::Product(product.id, None));
+                products.insert(product.id, product.clone());
+            }
+            StoreChange::UpdateQuantity { product_id, expected, quantity } => {
+                let product = products.get_mut(product_id)
+                    .ok_or(StorageError::ProductMissing(*product_id))?;
+                if product.quantity != *expected {
+                    return Err(StorageError::Conflict(*product_id));
+                }
+                undo.push(Undo::Product(*product_id, Some(product.clone())));
+                product.quantity = *quantity;
+            }
+            StoreChange::UpdateSku { product_id, sku } => {
+                ensure_unique_sku(products, *product_id, sku)?;
+                let product = products.get_mut(product_id)
+                    .ok_or(StorageError::ProductMissing(*product_id))?;
+                undo.push(Undo::Product(*product_id, Some(product.clone())));
+                product.sku = sku.clone();
+            }
+            StoreChange::AddBarcode { product_id, barcode } => {
+                if products.values().any(|product| product.barcodes.contains(barcode)) {
+                    return Err(StorageError::Constraint(format!("barcode {} already exists", barcode)));
+                }
+                let product = products.get_mut(product_id)
+                    .ok_or(StorageError::ProductMissing(*product_id))?;
+                undo.push(Undo::Product(*product_id, Some(product.clone())));
+                product.barcodes.push(barcode.clone());
+            }
+            StoreChange::RemoveBarcode { product_id, barcode } => {
+                let product = products.get_mut(product_id)
+                    .ok_or(StorageError::ProductMissing(*product_id))?;
+                undo.push(Undo::Product(*product_id, Some(product.clone())));
+                product.barcodes.retain(|existing| existing != barcode);
+            }
+            StoreChange::SetStockLevel { product_id, location_id, expected, quantity } => {
+                if !products.contains_key(product_id) {
+                    return Err(StorageError::ProductMissing(*product_id));
+                }
+                if !self.locations.contains_key(location_id) {
+                    return Err(StorageError::Constraint(format!("location {} does not exist", location_id)));
+                }
+                let key = (*product_id, location_id.clone());
+                let previous = self.stock_levels.get(&key).copied();
+                if previous.unwrap_or(0) != *expected {
+                    return Err(StorageError::Conflict(*product_id));
+                }
+                undo.push(Undo::StockLevel(key.clone(), previous));
+                self.stock_levels.insert(key, *quantity);
+            }
+            StoreChange::InsertLocation(location) => {
+                if self.locations.contains_key(&location.id) {
+                    return Err(StorageError::Constraint(format!("location {} already exists", location.id)));
+                }
+                undo.push(Undo::Location(location.id.clone()));
+                self.locations.insert(location.id.clone(), location.clone());
+            }
+            StoreChange::AppendMovement(movement) => {
+                if !products.contains_key(&movement.product_id) {
+                    return Err(StorageError::ProductMissing(movement.product_id));
+                }
+                if !self.movement_ids.insert(movement.id) {
+                    return Err(StorageError::Constraint(format!("stock movement {} already exists", movement.id)));
+                }
+                undo.push(Undo::Movement);
+                self.movements.push(movement.clone());
+            }
+            StoreChange::SetReorderPolicy(policy) => {
+                if !products.contains_key(&policy.product_id) {
+                    return Err(StorageError::ProductMissing(policy.product_id));
+                }
+                let previous = self.reorder_policies.insert(policy.product_id, policy.clone());
+                undo.push(Undo::ReorderPolicy(policy.product_id, previous));
+            }
+            StoreChange::InsertLot(lot) => {
+                if !products.contains_key(&lot.product_id) {
+                    return Err(StorageError::ProductMissing(lot.product_id));
+                }
+                let duplicate = self.lots.values().any(|existing| {
+                    existing.id == lot.id
+                        || (existing.product_id == lot.product_id
+                            && existing.location_id == lot.location_id
+                            && existing.lot_number == lot.lot_number)
+                });
+                if duplicate {
+                    return Err(StorageError::Constraint(format!("lot {} already exists", lot.lot_number)));
+                }
+                undo.push(Undo::Lot(lot.id, None));
+                self.lots.insert(lot.id, lot.clone());
+            }
+            StoreChange::UpdateLotQuantity { lot_id, expected, quantity } => {
+                let lot = self.lots.get_mut(lot_id)
+                    .ok_or_else(|| StorageError::Constraint(format!("lot {} does not exist", lot_id)))?;
+                if lot.quantity != *expected {
+                    return Err(StorageError::Conflict(lot.product_id));
+                }
+                undo.push(Undo::Lot(*lot_id, Some(lot.clone())));
+                lot.quantity = *quantity;
+            }
+            StoreChange::InsertUnit(unit) => {
+                if !products.contains_key(&unit.product_id) {
+                    return Err(StorageError::ProductMissing(unit.product_id));
+                }
+                if self.units.values().any(|existing| existing.id == unit.id || existing.serial_number == unit.serial_number) {
+                    return Err(StorageError::Constraint(format!("serial number {} already exists", unit.serial_number)));
+                }
+                undo.push(Undo::Unit(unit.id, None));
+                self.units.insert(unit.id, unit.clone());
+            }
+            StoreChange::UpdateUnit { expected_status, expected_location, unit } => {
+                let existing = self.units.get_mut(&unit.id)
+                    .ok_or_else(|| StorageError::Constraint(format!("unit {} does not exist", unit.id)))?;
+                if existing.status != *expected_status || existing.location_id != *expected_location {
+                    return Err(StorageError::Conflict(existing.product_id));
+                }
+                undo.push(Undo::Unit(unit.id, Some(existing.clone())));
+                *existing = unit.clone();
+            }
+            StoreChange::SetValuationMethod(method) => {
+                undo.push(Undo::ValuationMethod(self.valuation_method.replace(*method)));
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self, undo: Vec<Undo>) {
+        for entry in undo.into_iter().rev() {
+            match entry {
+                Undo::Product(id, previous) => restore(&mut self.products, id, previous),
+                Undo::Location(id) => {
+                    self.locations.remove(&id);
+                }
+                Undo::StockLevel(key, previous) => restore(&mut self.stock_levels, key, previous),
+                Undo::Movement => {
+                    if let Some(movement) = self.movements.pop() {
+                        self.movement_ids.remove(&movement.id);
+                    }
+                }
+                Undo::ReorderPolicy(id, previous) => restore(&mut self.reorder_policies, id, previous),
+                Undo::Lot(id, previous) => restore(&mut self.lots, id, previous),
+                Undo::Unit(id, previous) => restore(&mut self.units, id, previous),
+                Undo::ValuationMethod(previous) => self.valuation_method = previous,
+            }
+        }
+    }
+}
+
+fn restore<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, previous: Option<V>) {
+    match previous {
+        Some(value) => {
+            map.insert(key, value);
+        }
+        None => {
+            map.remove(&key);
+        }
+    }
+}
+
+// Giống chỉ mục UNIQUE ... COLLATE NOCASE của SQLite
+fn ensure_unique_sku(products: &HashMap<Uuid, Product>, product_id: Uuid, sku: &str) -> Result<(), StorageError> {
+    let taken = products.values()
+        .any(|product| product.id != product_id && product.sku.eq_ignore_ascii_case(sku));
+    if taken {
+        return Err(StorageError::Constraint(format!("sku {} already exists", sku)));
+    }
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Migration {version} failed: {reason}")]
+    Migration { version: u32, reason: String },
+    #[error("Database schema version {0} is newer than supported")]
+    UnsupportedSchema(u32),
+    #[error("Stored data is corrupted: {0}")]
+    Corrupted(String),
+    #[error("Constraint violation: {0}")]
+    Constraint(String),
+    #[error("Product {0} already exists in storage")]
+    DuplicateProduct(Uuid),
+    #[error("Product {0} is missing from storage")]
+    ProductMissing(Uuid),
+    #[error("Product {0} was modified concurrently")]
+    Conflict(Uuid),
+}
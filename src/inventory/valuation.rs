@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValuationMethod {
+    // Xuất theo giá của lớp nhập sớm nhất còn hàng
+    Fifo,
+    // Xuất theo giá bình quân gia quyền của hàng đang tồn
+    WeightedAverage,
+}
+
+impl ValuationMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValuationMethod::Fifo => "Fifo",
+            ValuationMethod::WeightedAverage => "WeightedAverage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Fifo" => Some(ValuationMethod::Fifo),
+            "WeightedAverage" => Some(ValuationMethod::WeightedAverage),
+            _ => None,
+        }
+    }
+}
+
+// Tồn kho ban đầu khi thêm sản phẩm, kèm giá vốn đơn vị
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpeningStock {
+    pub quantity: u32,
+    pub unit_cost: f64,
+}
+
+impl OpeningStock {
+    pub fn new(quantity: u32, unit_cost: f64) -> Self {
+        Self { quantity, unit_cost }
+    }
+}
+
+// Các lớp giá vốn của một sản phẩm (số lượng, giá vốn đơn vị) theo thứ tự nhập.
+// Với bình quân gia quyền chỉ có một lớp duy nhất
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostLayers {
+    layers: VecDeque<(u32, f64)>,
+}
+
+impl CostLayers {
+    pub fn receive(&mut self, quantity: u32, unit_cost: f64, method: ValuationMethod) {
+        if quantity == 0 {
+            return;
+        }
+        match (method, self.layers.front_mut()) {
+            (ValuationMethod::WeightedAverage, Some((on_hand, average))) => {
+                let total = f64::from(*on_hand) * *average + f64::from(quantity) * unit_cost;
+                *on_hand += quantity;
+                *average = total / f64::from(*on_hand);
+            }
+            _ => self.layers.push_back((quantity, unit_cost)),
+        }
+    }
+
+    // Lấy `quantity` đơn vị ra khỏi các lớp, trả về tổng giá vốn. Phần vượt quá hàng có giá vốn
+    // (dữ liệu cũ trước khi theo dõi giá vốn) được tính giá 0
+    pub fn issue(&mut self, quantity: u32) -> f64 {
+        let mut remaining = quantity;
+        let mut cost = 0.0;
+        while remaining > 0 {
+            let Some((on_hand, unit_cost)) = self.layers.front_mut() else {
+                break;
+            };
+            let taken = remaining.min(*on_hand);
+            cost += f64::from(taken) * *unit_cost;
+            *on_hand -= taken;
+            remaining -= taken;
+            if *on_hand == 0 {
+                self.layers.pop_front();
+            }
+        }
+        cost
+    }
+
+    pub fn quantity(&self) -> u32 {
+        self.layers.iter().map(|(quantity, _)| quantity).sum()
+    }
+
+    pub fn value(&self) -> f64 {
+        self.layers.iter().map(|(quantity, unit_cost)| f64::from(*quantity) * unit_cost).sum()
+    }
+
+    // Giá vốn bình quân của hàng đang tồn; 0 nếu hết hàng
+    pub fn average_cost(&self) -> f64 {
+        match self.quantity() {
+            0 => 0.0,
+            quantity => self.value() / f64::from(quantity),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValuationLine {
+    pub product_id: Uuid,
+    pub sku: String,
+    pub quantity: u32,
+    pub value: f64,
+}
+
+// Giá trị tồn kho tại một thời điểm, tính lại từ sổ kho
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValuationReport {
+    pub at: DateTime<Utc>,
+    pub method: ValuationMethod,
+    pub lines: Vec<ValuationLine>,
+    pub total_value: f64,
+}
+
+// Lãi gộp của một sản phẩm trong khoảng [since, until): doanh thu theo giá bán lúc bán,
+// giá vốn theo giá vốn đã ghi trên từng dòng bán; hàng trả lại được trừ ra
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrossMargin {
+    pub product_id: Uuid,
+    pub sku: String,
+    pub units_sold: i64,
+    pub revenue: f64,
+    pub cost_of_goods_sold: f64,
+    pub gross_profit: f64,
+    // Phần trăm lãi gộp trên doanh thu; None nếu không có doanh thu
+    pub margin_percent: Option<f64>,
+}
@@ -3,10 +3,22 @@ pub mod payment;
 pub mod supply_chain;
 pub mod inventory;
 pub mod blockchain;
+pub mod loyalty;
+pub mod token;
+pub mod wallet;
+pub mod risk;
+pub mod api;
+pub mod scheduler;
+pub mod purchasing;
 
 // Re-export các struct chính để dễ dàng import
 pub use blockchain::Blockchain;
 pub use payment::PaymentProcessor;
 pub use supply_chain::SupplyChainManager;
 pub use inventory::InventoryManager;
+pub use loyalty::LoyaltyProgram;
+pub use token::TokenRegistry;
+pub use wallet::Wallet;
+pub use scheduler::PaymentScheduler;
+pub use purchasing::PurchaseOrderManager;
 pub use models::{Currency, SupplyChainAction};
\ No newline at end of file
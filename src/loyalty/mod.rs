@@ -0,0 +1,362 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LoyaltyTier {
+    Bronze,
+    Silver,
+    Gold,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TierRule {
+    pub tier: LoyaltyTier,
+    pub min_lifetime_points: u64,
+    pub earn_multiplier: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Promotion {
+    pub name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub multiplier: f64,
+    pub bonus_points: u64,
+    // Chỉ áp dụng cho một danh mục nếu có
+    pub category: Option<String>,
+}
+
+impl Promotion {
+    fn is_active(&self, at: DateTime<Utc>) -> bool {
+        self.starts_at <= at && at < self.ends_at
+    }
+}
+
+// Một dòng hàng trong đơn, `amount` tính theo đơn vị tiền tệ cơ sở (USDT)
+#[derive(Debug, Clone)]
+pub struct PurchaseLine {
+    pub category: Option<String>,
+    pub amount: f64,
+}
+
+impl PurchaseLine {
+    pub fn new(category: Option<String>, amount: f64) -> Self {
+        Self { category, amount }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerEntryKind {
+    Earn,
+    Redeem,
+    Expire,
+    Adjust,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoyaltyLedgerEntry {
+    pub id: Uuid,
+    pub customer: String,
+    pub kind: LedgerEntryKind,
+    pub points: i64,
+    pub balance_after: u64,
+    pub reference: Option<Uuid>,
+    pub description: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redemption {
+    pub points_used: u64,
+    pub discount: f64,
+    pub amount_due: f64,
+}
+
+// Điểm được cộng theo từng lô để có thể hết hạn riêng
+#[derive(Debug, Clone)]
+struct PointLot {
+    expires_at: DateTime<Utc>,
+    remaining: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CustomerAccount {
+    lots: Vec<PointLot>,
+    lifetime_points: u64,
+}
+
+impl CustomerAccount {
+    fn balance(&self) -> u64 {
+        self.lots.iter().map(|lot| lot.remaining).sum()
+    }
+
+    fn available_at(&self, at: DateTime<Utc>) -> u64 {
+        self.lots.iter()
+            .filter(|lot| lot.expires_at > at)
+            .map(|lot| lot.remaining)
+            .sum()
+    }
+}
+
+pub struct LoyaltyProgram {
+    points_per_unit: f64,
+    point_value: f64,
+    points_validity: Duration,
+    category_multipliers: HashMap<String, f64>,
+    promotions: Vec<Promotion>,
+    tiers: Vec<TierRule>,
+    accounts: HashMap<String, CustomerAccount>,
+    ledger: Vec<LoyaltyLedgerEntry>,
+}
+
+impl LoyaltyProgram {
+    pub fn new(points_per_unit: f64, point_value: f64, validity_days: i64) -> Self {
+        Self {
+            points_per_unit,
+            point_value,
+            points_validity: Duration::days(validity_days),
+            category_multipliers: HashMap::new(),
+            promotions: Vec::new(),
+            tiers: vec![
+                TierRule { tier: LoyaltyTier::Bronze, min_lifetime_points: 0, earn_multiplier: 1.0 },
+                TierRule { tier: LoyaltyTier::Silver, min_lifetime_points: 1_000, earn_multiplier: 1.25 },
+                TierRule { tier: LoyaltyTier::Gold, min_lifetime_points: 5_000, earn_multiplier: 1.5 },
+            ],
+            accounts: HashMap::new(),
+            ledger: Vec::new(),
+        }
+    }
+
+    pub fn set_category_multiplier(&mut self, category: String, multiplier: f64) {
+        self.category_multipliers.insert(category, multiplier);
+    }
+
+    pub fn add_promotion(&mut self, promotion: Promotion) {
+        self.promotions.push(promotion);
+    }
+
+    pub fn set_tiers(&mut self, mut tiers: Vec<TierRule>) {
+        tiers.sort_by_key(|rule| rule.min_lifetime_points);
+        self.tiers = tiers;
+    }
+
+    pub fn get_balance(&self, customer: &str) -> u64 {
+        self.accounts.get(customer).map(|account| account.balance()).unwrap_or(0)
+    }
+
+    pub fn get_available_points(&self, customer: &str, at: DateTime<Utc>) -> u64 {
+        self.accounts.get(customer).map(|account| account.available_at(at)).unwrap_or(0)
+    }
+
+    pub fn get_tier(&self, customer: &str) -> LoyaltyTier {
+        self.tier_rule(customer).map(|rule| rule.tier).unwrap_or(LoyaltyTier::Bronze)
+    }
+
+    fn tier_rule(&self, customer: &str) -> Option<&TierRule> {
+        let lifetime = self.accounts.get(customer).map(|account| account.lifetime_points).unwrap_or(0);
+        self.tiers.iter()
+            .rev()
+            .find(|rule| lifetime >= rule.min_lifetime_points)
+    }
+
+    pub fn calculate_points(&self, customer: &str, lines: &[PurchaseLine], at: DateTime<Utc>) -> u64 {
+        let tier_multiplier = self.tier_rule(customer).map(|rule| rule.earn_multiplier).unwrap_or(1.0);
+        let active: Vec<&Promotion> = self.promotions.iter()
+            .filter(|promotion| promotion.is_active(at))
+            .collect();
+
+        let mut points = 0.0;
+        for line in lines {
+            let mut multiplier = tier_multiplier;
+            if let Some(category) = &line.category {
+                multiplier *= self.category_multipliers.get(category).copied().unwrap_or(1.0);
+            }
+            for promotion in &active {
+                if promotion.category.is_none() || promotion.category == line.category {
+                    multiplier *= promotion.multiplier;
+                }
+            }
+            points += line.amount.max(0.0) * self.points_per_unit * multiplier;
+        }
+
+        let bonus: u64 = active.iter().map(|promotion| promotion.bonus_points).sum();
+        points.floor() as u64 + bonus
+    }
+
+    pub fn earn_points(
+        &mut self,
+        customer: &str,
+        lines: &[PurchaseLine],
+        reference: Option<Uuid>,
+        at: DateTime<Utc>,
+    ) -> Result<LoyaltyLedgerEntry, LoyaltyError> {
+        let points = self.calculate_points(customer, lines, at);
+        if points == 0 {
+            return Err(LoyaltyError::InvalidPoints);
+        }
+
+        let expires_at = at + self.points_validity;
+        let account = self.accounts.entry(customer.to_string()).or_default();
+        account.lots.push(PointLot { expires_at, remaining: points });
+        account.lifetime_points += points;
+
+        println!("🎫 {} earned {} loyalty points", customer, points);
+        Ok(self.append_entry(customer, LedgerEntryKind::Earn, points as i64, reference, "Purchase".to_string(), at))
+    }
+
+    pub fn preview_redemption(
+        &self,
+        customer: &str,
+        order_total: f64,
+        points: u64,
+        at: DateTime<Utc>,
+    ) -> Result<Redemption, LoyaltyError> {
+        if points == 0 || order_total <= 0.0 {
+            return Err(LoyaltyError::InvalidPoints);
+        }
+        if self.get_available_points(customer, at) < points {
+            return Err(LoyaltyError::InsufficientPoints);
+        }
+
+        // Không dùng nhiều điểm hơn giá trị đơn hàng
+        let max_points = (order_total / self.point_value).floor() as u64;
+        let points_used = points.min(max_points);
+        let discount = points_used as f64 * self.point_value;
+
+        Ok(Redemption {
+            points_used,
+            discount,
+            amount_due: order_total - discount,
+        })
+    }
+
+    pub fn redeem_points(
+        &mut self,
+        customer: &str,
+        order_total: f64,
+        points: u64,
+        reference: Option<Uuid>,
+        at: DateTime<Utc>,
+    ) -> Result<Redemption, LoyaltyError> {
+        let redemption = self.preview_redemption(customer, order_total, points, at)?;
+        self.consume(customer, redemption.points_used, at)?;
+        self.append_entry(
+            customer,
+            LedgerEntryKind::Redeem,
+            -(redemption.points_used as i64),
+            reference,
+            format!("Discount {:.2}", redemption.discount),
+            at,
+        );
+
+        println!("🎫 {} redeemed {} points for {:.2} discount", customer, redemption.points_used, redemption.discount);
+        Ok(redemption)
+    }
+
+    // `reference` là giao dịch được điều chỉnh, nếu có (ví dụ hoàn lại điểm của thanh toán bị từ chối)
+    pub fn adjust_points(
+        &mut self,
+        customer: &str,
+        delta: i64,
+        reason: String,
+        reference: Option<Uuid>,
+        at: DateTime<Utc>,
+    ) -> Result<LoyaltyLedgerEntry, LoyaltyError> {
+        if delta == 0 {
+            return Err(LoyaltyError::InvalidPoints);
+        }
+
+        if delta > 0 {
+            let expires_at = at + self.points_validity;
+            let account = self.accounts.entry(customer.to_string()).or_default();
+            account.lots.push(PointLot { expires_at, remaining: delta as u64 });
+        } else {
+            self.consume(customer, delta.unsigned_abs(), at)?;
+        }
+
+        Ok(self.append_entry(customer, LedgerEntryKind::Adjust, delta, reference, reason, at))
+    }
+
+    pub fn expire_points(&mut self, at: DateTime<Utc>) -> Vec<LoyaltyLedgerEntry> {
+        let mut expired: Vec<(String, u64)> = Vec::new();
+        for (customer, account) in self.accounts.iter_mut() {
+            let points: u64 = account.lots.iter()
+                .filter(|lot| lot.expires_at <= at)
+                .map(|lot| lot.remaining)
+                .sum();
+            account.lots.retain(|lot| lot.expires_at > at && lot.remaining > 0);
+            if points > 0 {
+                expired.push((customer.clone(), points));
+            }
+        }
+        expired.sort();
+
+        expired.into_iter()
+            .map(|(customer, points)| {
+                self.append_entry(&customer, LedgerEntryKind::Expire, -(points as i64), None, "Points expired".to_string(), at)
+            })
+            .collect()
+    }
+
+    pub fn get_ledger(&self, customer: &str) -> Vec<&LoyaltyLedgerEntry> {
+        self.ledger.iter()
+            .filter(|entry| entry.customer == customer)
+            .collect()
+    }
+
+    // Trừ điểm từ lô sắp hết hạn trước
+    fn consume(&mut self, customer: &str, points: u64, at: DateTime<Utc>) -> Result<(), LoyaltyError> {
+        let account = self.accounts.get_mut(customer)
+            .ok_or(LoyaltyError::InsufficientPoints)?;
+        if account.available_at(at) < points {
+            return Err(LoyaltyError::InsufficientPoints);
+        }
+
+        account.lots.sort_by_key(|lot| lot.expires_at);
+        let mut remaining = points;
+        for lot in account.lots.iter_mut().filter(|lot| lot.expires_at > at) {
+            let taken = lot.remaining.min(remaining);
+            lot.remaining -= taken;
+            remaining -= taken;
+            if remaining == 0 {
+                break;
+            }
+        }
+        account.lots.retain(|lot| lot.remaining > 0);
+        Ok(())
+    }
+
+    fn append_entry(
+        &mut self,
+        customer: &str,
+        kind: LedgerEntryKind,
+        points: i64,
+        reference: Option<Uuid>,
+        description: String,
+        at: DateTime<Utc>,
+    ) -> LoyaltyLedgerEntry {
+        let entry = LoyaltyLedgerEntry {
+            id: Uuid::new_v4(),
+            customer: customer.to_string(),
+            kind,
+            points,
+            balance_after: self.get_balance(customer),
+            reference,
+            description,
+            timestamp: at,
+        };
+
+        self.ledger.push(entry.clone());
+        entry
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoyaltyError {
+    #[error("Invalid loyalty points")]
+    InvalidPoints,
+    #[error("Insufficient loyalty points")]
+    InsufficientPoints,
+}
@@ -1,31 +1,65 @@
 use retailchain::{
-    PaymentProcessor, SupplyChainManager, InventoryManager, Blockchain,
+    PaymentProcessor, SupplyChainManager, InventoryManager, Blockchain, LoyaltyProgram, TokenRegistry, Wallet,
+    PurchaseOrderManager,
+    inventory::{location::{Location, LocationKind, DEFAULT_LOCATION}, lot::LotInfo, reorder::ReorderPolicy, valuation::OpeningStock},
+    loyalty::PurchaseLine,
     models::{Currency, SupplyChainAction},
+    payment::{RefundMethod, Tender, fees::{FeeRule, FeeSchedule}},
 };
 use serde_json::json;
 
+// Địa chỉ demo: ví ERC-20 (ETH/USDT) và ví RETAIL của khách hàng và cửa hàng
+const CUSTOMER_WALLET: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+const CUSTOMER_RETAIL_WALLET: &str = "rtl12mfr257vyaueyxjwyy5d04m53vz2dgnh08kfus";
+const RETAILER_WALLET: &str = "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359";
+const RETAILER_RETAIL_WALLET: &str = "rtl1dzm54tm7mjd4d6xr80n29urj20wsn0ctn70n32";
+const RETURNING_CUSTOMER_WALLET: &str = "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB";
+// Nhân viên thực hiện các thay đổi tồn kho trong demo
+const STORE_CLERK: &str = "clerk-01";
+
 #[tokio::main]
 async fn main() {
     println!("🚀 Khởi chạy RetailChain...");
 
     // Khởi tạo các module
     let mut payment_processor = PaymentProcessor::new();
+    payment_processor.set_fee_schedule(
+        FeeSchedule::new(1.0)
+            .with_currency_rule(Currency::USDT, FeeRule::new(0.1, 0.5))
+            .with_network_fee(Currency::BTC, 0.00002),
+    );
     let mut supply_chain = SupplyChainManager::new();
     let mut inventory = InventoryManager::new(10);
     let mut blockchain = Blockchain::new();
+    let mut loyalty = LoyaltyProgram::new(1.0, 0.01, 365);
+    loyalty.set_category_multiplier("Electronics".to_string(), 2.0);
 
     // Demo: Thêm sản phẩm mới
     println!("\n📦 Thêm sản phẩm vào kho...");
-    let product = inventory.add_product(
+    let product = inventory.add_product_with_opening_stock(
         "iPhone 14 Pro".to_string(),
         "IP14P-256".to_string(),
         "Latest Apple smartphone".to_string(),
         999.99,
-        50,
         "Apple Inc.".to_string(),
-    );
+        OpeningStock::new(50, 820.0),
+    ).expect("Không thể thêm sản phẩm");
 
     println!("✅ Đã thêm sản phẩm: {} (SKU: {})", product.name, product.sku);
+    let _ = inventory.set_reorder_policy(
+        ReorderPolicy::new(product.id, 150, 100).with_supplier("Apple Vietnam".to_string()),
+    );
+
+    // Demo: Tồn kho theo địa điểm
+    let warehouse = Location::new("WH-HCM".to_string(), "Kho Thủ Đức".to_string(), LocationKind::Warehouse);
+    if inventory.add_location(warehouse).is_ok() {
+        let lot = LotInfo::new("BATCH-001".to_string(), chrono::Utc::now().date_naive()).with_unit_cost(800.0);
+        let _ = inventory.receive_lot(product.id, "WH-HCM", lot, 100, STORE_CLERK, None);
+        let _ = inventory.transfer_stock(product.id, "WH-HCM", DEFAULT_LOCATION, 20, STORE_CLERK);
+        for (location, quantity) in inventory.get_stock_levels(product.id) {
+            println!("• {} ({:?}): {}", location.name, location.kind, quantity);
+        }
+    }
 
     // Demo: Theo dõi chuỗi cung ứng
     println!("\n📋 Ghi nhận chuỗi cung ứng...");
@@ -36,7 +70,7 @@ async fn main() {
         "Factory China".to_string(),
         "Manufacturer".to_string(),
         SupplyChainAction::Manufactured,
-        json!({"batch": "BATCH-001"}),
+        json!({"lot": "BATCH-001"}),
     );
 
     let _ = supply_chain.record_movement(
@@ -47,17 +81,78 @@ async fn main() {
         json!({"shipping_id": "SHIP-123"}),
     );
 
+    // Demo: Đơn đặt hàng nhập về kho, nhà cung cấp giao thiếu
+    println!("\n📦 Nhập hàng theo đơn đặt hàng...");
+    let mut purchase_orders = PurchaseOrderManager::new();
+    if let Ok(order_id) = purchase_orders.create_draft("Apple Vietnam".to_string(), "WH-HCM", &inventory) {
+        let _ = purchase_orders.add_line(order_id, &inventory, product.id, 40, 820.0);
+        let _ = purchase_orders.send(order_id);
+        match purchase_orders.receive(order_id, &[(product.id, 36)], STORE_CLERK, &mut inventory, &mut supply_chain) {
+            Ok(receipt) => println!("✅ Đã nhận {} dòng hàng theo đơn {}", receipt.lines.len(), order_id),
+            Err(e) => println!("❌ Lỗi nhập hàng: {}", e),
+        }
+        if let Ok(report) = purchase_orders.discrepancy_report(order_id) {
+            for line in report.short_lines() {
+                println!("⚠️  {} giao thiếu {} (đặt {}, nhận {})", line.sku, -line.difference, line.ordered, line.received);
+            }
+        }
+    }
+
+    // Demo: Ví HD sinh địa chỉ nhận tiền riêng cho từng đơn hàng
+    println!("\n🔑 Khởi tạo ví cửa hàng...");
+    let mut wallet = Wallet::generate().expect("Không thể tạo ví");
+
+    // Demo: Tạo yêu cầu thanh toán kèm mã QR cho khách quét, nhận tiền vào địa chỉ riêng của đơn
+    println!("\n📱 Tạo yêu cầu thanh toán QR...");
+    let mut order_address = RETAILER_WALLET.to_string();
+    match payment_processor.create_order_payment_request(
+        &mut wallet,
+        999.99,
+        Currency::USDT,
+        "ORDER-0001".to_string(),
+        Some("RetailChain Store".to_string()),
+    ) {
+        Ok(request) => {
+            order_address = request.address.clone();
+            println!("✅ URI: {}", request.to_uri());
+            if let Ok(svg) = request.to_qr_svg() {
+                println!("🖼️  QR SVG: {} bytes", svg.len());
+            }
+            // Giữ hàng trong lúc chờ khách thanh toán để không bán trùng chiếc cuối cùng
+            if inventory.reserve_stock(&request.reference, product.id, DEFAULT_LOCATION, 1, product.price, chrono::Duration::minutes(15)).is_ok() {
+                println!("🔒 Đã giữ 1 {} cho {} - còn có thể bán: {}",
+                         product.name, request.reference, inventory.get_available_quantity(product.id));
+            }
+        }
+        Err(e) => println!("❌ Lỗi tạo yêu cầu thanh toán: {}", e),
+    }
+
     // Demo: Xử lý thanh toán
     println!("\n💳 Xử lý thanh toán...");
     match payment_processor.process_payment(
-        "customer_wallet_123".to_string(),
-        "retailer_wallet_456".to_string(),
+        CUSTOMER_WALLET.to_string(),
+        order_address.clone(),
         999.99,
         Currency::USDT,
     ) {
         Ok(transaction) => {
             println!("✅ Thanh toán thành công: {} {}", transaction.amount, "USDT");
+            match payment_processor.reconcile_payment_request_with_reservations(&transaction, &mut inventory) {
+                Ok(Some(request)) => println!("🧾 Đã khớp với yêu cầu thanh toán {} và xuất kho", request.reference),
+                Ok(None) => {}
+                Err(e) => println!("❌ Lỗi xuất kho cho đơn: {}", e),
+            }
             
+            // Tích điểm cho khách hàng
+            if let Ok(entry) = loyalty.earn_points(
+                CUSTOMER_RETAIL_WALLET,
+                &[PurchaseLine::new(Some("Electronics".to_string()), transaction.amount)],
+                Some(transaction.id),
+                chrono::Utc::now(),
+            ) {
+                println!("🎫 Tích {} điểm, số dư: {}", entry.points, entry.balance_after);
+            }
+
             // Thêm giao dịch vào blockchain
             blockchain.add_transaction(transaction.clone());
             println!("📝 Đã thêm giao dịch vào blockchain");
@@ -68,24 +163,79 @@ async fn main() {
     // Demo thanh toán với loyalty points
     println!("\n🎫 Xử lý thanh toán với Loyalty Points...");
     match payment_processor.process_payment_with_loyalty(
-        "customer_wallet_123".to_string(),
-        "retailer_wallet_456".to_string(),
+        &mut loyalty,
+        CUSTOMER_RETAIL_WALLET.to_string(),
+        RETAILER_RETAIL_WALLET.to_string(),
         50.0,
         100,
     ) {
         Ok(transaction) => {
             println!("✅ Thanh toán loyalty thành công: {} RETAIL", transaction.amount);
+            println!("🎫 Điểm còn lại: {} (hạng {:?})",
+                     loyalty.get_balance(CUSTOMER_RETAIL_WALLET), loyalty.get_tier(CUSTOMER_RETAIL_WALLET));
             blockchain.add_transaction(transaction);
         }
         Err(e) => println!("❌ Lỗi thanh toán loyalty: {}", e),
     }
 
+    // Demo: Phát hành và chuyển token RETAIL
+    println!("\n🪙 Phát hành token RETAIL...");
+    let mut tokens = TokenRegistry::new();
+    let _ = tokens.register_token(
+        "RETAIL".to_string(),
+        "RetailChain Token".to_string(),
+        2,
+        RETAILER_RETAIL_WALLET.to_string(),
+    );
+    let _ = tokens.mint(&mut blockchain, "RETAIL", RETAILER_RETAIL_WALLET, CUSTOMER_RETAIL_WALLET, 50_000);
+    match tokens.transfer(&mut blockchain, "RETAIL", CUSTOMER_RETAIL_WALLET, RETAILER_RETAIL_WALLET, 4_900) {
+        Ok(transaction) => println!("✅ Đã chuyển {} RETAIL, số dư khách: {} (tổng cung: {:?})",
+                                    transaction.amount,
+                                    tokens.balance_of("RETAIL", CUSTOMER_RETAIL_WALLET),
+                                    tokens.total_supply("RETAIL")),
+        Err(e) => println!("❌ Lỗi chuyển token: {}", e),
+    }
+
+    // Demo: Thanh toán một đơn bằng nhiều hình thức
+    println!("\n💵 Thanh toán kết hợp USDT + RETAIL + tiền mặt...");
+    match payment_processor.process_order_payment(
+        120.0,
+        &Currency::USDT,
+        vec![
+            Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 70.0, Currency::USDT),
+            Tender::new(
+                CUSTOMER_RETAIL_WALLET.to_string(),
+                RETAILER_RETAIL_WALLET.to_string(),
+                30.0,
+                Currency::RETAIL("RETAIL".to_string()),
+            ),
+            Tender::new("customer_cash".to_string(), "cash_drawer_01".to_string(), 20.0, Currency::CASH),
+        ],
+    ) {
+        Ok(transactions) => {
+            println!("✅ Đơn hàng đã thanh toán bằng {} giao dịch", transactions.len());
+            for transaction in transactions {
+                blockchain.add_transaction(transaction);
+            }
+        }
+        Err(e) => println!("❌ Lỗi thanh toán kết hợp: {}", e),
+    }
+
+    // Demo: Báo cáo quyết toán trong ngày
+    println!("\n🧾 Báo cáo quyết toán...");
+    let settlement = payment_processor.settlement_report(RETAILER_WALLET, chrono::Utc::now().date_naive());
+    for line in &settlement.lines {
+        println!("• {}: gross {} - phí {} = net {}", line.currency.symbol(), line.gross, line.fees, line.net);
+    }
+
     // Demo: Bán sản phẩm
     println!("\n🛒 Bán sản phẩm...");
-    match inventory.sell_product(product.id, 1) {
-        Ok(()) => {
+    match inventory.sell_product(product.id, DEFAULT_LOCATION, 1, product.price, STORE_CLERK, Some("ORDER-0001".to_string())) {
+        Ok(movements) => {
             let updated_product = inventory.get_product(product.id).unwrap();
             println!("✅ Đã bán 1 {} - Tồn kho còn: {}", product.name, updated_product.quantity);
+            let cost: f64 = movements.iter().map(|movement| movement.total_cost()).sum();
+            println!("• Giá vốn hàng bán: {:.2}", cost);
             
             // Ghi nhận bán hàng trong chuỗi cung ứng
             let _ = supply_chain.record_movement(
@@ -99,6 +249,43 @@ async fn main() {
         Err(e) => println!("❌ Lỗi bán hàng: {}", e),
     }
 
+    // Demo: Bán thiết bị theo IMEI, lần bán được neo lên blockchain
+    println!("\n📱 Bán thiết bị theo IMEI...");
+    let imeis = ["490154203237518", "356938035643809"];
+    if inventory.receive_serialized_units(product.id, DEFAULT_LOCATION, &imeis, 820.0, STORE_CLERK, &mut supply_chain).is_ok() {
+        match inventory.sell_serialized_unit(
+            imeis[0], product.price, STORE_CLERK, Some("SALE-002".to_string()), &mut supply_chain, &mut blockchain,
+        ) {
+            Ok(unit) => println!("✅ Đã bán IMEI {} (mã thiết bị {})", unit.serial_number, unit.id),
+            Err(e) => println!("❌ Lỗi bán thiết bị: {}", e),
+        }
+    }
+
+    // Demo: Hoàn tiền một phần và nhập lại hàng
+    println!("\n↩️  Xử lý hoàn tiền...");
+    if let Ok(transaction) = payment_processor.process_payment(
+        RETURNING_CUSTOMER_WALLET.to_string(),
+        RETAILER_WALLET.to_string(),
+        200.0,
+        Currency::USDT,
+    ) {
+        match payment_processor.refund_payment_with_restock(
+            transaction.id,
+            100.0,
+            RefundMethod::OriginalCurrency,
+            &mut inventory,
+            DEFAULT_LOCATION,
+            &[(product.id, 1)],
+        ) {
+            Ok(refund) => {
+                println!("✅ Đã hoàn {} USDT, còn có thể hoàn: {:?}",
+                         refund.amount, payment_processor.get_refundable_amount(transaction.id));
+                blockchain.add_transaction(refund);
+            }
+            Err(e) => println!("❌ Lỗi hoàn tiền: {}", e),
+        }
+    }
+
     // Demo: Đào block mới
     println!("\n⛏️  Đào block mới...");
     match blockchain.mine_block() {
@@ -152,6 +339,23 @@ async fn main() {
             println!("   - {} (SKU: {}): {} sản phẩm", product.name, product.sku, product.quantity);
         }
     }
+    for order in inventory.suggest_purchase_orders(&purchase_orders.open_quantities()) {
+        println!("📝 Đề xuất đặt hàng {}: {} sản phẩm", order.supplier, order.total_units());
+        for line in &order.lines {
+            println!("   - {} (SKU: {}): đặt {}, còn {}, đang về {}", line.name, line.sku, line.quantity, line.on_hand, line.on_order);
+        }
+    }
+
+    // Giá trị tồn kho và lãi gộp theo giá vốn FIFO
+    let now = chrono::Utc::now();
+    if let Ok(report) = inventory.valuation_at(now) {
+        println!("💰 Giá trị tồn kho ({:?}): {:.2}", report.method, report.total_value);
+    }
+    if let Ok(margins) = inventory.gross_margin_report(now - chrono::Duration::days(1), now) {
+        for margin in margins {
+            println!("   - {}: bán {}, doanh thu {:.2}, lãi gộp {:.2}", margin.sku, margin.units_sold, margin.revenue, margin.gross_profit);
+        }
+    }
 
     println!("\n🎉 RetailChain hoạt động thành công!");
 }
@@ -161,12 +365,15 @@ mod tests {
     use super::*;
     use retailchain::models::Currency;
 
+    const CUSTOMER_BTC_WALLET: &str = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+    const RETAILER_BTC_WALLET: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
     #[test]
     fn test_payment_processing() {
         let mut processor = PaymentProcessor::new();
         let result = processor.process_payment(
-            "addr1".to_string(),
-            "addr2".to_string(),
+            CUSTOMER_WALLET.to_string(),
+            RETAILER_WALLET.to_string(),
             100.0,
             Currency::USDT,
         );
@@ -187,6 +394,1775 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_partial_refund_cannot_exceed_payment() {
+        let mut processor = PaymentProcessor::new();
+        let payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(),
+            RETAILER_WALLET.to_string(),
+            100.0,
+            Currency::USDT,
+        ).unwrap();
+
+        let refund = processor.refund_payment(payment.id, 60.0, RefundMethod::OriginalCurrency).unwrap();
+        assert_eq!(refund.refund_of, Some(payment.id));
+        assert_eq!(refund.from_address, RETAILER_WALLET);
+        assert!(processor.refund_payment(payment.id, 50.0, RefundMethod::OriginalCurrency).is_err());
+        assert!(processor.refund_payment(payment.id, 40.0, RefundMethod::OriginalCurrency).is_ok());
+        assert_eq!(
+            processor.get_transaction(payment.id).unwrap().status,
+            retailchain::models::TransactionStatus::Refunded
+        );
+    }
+
+    #[test]
+    fn test_fiat_value_refund_uses_current_rate() {
+        let mut processor = PaymentProcessor::new();
+        let payment = processor.process_payment(
+            CUSTOMER_BTC_WALLET.to_string(),
+            RETAILER_BTC_WALLET.to_string(),
+            0.01,
+            Currency::BTC,
+        ).unwrap();
+
+        processor.set_exchange_rate(Currency::BTC, 50000.0).unwrap();
+        let refund = processor.refund_payment(payment.id, 0.01, RefundMethod::FiatValue).unwrap();
+        assert!((refund.amount - 0.009).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_refund_restocks_inventory() {
+        let mut processor = PaymentProcessor::new();
+        let mut inventory = InventoryManager::new(10);
+        let product = inventory.add_product(
+            "Case".to_string(), "CASE-1".to_string(), String::new(), 10.0, 5, "Acme".to_string(),
+        ).unwrap();
+        inventory.sell_product(product.id, DEFAULT_LOCATION, 1, product.price, STORE_CLERK, None).unwrap();
+        let payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::USDT,
+        ).unwrap();
+
+        // Một sản phẩm không tồn tại: không hoàn tiền và không nhập lại gì
+        let unknown = uuid::Uuid::new_v4();
+        assert!(processor.refund_payment_with_restock(
+            payment.id, 10.0, RefundMethod::OriginalCurrency, &mut inventory, DEFAULT_LOCATION, &[(product.id, 1), (unknown, 1)],
+        ).is_err());
+        assert!(processor.get_refunds(payment.id).is_empty());
+        assert_eq!(inventory.get_product(product.id).unwrap().quantity, 4);
+
+        processor.refund_payment_with_restock(
+            payment.id, 10.0, RefundMethod::OriginalCurrency, &mut inventory, DEFAULT_LOCATION, &[(product.id, 1)],
+        ).unwrap();
+        assert_eq!(inventory.get_product(product.id).unwrap().quantity, 5);
+        assert!(processor.get_total_processed_amount().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_idempotent_payment_retry() {
+        let mut processor = PaymentProcessor::new();
+        let first = processor.process_payment_idempotent(
+            "pos-1-order-42".to_string(), CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 50.0, Currency::USDT,
+        ).unwrap();
+        let retry = processor.process_payment_idempotent(
+            "pos-1-order-42".to_string(), CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 50.0, Currency::USDT,
+        ).unwrap();
+
+        assert_eq!(first.id, retry.id);
+        assert_eq!(processor.get_all_transactions().len(), 1);
+
+        let conflict = processor.process_payment_idempotent(
+            "pos-1-order-42".to_string(), CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 60.0, Currency::USDT,
+        );
+        assert!(matches!(conflict, Err(retailchain::payment::PaymentError::IdempotencyConflict(_))));
+    }
+
+    #[test]
+    fn test_fees_and_settlement_report() {
+        let mut processor = PaymentProcessor::new();
+        processor.set_fee_schedule(
+            FeeSchedule::new(1.0)
+                .with_currency_rule(Currency::USDT, FeeRule::new(0.5, 2.0)),
+        );
+
+        let payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 100.0, Currency::USDT,
+        ).unwrap();
+        assert!((payment.fee - 2.5).abs() < 1e-9);
+        processor.process_payment(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 200.0, Currency::USDT).unwrap();
+        processor.refund_payment(payment.id, 10.0, RefundMethod::OriginalCurrency).unwrap();
+
+        let report = processor.settlement_report(RETAILER_WALLET, chrono::Utc::now().date_naive());
+        let usdt = report.get_line(&Currency::USDT).unwrap();
+        assert_eq!(usdt.payment_count, 2);
+        assert!((usdt.gross - 300.0).abs() < 1e-9);
+        assert!((usdt.fees - 7.0).abs() < 1e-9);
+        assert!((usdt.net - 283.0).abs() < 1e-9);
+
+        // Loại tiền không có quy tắc riêng chỉ chịu phí phần trăm, không bị tính phí cố định của USDT
+        let eth = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 2.0, Currency::ETH,
+        ).unwrap();
+        assert!((eth.fee - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_loyalty_redemption_and_expiry() {
+        use retailchain::loyalty::LedgerEntryKind;
+        use retailchain::payment::PaymentError;
+        use retailchain::models::TransactionStatus;
+        use retailchain::risk::{AmountRule, RiskAction, RiskRules, VelocityRule};
+
+        let mut processor = PaymentProcessor::new();
+        let mut loyalty = LoyaltyProgram::new(1.0, 0.1, 30);
+        let now = chrono::Utc::now();
+        loyalty.earn_points(CUSTOMER_RETAIL_WALLET, &[PurchaseLine::new(None, 500.0)], None, now).unwrap();
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 500);
+
+        let payment = processor.process_payment_with_loyalty(
+            &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 20.0, 100,
+        ).unwrap();
+        assert!((payment.amount - 10.0).abs() < 1e-9);
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 410);
+        assert!(processor.process_payment_with_loyalty(
+            &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 20.0, 10_000,
+        ).is_err());
+        assert_eq!(processor.get_all_transactions().len(), 1);
+
+        // Điểm trả toàn bộ đơn: giao dịch 0 đồng, không cộng điểm và không tính vào tần suất thanh toán
+        processor.set_risk_rules(RiskRules {
+            velocity: Some(VelocityRule { max_payments: 1, window: chrono::Duration::minutes(10), action: RiskAction::Review }),
+            ..RiskRules::default()
+        });
+        let free = processor.process_payment_with_loyalty(
+            &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 20.0, 200,
+        ).unwrap();
+        assert_eq!(free.amount, 0.0);
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 210);
+        assert_eq!(loyalty.get_ledger(CUSTOMER_RETAIL_WALLET).last().unwrap().timestamp, free.timestamp);
+        let next = processor.process_payment(
+            CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 5.0, Currency::RETAIL("RETAIL".to_string()),
+        ).unwrap();
+        assert_eq!(next.status, TransactionStatus::Completed);
+
+        // Địa chỉ bị chặn không đổi được điểm, kể cả khi điểm trả hết đơn
+        let mut rules = RiskRules::default();
+        rules.blocked_addresses.insert(CUSTOMER_RETAIL_WALLET.to_string());
+        processor.set_risk_rules(rules);
+        assert!(matches!(
+            processor.process_payment_with_loyalty(
+                &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 10.0, 100,
+            ),
+            Err(PaymentError::PaymentDenied(_))
+        ));
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 210);
+
+        // Thanh toán chờ duyệt giữ điểm đã đổi nhưng chưa cộng điểm; bị từ chối thì điểm được hoàn lại
+        let mut rules = RiskRules::default();
+        rules.max_amounts.insert(Currency::RETAIL("RETAIL".to_string()), AmountRule { limit: 5.0, action: RiskAction::Review });
+        processor.set_risk_rules(rules);
+        let flagged = processor.process_payment_with_loyalty(
+            &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 20.0, 100,
+        ).unwrap();
+        assert_eq!(flagged.status, TransactionStatus::Pending);
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 110);
+        assert!(matches!(
+            processor.resolve_flagged_payment(flagged.id, false, "manager".to_string()),
+            Err(PaymentError::LoyaltyReviewRequired(_))
+        ));
+        let rejected = processor.resolve_flagged_payment_with_loyalty(
+            &mut loyalty, flagged.id, false, "manager".to_string(),
+        ).unwrap();
+        assert_eq!(rejected.status, TransactionStatus::Failed);
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 210);
+        let reversal = *loyalty.get_ledger(CUSTOMER_RETAIL_WALLET).last().unwrap();
+        assert_eq!((&reversal.kind, reversal.points, reversal.reference), (&LedgerEntryKind::Adjust, 100, Some(flagged.id)));
+
+        // Được chấp nhận thì mới cộng điểm cho phần đã trả
+        let flagged = processor.process_payment_with_loyalty(
+            &mut loyalty, CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 20.0, 100,
+        ).unwrap();
+        processor.resolve_flagged_payment_with_loyalty(&mut loyalty, flagged.id, true, "manager".to_string()).unwrap();
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 120);
+        let earned = *loyalty.get_ledger(CUSTOMER_RETAIL_WALLET).last().unwrap();
+        assert_eq!((&earned.kind, earned.reference), (&LedgerEntryKind::Earn, Some(flagged.id)));
+
+        let expired = loyalty.expire_points(now + chrono::Duration::days(31));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(loyalty.get_balance(CUSTOMER_RETAIL_WALLET), 0);
+        assert_eq!(loyalty.get_ledger(CUSTOMER_RETAIL_WALLET).len(), 9);
+    }
+
+    #[test]
+    fn test_token_mint_transfer_burn() {
+        let mut blockchain = Blockchain::new();
+        let mut tokens = TokenRegistry::new();
+        tokens.register_token("RETAIL".to_string(), "Retail".to_string(), 2, "issuer".to_string()).unwrap();
+
+        assert!(tokens.mint(&mut blockchain, "RETAIL", "someone", "alice", 100).is_err());
+        tokens.mint(&mut blockchain, "RETAIL", "issuer", "alice", 1_000).unwrap();
+        let transfer = tokens.transfer(&mut blockchain, "RETAIL", "alice", "bob", 250).unwrap();
+        assert_eq!(transfer.currency, Currency::RETAIL("RETAIL".to_string()));
+        assert!((transfer.amount - 2.5).abs() < 1e-9);
+        assert!(tokens.transfer(&mut blockchain, "RETAIL", "bob", "alice", 251).is_err());
+        tokens.burn(&mut blockchain, "RETAIL", "bob", 50).unwrap();
+
+        assert_eq!(tokens.balance_of("RETAIL", "alice"), 750);
+        assert_eq!(tokens.balance_of("RETAIL", "bob"), 200);
+        assert_eq!(tokens.total_supply("RETAIL"), Some(950));
+        assert_eq!(blockchain.get_pending_transactions_count(), 3);
+    }
+
+    #[test]
+    fn test_split_tender_is_all_or_nothing() {
+        let mut processor = PaymentProcessor::new();
+        let tenders = vec![
+            Tender::new(CUSTOMER_BTC_WALLET.to_string(), RETAILER_BTC_WALLET.to_string(), 0.001, Currency::BTC),
+            Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 5.0, Currency::USDT),
+            Tender::new("customer".to_string(), "till".to_string(), 10.0, Currency::CASH),
+        ];
+        let transactions = processor.process_order_payment(60.0, &Currency::USDT, tenders).unwrap();
+        assert_eq!(transactions.len(), 3);
+
+        let short = vec![
+            Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 5.0, Currency::USDT),
+            Tender::new("customer".to_string(), "till".to_string(), 10.0, Currency::CASH),
+        ];
+        assert!(processor.process_order_payment(60.0, &Currency::USDT, short).is_err());
+
+        let invalid = vec![
+            Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 59.0, Currency::USDT),
+            Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_BTC_WALLET.to_string(), 1.0, Currency::USDT),
+        ];
+        assert!(processor.process_order_payment(60.0, &Currency::USDT, invalid).is_err());
+        assert_eq!(processor.get_all_transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_payment_request_uris_round_trip() {
+        use retailchain::payment::request::{parse_payment_uri, PaymentRequest};
+
+        let btc = PaymentRequest::new(
+            RETAILER_BTC_WALLET.to_string(), 0.0015, Currency::BTC,
+            "INV 42".to_string(), Some("Shop".to_string()),
+        ).unwrap();
+        assert_eq!(
+            btc.to_uri(),
+            "bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq?amount=0.0015&label=Shop&message=INV%2042"
+        );
+        let parsed = parse_payment_uri(&btc.to_uri()).unwrap();
+        assert_eq!(parsed.reference.as_deref(), Some("INV 42"));
+        assert_eq!(parsed.amount, Some(0.0015));
+
+        let usdt = PaymentRequest::new(
+            CUSTOMER_WALLET.to_string(), 12.5, Currency::USDT,
+            "INV-43".to_string(), None,
+        ).unwrap();
+        assert!(usdt.to_uri().ends_with("/transfer?address=0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed&uint256=12500000"));
+        let parsed = parse_payment_uri(&usdt.to_uri()).unwrap();
+        assert_eq!(parsed.currency, Currency::USDT);
+        assert_eq!(parsed.amount, Some(12.5));
+
+        assert!(usdt.to_qr_svg().unwrap().starts_with("<?xml"));
+        assert!(usdt.to_qr_png().unwrap().starts_with(&[0x89, b'P', b'N', b'G']));
+    }
+
+    #[test]
+    fn test_incoming_payment_reconciles_request() {
+        use retailchain::risk::{AmountRule, RiskAction, RiskRules};
+
+        let mut processor = PaymentProcessor::new();
+        let request = processor.create_payment_request(
+            RETAILER_RETAIL_WALLET.to_string(), 25.0, Currency::RETAIL("RETAIL".to_string()), "ORDER-7".to_string(), None,
+        ).unwrap();
+        let found = processor.find_payment_request_by_uri(&request.to_uri()).unwrap().unwrap();
+        assert_eq!(found.id, request.id);
+
+        let payment = processor.process_payment(
+            CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 25.0, Currency::RETAIL("RETAIL".to_string()),
+        ).unwrap();
+        let paid = processor.reconcile_payment_request(&payment).unwrap();
+        assert_eq!(paid.id, request.id);
+        assert!(processor.get_payment_request(request.id).unwrap().is_paid());
+        assert!(processor.reconcile_payment_request(&payment).is_none());
+
+        // Giao dịch bị gắn cờ chờ duyệt không làm yêu cầu thành đã trả
+        let mut rules = RiskRules::default();
+        rules.max_amounts.insert(Currency::RETAIL("RETAIL".to_string()), AmountRule { limit: 20.0, action: RiskAction::Review });
+        processor.set_risk_rules(rules);
+        let flagged_request = processor.create_payment_request(
+            RETAILER_RETAIL_WALLET.to_string(), 30.0, Currency::RETAIL("RETAIL".to_string()), "ORDER-8".to_string(), None,
+        ).unwrap();
+        let flagged = processor.process_payment(
+            CUSTOMER_RETAIL_WALLET.to_string(), RETAILER_RETAIL_WALLET.to_string(), 30.0, Currency::RETAIL("RETAIL".to_string()),
+        ).unwrap();
+        assert!(processor.reconcile_payment_request(&flagged).is_none());
+        let approved = processor.resolve_flagged_payment(flagged.id, true, "manager".to_string()).unwrap();
+        assert_eq!(processor.reconcile_payment_request(&approved).unwrap().id, flagged_request.id);
+
+        // URI ETH không mang reference: mỗi yêu cầu nhận vào một địa chỉ riêng sinh từ ví
+        let mut wallet = Wallet::generate().unwrap();
+        let first = processor.create_order_payment_request(&mut wallet, 0.01, Currency::ETH, "ORDER-9".to_string(), None).unwrap();
+        let second = processor.create_order_payment_request(&mut wallet, 0.01, Currency::ETH, "ORDER-10".to_string(), None).unwrap();
+        assert_ne!(first.address, second.address);
+        assert_eq!(processor.find_payment_request_by_uri(&second.to_uri()).unwrap().unwrap().reference, "ORDER-10");
+        assert!(matches!(
+            processor.create_payment_request(first.address.clone(), 0.02, Currency::ETH, "ORDER-11".to_string(), None),
+            Err(retailchain::payment::PaymentError::AddressInUse(_))
+        ));
+    }
+
+    #[test]
+    fn test_wallet_hd_derivation_vectors() {
+        // Seed BIP39 của mnemonic "abandon abandon ... about" (test vector BIP84/BIP44)
+        let seed = hex::decode(
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc1\
+             9a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        ).unwrap();
+        let mut wallet = Wallet::from_seed(&seed).unwrap();
+
+        let btc = wallet.new_order_address("ORDER-1", &Currency::BTC).unwrap();
+        assert_eq!(btc.address, "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(btc.path, "m/84'/0'/0'/0/0");
+        let eth = wallet.new_order_address("ORDER-1", &Currency::ETH).unwrap();
+        assert_eq!(eth.address, "0x9858EfFD232B4033E47d90003D41EC34EcaEda94");
+
+        let next = wallet.new_order_address("ORDER-2", &Currency::BTC).unwrap();
+        assert_ne!(next.address, btc.address);
+        assert_eq!(wallet.get_order_for_address(&next.address), Some("ORDER-2"));
+        assert_eq!(wallet.get_order_addresses("ORDER-1").len(), 2);
+    }
+
+    #[test]
+    fn test_signed_payment_verifies() {
+        use retailchain::wallet::verify_transaction_signature;
+
+        let mut wallet = Wallet::generate().unwrap();
+        let mut processor = PaymentProcessor::new();
+        let from = wallet.derive_address(&Currency::USDT).unwrap().address;
+
+        let mut transaction = processor.process_signed_payment(
+            &wallet, from, RETURNING_CUSTOMER_WALLET.to_string(), 250.0, Currency::USDT,
+        ).unwrap();
+        assert!(verify_transaction_signature(&transaction));
+
+        transaction.amount = 260.0;
+        assert!(!verify_transaction_signature(&transaction));
+        assert!(processor.process_signed_payment(
+            &wallet, CUSTOMER_WALLET.to_string(), RETURNING_CUSTOMER_WALLET.to_string(), 1.0, Currency::USDT,
+        ).is_err());
+    }
+
+    fn temp_keystore_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("retailchain-keystore-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_keystore_unlock_lock_and_change_password() {
+        use retailchain::wallet::keystore::{Keystore, KeystoreError};
+
+        let path = temp_keystore_path();
+        let mut wallet = Wallet::generate().unwrap();
+        let address = wallet.new_order_address("ORDER-1", &Currency::ETH).unwrap().address;
+        let mut keystore = Keystore::create_with_iterations(&path, wallet, "correct horse", 1_000).unwrap();
+        keystore.lock();
+        assert!(matches!(keystore.wallet(), Err(KeystoreError::Locked)));
+
+        let mut reopened = Keystore::open(&path).unwrap();
+        assert!(matches!(reopened.unlock("wrong password"), Err(KeystoreError::WrongPassword)));
+        let wallet = reopened.unlock("correct horse").unwrap();
+        assert_eq!(wallet.get_order_for_address(&address), Some("ORDER-1"));
+
+        // Địa chỉ cấp sau khi mở khóa phải được lưu lại và không trùng địa chỉ cũ
+        let next = reopened.wallet_mut().unwrap().new_order_address("ORDER-2", &Currency::ETH).unwrap();
+        assert_ne!(next.address, address);
+        reopened.save().unwrap();
+
+        reopened.change_password("correct horse", "battery staple").unwrap();
+        let mut again = Keystore::open(&path).unwrap();
+        assert!(matches!(again.unlock("correct horse"), Err(KeystoreError::WrongPassword)));
+        let wallet = again.unlock("battery staple").unwrap();
+        assert_eq!(wallet.get_order_for_address(&next.address), Some("ORDER-2"));
+        assert!(matches!(again.change_password("wrong password", "another one"), Err(KeystoreError::WrongPassword)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keystore_detects_corrupted_files() {
+        use retailchain::wallet::keystore::{Keystore, KeystoreError};
+
+        let path = temp_keystore_path();
+        Keystore::create_with_iterations(&path, Wallet::generate().unwrap(), "correct horse", 1_000).unwrap();
+        assert!(matches!(
+            Keystore::create_with_iterations(&path, Wallet::generate().unwrap(), "correct horse", 1_000),
+            Err(KeystoreError::AlreadyExists(_))
+        ));
+
+        let mut contents: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let ciphertext = contents["ciphertext"].as_str().unwrap().to_string();
+        let flipped = if ciphertext.starts_with('0') { "1" } else { "0" };
+        contents["ciphertext"] = json!(format!("{}{}", flipped, &ciphertext[1..]));
+        std::fs::write(&path, contents.to_string()).unwrap();
+        let mut tampered = Keystore::open(&path).unwrap();
+        assert!(matches!(tampered.unlock("correct horse"), Err(KeystoreError::Corrupted(_))));
+
+        std::fs::write(&path, "{\"version\": 1, \"kdf\":").unwrap();
+        assert!(matches!(Keystore::open(&path), Err(KeystoreError::Corrupted(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_address_validation_per_currency() {
+        use retailchain::payment::{PaymentError, address::{validate_address, AddressError}};
+
+        assert!(validate_address(CUSTOMER_BTC_WALLET, &Currency::BTC).is_ok());
+        assert!(validate_address("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", &Currency::BTC).is_ok());
+        assert!(validate_address(RETAILER_BTC_WALLET, &Currency::BTC).is_ok());
+        assert!(validate_address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3", &Currency::BTC).is_err());
+        assert!(validate_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdx", &Currency::BTC).is_err());
+
+        assert!(validate_address(CUSTOMER_WALLET, &Currency::USDT).is_ok());
+        assert!(validate_address(&CUSTOMER_WALLET.to_lowercase(), &Currency::ETH).is_ok());
+        assert_eq!(
+            validate_address("0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed", &Currency::ETH),
+            Err(AddressError::ChecksumMismatch)
+        );
+        assert!(validate_address(CUSTOMER_RETAIL_WALLET, &Currency::RETAIL("RETAIL".to_string())).is_ok());
+        assert!(validate_address(CUSTOMER_WALLET, &Currency::RETAIL("RETAIL".to_string())).is_err());
+
+        let mut processor = PaymentProcessor::new();
+        let result = processor.process_payment(
+            "not-a-wallet".to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::InvalidSenderAddress(_))));
+        let result = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_BTC_WALLET.to_string(), 10.0, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::InvalidRecipientAddress(_))));
+    }
+
+    #[test]
+    fn test_risk_rules_review_and_deny() {
+        use chrono::{Duration, FixedOffset, TimeZone, Utc};
+        use retailchain::models::TransactionStatus;
+        use retailchain::payment::PaymentError;
+        use retailchain::risk::{
+            AmountRule, PaymentContext, RiskAction, RiskEngine, RiskReason, RiskRules, UnusualHoursRule, VelocityRule,
+        };
+
+        let mut rules = RiskRules {
+            velocity: Some(VelocityRule { max_payments: 2, window: Duration::minutes(10), action: RiskAction::Review }),
+            ..RiskRules::default()
+        };
+        rules.max_amounts.insert(Currency::ETH, AmountRule { limit: 500.0, action: RiskAction::Deny });
+        rules.blocked_addresses.insert(RETURNING_CUSTOMER_WALLET.to_string());
+
+        let mut processor = PaymentProcessor::new();
+        processor.set_risk_rules(rules.clone());
+        for _ in 0..2 {
+            let tx = processor.process_payment(
+                CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::ETH,
+            ).unwrap();
+            assert_eq!(tx.status, TransactionStatus::Completed);
+        }
+
+        // Lần thứ ba trong 10 phút bị giữ lại để xem xét
+        let flagged = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::ETH,
+        ).unwrap();
+        assert_eq!(flagged.status, TransactionStatus::Pending);
+        let queue = processor.get_review_queue();
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(queue[0].reasons[0], RiskReason::VelocityExceeded { count: 3, .. }));
+
+        let approved = processor.resolve_flagged_payment(flagged.id, true, "manager".to_string()).unwrap();
+        assert_eq!(approved.status, TransactionStatus::Completed);
+        assert!(processor.get_review_queue().is_empty());
+        assert!(matches!(
+            processor.resolve_flagged_payment(flagged.id, false, "manager".to_string()),
+            Err(PaymentError::ReviewNotFound)
+        ));
+
+        let result = processor.process_payment(
+            RETURNING_CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::PaymentDenied(ref reasons))
+            if reasons == &vec![RiskReason::BlockedAddress(RETURNING_CUSTOMER_WALLET.to_string())]));
+        let result = processor.process_payment(
+            RETAILER_WALLET.to_string(), CUSTOMER_WALLET.to_string(), 600.0, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::PaymentDenied(_))));
+
+        // Nhiều hình thức thanh toán của cùng một đơn từ một địa chỉ cũng bị tính tần suất
+        let mut processor = PaymentProcessor::new();
+        processor.set_risk_rules(rules.clone());
+        let tenders = (0..3)
+            .map(|_| Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 10.0, Currency::USDT))
+            .collect();
+        let paid = processor.process_order_payment(30.0, &Currency::USDT, tenders).unwrap();
+        assert_eq!(paid[1].status, TransactionStatus::Completed);
+        assert_eq!(paid[2].status, TransactionStatus::Pending);
+
+        // Giờ bất thường tính theo múi giờ cửa hàng (UTC+7): 01:30 giờ địa phương
+        rules.velocity = None;
+        rules.unusual_hours = Some(UnusualHoursRule {
+            start_hour: 23,
+            end_hour: 5,
+            timezone: FixedOffset::east_opt(7 * 3600).unwrap(),
+            action: RiskAction::Review,
+        });
+        let engine = RiskEngine::new(rules);
+        let context = |timestamp| PaymentContext {
+            from_address: CUSTOMER_WALLET,
+            to_address: RETAILER_WALLET,
+            amount: 10.0,
+            currency: &Currency::ETH,
+            timestamp,
+        };
+        let night = engine.evaluate(&context(Utc.with_ymd_and_hms(2024, 3, 1, 18, 30, 0).unwrap()));
+        assert_eq!(night.action, RiskAction::Review);
+        assert_eq!(night.reasons, vec![RiskReason::UnusualHour(1)]);
+        let day = engine.evaluate(&context(Utc.with_ymd_and_hms(2024, 3, 1, 3, 0, 0).unwrap()));
+        assert_eq!(day.action, RiskAction::Allow);
+    }
+
+    #[test]
+    fn test_spending_limits_per_address() {
+        use retailchain::payment::{PaymentError, limits::SpendingLimit};
+
+        let mut processor = PaymentProcessor::new();
+        processor.set_spending_limit(
+            CUSTOMER_WALLET.to_string(),
+            Currency::ETH,
+            SpendingLimit::new(Currency::USDT).with_per_transaction(900.0).with_daily_cap(1500.0),
+        );
+        processor.set_spending_limit(
+            CUSTOMER_WALLET.to_string(),
+            Currency::USDT,
+            SpendingLimit::new(Currency::CASH).with_monthly_cap(50.0),
+        );
+
+        let first = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.2, Currency::ETH,
+        ).unwrap();
+        let result = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.4, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::TransactionLimitExceeded { limit, attempted, .. })
+            if limit == 900.0 && (attempted - 1200.0).abs() < 1e-6));
+
+        processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.25, Currency::ETH,
+        ).unwrap();
+        let result = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.1, Currency::ETH,
+        );
+        assert!(matches!(result, Err(PaymentError::DailyLimitExceeded { spent, .. }) if (spent - 1350.0).abs() < 1e-6));
+
+        // Phần đã hoàn trả được trả lại vào hạn mức; địa chỉ khác không bị giới hạn
+        processor.refund_payment(first.id, 0.2, RefundMethod::OriginalCurrency).unwrap();
+        assert!(processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.1, Currency::ETH,
+        ).is_ok());
+        assert!(processor.process_payment(
+            RETURNING_CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.4, Currency::ETH,
+        ).is_ok());
+
+        processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 30.0, Currency::USDT,
+        ).unwrap();
+        let result = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 30.0, Currency::USDT,
+        );
+        assert!(matches!(result, Err(PaymentError::MonthlyLimitExceeded { limit, .. }) if limit == 50.0));
+
+        // Phần đã chi được tính theo tỷ giá lúc giao dịch, không theo tỷ giá hôm nay
+        let mut processor = PaymentProcessor::new();
+        processor.set_spending_limit(
+            CUSTOMER_WALLET.to_string(),
+            Currency::ETH,
+            SpendingLimit::new(Currency::USDT).with_daily_cap(1000.0),
+        );
+        processor.process_payment(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.3, Currency::ETH).unwrap();
+        processor.set_exchange_rate(Currency::ETH, 6000.0).unwrap();
+        processor.process_payment(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.01, Currency::ETH).unwrap();
+
+        // Các hình thức thanh toán của cùng đơn cộng dồn vào hạn mức: 960 + 30 + 30 > 1000
+        let tenders = (0..2)
+            .map(|_| Tender::new(CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.005, Currency::ETH))
+            .collect();
+        let result = processor.process_order_payment(60.0, &Currency::USDT, tenders);
+        assert!(matches!(result, Err(PaymentError::DailyLimitExceeded { spent, .. }) if (spent - 990.0).abs() < 1e-6));
+        assert_eq!(processor.get_all_transactions().len(), 2);
+    }
+
+    #[test]
+    fn test_transaction_search_with_pagination() {
+        use retailchain::models::TransactionStatus;
+        use retailchain::payment::{PaymentError, query::TransactionQuery};
+
+        let mut processor = PaymentProcessor::new();
+        let mut expected = Vec::new();
+        for amount in [1.0, 2.0, 3.0, 4.0, 5.0] {
+            let tx = processor.process_payment(
+                CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), amount, Currency::ETH,
+            ).unwrap();
+            expected.push(tx.id);
+        }
+        processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 3.0, Currency::USDT,
+        ).unwrap();
+        processor.process_payment(
+            RETURNING_CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 3.0, Currency::ETH,
+        ).unwrap();
+        processor.refund_payment(expected[4], 5.0, RefundMethod::OriginalCurrency).unwrap();
+
+        let mut query = TransactionQuery::new()
+            .with_address(CUSTOMER_WALLET.to_string())
+            .with_currency(Currency::ETH)
+            .with_amount_range(None, Some(4.5))
+            .with_limit(2);
+        let mut ids = Vec::new();
+        loop {
+            let page = processor.search_transactions(&query).unwrap();
+            assert!(page.transactions.len() <= 2);
+            ids.extend(page.transactions.iter().map(|tx| tx.id));
+            match page.next_cursor {
+                Some(cursor) => query = query.with_cursor(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(ids, expected[..4]);
+
+        // Giao dịch gốc đã hoàn tiền mang trạng thái Refunded
+        let refunded = processor.search_transactions(
+            &TransactionQuery::new().with_address(CUSTOMER_WALLET.to_string()).with_status(TransactionStatus::Refunded),
+        ).unwrap();
+        assert_eq!(refunded.transactions.len(), 1);
+        assert_eq!(refunded.transactions[0].id, expected[4]);
+
+        // Giao dịch hoàn tiền có địa chỉ nhận là khách hàng và trỏ về giao dịch gốc
+        let customer = processor.search_transactions(
+            &TransactionQuery::new().with_address(CUSTOMER_WALLET.to_string()),
+        ).unwrap();
+        let refunds: Vec<_> = customer.transactions.iter().filter(|tx| tx.refund_of.is_some()).collect();
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].refund_of, Some(expected[4]));
+        assert_eq!(refunds[0].to_address, CUSTOMER_WALLET);
+        let page = processor.search_transactions(
+            &TransactionQuery::new().with_amount_range(Some(3.0), Some(3.0)),
+        ).unwrap();
+        assert_eq!(page.transactions.len(), 3);
+        let page = processor.search_transactions(
+            &TransactionQuery::new().with_date_range(Some(chrono::Utc::now()), None),
+        ).unwrap();
+        assert!(page.transactions.is_empty());
+
+        let result = processor.search_transactions(&TransactionQuery::new().with_cursor("garbage".to_string()));
+        assert!(matches!(result, Err(PaymentError::InvalidCursor)));
+        let result = processor.search_transactions(&TransactionQuery::new().with_limit(0));
+        assert!(matches!(result, Err(PaymentError::InvalidPageSize(0))));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_search_api() {
+        use retailchain::api::transaction_routes;
+        use std::sync::{Arc, Mutex};
+
+        let processor = Arc::new(Mutex::new(PaymentProcessor::new()));
+        for amount in [1.0, 2.0, 3.0] {
+            processor.lock().unwrap().process_payment(
+                CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), amount, Currency::ETH,
+            ).unwrap();
+        }
+        processor.lock().unwrap().process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 1.0, Currency::USDT,
+        ).unwrap();
+        let routes = transaction_routes(processor);
+
+        let response = warp::test::request()
+            .path("/api/transactions?currency=ETH&status=Completed&limit=2")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["transactions"].as_array().unwrap().len(), 2);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+        let response = warp::test::request()
+            .path(&format!("/api/transactions?currency=ETH&limit=2&cursor={}", cursor))
+            .reply(&routes)
+            .await;
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["transactions"][0]["amount"], 3.0);
+        assert!(page["next_cursor"].is_null());
+
+        let response = warp::test::request()
+            .path("/api/transactions?since=2020-01-01T00:00:00Z&min_amount=1.5")
+            .reply(&routes)
+            .await;
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["transactions"].as_array().unwrap().len(), 2);
+
+        let response = warp::test::request()
+            .path("/api/transactions?cursor=bogus")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        // Ký hiệu không phân biệt hoa thường; ký hiệu không biết là lỗi chứ không phải trang rỗng
+        let response = warp::test::request()
+            .path("/api/transactions?currency=usdt")
+            .reply(&routes)
+            .await;
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["transactions"].as_array().unwrap().len(), 1);
+        for currency in ["btcx", "retailx"] {
+            let response = warp::test::request()
+                .path(&format!("/api/transactions?currency={}", currency))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 400);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["error"], format!("Unknown currency {}", currency));
+        }
+        let response = warp::test::request()
+            .path("/api/transactions?currency=retail")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn test_accounting_export_csv_and_journal() {
+        use retailchain::payment::export::{EntryKind, PAYMENT_FEES_ACCOUNT, SALES_ACCOUNT, SALES_RETURNS_ACCOUNT};
+
+        let mut processor = PaymentProcessor::new();
+        processor.set_fee_schedule(FeeSchedule::new(1.0));
+        let eth_payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 0.5, Currency::ETH,
+        ).unwrap();
+        processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 100.0, Currency::USDT,
+        ).unwrap();
+        // Giao dịch không liên quan tới cửa hàng thì không được xuất
+        processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETURNING_CUSTOMER_WALLET.to_string(), 5.0, Currency::USDT,
+        ).unwrap();
+        processor.set_exchange_rate(Currency::ETH, 3200.0).unwrap();
+        processor.refund_payment(eth_payment.id, 0.1, RefundMethod::OriginalCurrency).unwrap();
+
+        let today = chrono::Utc::now().date_naive();
+        let export = processor.accounting_export(RETAILER_WALLET, today, today);
+        assert_eq!(export.rows.len(), 3);
+        let refund = export.rows.iter().find(|row| row.kind == EntryKind::Refund).unwrap();
+        assert_eq!(refund.refund_of, Some(eth_payment.id));
+        assert!((refund.fiat_amount - 320.0).abs() < 1e-6);
+
+        let csv = export.to_csv();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("timestamp,transaction_id,type,"));
+        assert_eq!(lines.count(), 3);
+        assert!(csv.contains(&format!("{},Payment,", eth_payment.id)));
+        assert!(csv.contains(",ETH,0.5,0.005,3000,USD,1500.00,15.00,"));
+
+        let journal = export.journal_lines();
+        for row in &export.rows {
+            let entry: Vec<_> = journal.iter().filter(|line| line.transaction_id == row.transaction_id).collect();
+            let debit: f64 = entry.iter().map(|line| line.debit).sum();
+            let credit: f64 = entry.iter().map(|line| line.credit).sum();
+            let fiat_debit: f64 = entry.iter().map(|line| line.fiat_debit).sum();
+            let fiat_credit: f64 = entry.iter().map(|line| line.fiat_credit).sum();
+            assert!((debit - credit).abs() < 1e-9);
+            assert!((fiat_debit - fiat_credit).abs() < 1e-6);
+        }
+        let eth_entry: Vec<_> = journal.iter().filter(|line| line.transaction_id == eth_payment.id).collect();
+        assert_eq!(eth_entry.len(), 3);
+        assert!(eth_entry.iter().any(|line| line.account == "Assets:Crypto:ETH" && (line.debit - 0.495).abs() < 1e-9));
+        assert!(eth_entry.iter().any(|line| line.account == PAYMENT_FEES_ACCOUNT && (line.fiat_debit - 15.0).abs() < 1e-6));
+        assert!(eth_entry.iter().any(|line| line.account == SALES_ACCOUNT && (line.fiat_credit - 1500.0).abs() < 1e-6));
+        assert!(journal.iter().any(|line| line.account == SALES_RETURNS_ACCOUNT));
+        assert!(export.journal_to_csv().lines().next().unwrap().starts_with("date,transaction_id,account,currency,debit,credit"));
+
+        let yesterday = today.pred_opt().unwrap();
+        assert!(processor.accounting_export(RETAILER_WALLET, yesterday, yesterday).rows.is_empty());
+    }
+
+    #[test]
+    fn test_scheduled_payments_with_manual_clock() {
+        use chrono::{Duration, NaiveTime, TimeZone, Utc, Weekday};
+        use retailchain::models::TransactionStatus;
+        use retailchain::payment::limits::SpendingLimit;
+        use retailchain::risk::{AmountRule, RiskAction, RiskRules};
+        use retailchain::PaymentScheduler;
+        use retailchain::scheduler::{
+            cron::CronSchedule, Clock, ManualClock, RetryPolicy, RunOutcome, Schedule, ScheduleStatus, SchedulerError,
+        };
+        use std::sync::Arc;
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 31, 9, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut scheduler = PaymentScheduler::with_clock(clock.clone());
+        let mut processor = PaymentProcessor::new();
+        let ten_am = NaiveTime::from_hms_opt(10, 0, 0).unwrap();
+
+        let rent = scheduler.schedule_payment(
+            "Rent".to_string(),
+            Tender::new(RETAILER_WALLET.to_string(), CUSTOMER_WALLET.to_string(), 500.0, Currency::USDT),
+            Schedule::Monthly { day: 31, time: ten_am },
+            RetryPolicy::default(),
+        ).unwrap();
+        assert_eq!(scheduler.get_schedule(rent).unwrap().next_run, Some(start + Duration::hours(1)));
+        assert!(scheduler.run_due(&mut processor).is_empty());
+
+        // Các lần bị lỡ được trả bù; tháng 2 dời về ngày cuối tháng. Mỗi lần được tính hạn mức
+        // theo ngày đến hạn của nó nên ba lần trả bù không dồn vào cùng một ngày
+        processor.set_spending_limit(
+            RETAILER_WALLET.to_string(),
+            Currency::USDT,
+            SpendingLimit::new(Currency::USDT).with_daily_cap(800.0),
+        );
+        clock.set(Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap());
+        let runs = scheduler.run_due(&mut processor);
+        let due: Vec<_> = runs.iter().map(|run| run.due_at.format("%Y-%m-%d").to_string()).collect();
+        assert_eq!(due, ["2024-01-31", "2024-02-29", "2024-03-31"]);
+        assert!(runs.iter().all(|run| matches!(run.outcome, RunOutcome::Paid(_))));
+        assert_eq!(processor.get_all_transactions().len(), 3);
+        for run in &runs {
+            let RunOutcome::Paid(id) = run.outcome else { unreachable!() };
+            assert_eq!(processor.get_transaction(id).unwrap().timestamp, run.due_at);
+        }
+        assert_eq!(
+            scheduler.get_schedule(rent).unwrap().next_run,
+            Some(Utc.with_ymd_and_hms(2024, 4, 30, 10, 0, 0).unwrap())
+        );
+
+        // Thất bại thì thử lại sau retry_delay, thành công khi lỗi được khắc phục
+        let mut rules = RiskRules::default();
+        rules.blocked_addresses.insert(RETURNING_CUSTOMER_WALLET.to_string());
+        processor.set_risk_rules(rules);
+        let retainer = scheduler.schedule_payment(
+            "Supplier retainer".to_string(),
+            Tender::new(RETAILER_WALLET.to_string(), RETURNING_CUSTOMER_WALLET.to_string(), 50.0, Currency::USDT),
+            Schedule::Once(clock.now()),
+            RetryPolicy::new(3, Duration::minutes(15)),
+        ).unwrap();
+        let runs = scheduler.run_due(&mut processor);
+        assert!(matches!(runs[..], [ref run] if matches!(run.outcome, RunOutcome::Failed(_))));
+        assert_eq!(scheduler.get_retrying_schedules().len(), 1);
+        clock.advance(Duration::minutes(10));
+        assert!(scheduler.run_due(&mut processor).is_empty());
+        clock.advance(Duration::minutes(5));
+        assert_eq!(scheduler.run_due(&mut processor)[0].attempt, 2);
+        processor.set_risk_rules(RiskRules::default());
+        clock.advance(Duration::minutes(15));
+        let runs = scheduler.run_due(&mut processor);
+        assert!(matches!(runs[0].outcome, RunOutcome::Paid(_)));
+        let retainer = scheduler.get_schedule(retainer).unwrap();
+        assert_eq!(retainer.status, ScheduleStatus::Completed);
+        assert_eq!(retainer.failed_runs().count(), 2);
+
+        let oversized = scheduler.schedule_payment(
+            "Contractor".to_string(),
+            Tender::new(RETAILER_WALLET.to_string(), CUSTOMER_WALLET.to_string(), 5000.0, Currency::USDT),
+            Schedule::Once(clock.now()),
+            RetryPolicy::new(2, Duration::minutes(1)),
+        ).unwrap();
+        scheduler.run_due(&mut processor);
+        clock.advance(Duration::minutes(1));
+        scheduler.run_due(&mut processor);
+        assert_eq!(scheduler.get_schedule(oversized).unwrap().status, ScheduleStatus::Failed);
+
+        // Giao dịch bị giữ lại chờ duyệt không được báo là đã trả
+        let mut rules = RiskRules::default();
+        rules.max_amounts.insert(Currency::USDT, AmountRule { limit: 100.0, action: RiskAction::Review });
+        processor.set_risk_rules(rules);
+        scheduler.schedule_payment(
+            "Equipment".to_string(),
+            Tender::new(RETAILER_WALLET.to_string(), CUSTOMER_WALLET.to_string(), 200.0, Currency::USDT),
+            Schedule::Once(clock.now()),
+            RetryPolicy::default(),
+        ).unwrap();
+        let runs = scheduler.run_due(&mut processor);
+        let RunOutcome::PendingReview(id) = runs[0].outcome else { panic!("expected pending review") };
+        assert_eq!(processor.get_transaction(id).unwrap().status, TransactionStatus::Pending);
+        processor.set_risk_rules(RiskRules::default());
+
+        scheduler.cancel(rent).unwrap();
+        assert!(matches!(scheduler.cancel(rent), Err(SchedulerError::NotActive)));
+        clock.advance(Duration::days(60));
+        assert!(scheduler.run_due(&mut processor).is_empty());
+
+        let friday_evening = Utc.with_ymd_and_hms(2024, 2, 2, 17, 50, 0).unwrap();
+        let weekdays = Schedule::Cron(CronSchedule::parse("*/15 9-17 * * 1-5").unwrap());
+        assert_eq!(weekdays.next_after(friday_evening), Some(Utc.with_ymd_and_hms(2024, 2, 5, 9, 0, 0).unwrap()));
+        let leap_day = Schedule::Cron(CronSchedule::parse("0 0 29 2 *").unwrap());
+        assert_eq!(leap_day.next_after(start + Duration::days(30)), Some(Utc.with_ymd_and_hms(2028, 2, 29, 0, 0, 0).unwrap()));
+        let weekly = Schedule::Weekly { weekday: Weekday::Mon, time: ten_am };
+        assert_eq!(weekly.next_after(start), Some(Utc.with_ymd_and_hms(2024, 2, 5, 10, 0, 0).unwrap()));
+        assert!(matches!(CronSchedule::parse("61 * * * *"), Err(SchedulerError::InvalidCron(_))));
+        assert!(matches!(CronSchedule::parse("0 9 * *"), Err(SchedulerError::InvalidCron(_))));
+    }
+
+    // Tệp SQLite tạm cho test; được xóa khi ra khỏi phạm vi, kể cả khi test thất bại
+    struct TempDatabase {
+        path: std::path::PathBuf,
+    }
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("retailchain-{}-{}.db", name, uuid::Uuid::new_v4()));
+            Self { path }
+        }
+
+        fn open(&self) -> retailchain::inventory::sqlite::SqliteStore {
+            retailchain::inventory::sqlite::SqliteStore::open(&self.path).unwrap()
+        }
+
+        fn open_inventory(&self, low_stock_threshold: u32) -> InventoryManager {
+            InventoryManager::with_store(low_stock_threshold, Box::new(self.open())).unwrap()
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn test_inventory_persists_in_sqlite() {
+        use retailchain::inventory::{InventoryError, sqlite::SCHEMA_VERSION, storage::{InMemoryStore, InventoryStore, StorageError, StoreChange}};
+
+        let db = TempDatabase::new("inventory");
+        let product = {
+            let store = db.open();
+            assert_eq!(store.schema_version().unwrap(), SCHEMA_VERSION);
+            let mut inventory = InventoryManager::with_store(10, Box::new(store)).unwrap();
+            let product = inventory.add_product(
+                "Charger".to_string(), "CHG-20W".to_string(), "USB-C".to_string(), 19.0, 8, "Acme".to_string(),
+            ).unwrap();
+            inventory.sell_product(product.id, DEFAULT_LOCATION, 3, product.price, STORE_CLERK, None).unwrap();
+            assert!(matches!(
+                inventory.sell_product(product.id, DEFAULT_LOCATION, 6, product.price, STORE_CLERK, None),
+                Err(InventoryError::InsufficientStock)
+            ));
+            product
+        };
+
+        // Mở lại file: dữ liệu còn nguyên, migration không chạy lại
+        let store = db.open();
+        assert_eq!(store.schema_version().unwrap(), SCHEMA_VERSION);
+        let mut inventory = InventoryManager::with_store(10, Box::new(store)).unwrap();
+        let reloaded = inventory.get_product(product.id).unwrap();
+        assert_eq!(reloaded.quantity, 5);
+        assert_eq!(reloaded.sku, "CHG-20W");
+        assert_eq!(reloaded.created_at, product.created_at);
+        inventory.restock_product(product.id, DEFAULT_LOCATION, 2, None, STORE_CLERK).unwrap();
+
+        // Một thay đổi lỗi trong batch làm rollback cả batch
+        let mut store = db.open();
+        let result = store.apply(&[
+            StoreChange::UpdateQuantity { product_id: product.id, expected: 7, quantity: 0 },
+            StoreChange::UpdateQuantity { product_id: product.id, expected: 7, quantity: 1 },
+        ]);
+        assert!(matches!(result, Err(StorageError::Conflict(id)) if id == product.id));
+        assert_eq!(store.load_products().unwrap()[0].quantity, 7);
+
+        // Ghi đồng thời từ nơi khác: bộ đệm cũ bị từ chối cho tới khi nạp lại
+        store.apply(&[StoreChange::UpdateQuantity { product_id: product.id, expected: 7, quantity: 4 }]).unwrap();
+        assert!(matches!(
+            inventory.sell_product(product.id, DEFAULT_LOCATION, 1, product.price, STORE_CLERK, None),
+            Err(InventoryError::Storage(StorageError::Conflict(_)))
+        ));
+        assert_eq!(inventory.get_product(product.id).unwrap().quantity, 7);
+        inventory.reload().unwrap();
+        inventory.sell_product(product.id, DEFAULT_LOCATION, 1, product.price, STORE_CLERK, None).unwrap();
+        assert_eq!(inventory.get_product(product.id).unwrap().quantity, 3);
+
+        // Kho trong bộ nhớ cũng hoàn tác mọi thay đổi trước đó của batch lỗi
+        let mut memory = InMemoryStore::new();
+        let stored = store.load_products().unwrap().remove(0);
+        let movement = store.load_movements(product.id).unwrap().remove(0);
+        memory.apply(&[StoreChange::InsertProduct(stored.clone()), StoreChange::AppendMovement(movement.clone())]).unwrap();
+        let result = memory.apply(&[
+            StoreChange::UpdateQuantity { product_id: product.id, expected: stored.quantity, quantity: 0 },
+            StoreChange::SetStockLevel {
+                product_id: product.id, location_id: DEFAULT_LOCATION.to_string(), expected: 0, quantity: 9,
+            },
+            StoreChange::AppendMovement(movement),
+        ]);
+        assert!(matches!(result, Err(StorageError::Constraint(_))));
+        assert_eq!(memory.load_products().unwrap()[0].quantity, stored.quantity);
+        assert!(memory.load_stock_levels().unwrap().is_empty());
+        assert_eq!(memory.load_movements(product.id).unwrap().len(), 1);
+
+        drop(inventory);
+        drop(store);
+    }
+
+    #[test]
+    fn test_sku_uniqueness_and_barcode_lookup() {
+        use retailchain::inventory::{InventoryError, barcode::normalize_barcode, sqlite::SqliteStore};
+
+        assert_eq!(normalize_barcode("4006381333931").unwrap(), "4006381333931");
+        assert_eq!(normalize_barcode("036000291452").unwrap(), "0036000291452");
+        assert!(matches!(normalize_barcode("4006381333932"), Err(InventoryError::InvalidBarcode(_))));
+        assert!(normalize_barcode("40063813339").is_err());
+        assert!(normalize_barcode("40063813339X1").is_err());
+
+        let mut inventory = InventoryManager::with_store(5, Box::new(SqliteStore::open_in_memory().unwrap())).unwrap();
+        let pen = inventory.add_product(
+            "Pen".to_string(), " PEN-01 ".to_string(), String::new(), 1.5, 100, "Stabilo".to_string(),
+        ).unwrap();
+        assert_eq!(pen.sku, "PEN-01");
+        let duplicate = inventory.add_product(
+            "Other pen".to_string(), "pen-01".to_string(), String::new(), 2.0, 10, "Bic".to_string(),
+        );
+        assert!(matches!(duplicate, Err(InventoryError::DuplicateSku(ref sku)) if sku == "pen-01"));
+        assert!(matches!(
+            inventory.add_product("Blank".to_string(), "  ".to_string(), String::new(), 1.0, 1, String::new()),
+            Err(InventoryError::InvalidSku)
+        ));
+        let ink = inventory.add_product(
+            "Ink".to_string(), "INK-01".to_string(), String::new(), 3.0, 20, "Stabilo".to_string(),
+        ).unwrap();
+
+        // UPC-A và EAN-13 tương ứng cùng trỏ tới một sản phẩm
+        assert_eq!(inventory.add_barcode(pen.id, "036000291452").unwrap(), "0036000291452");
+        inventory.add_barcode(pen.id, "4006381333931").unwrap();
+        assert_eq!(inventory.get_product_by_barcode("0036000291452").unwrap().id, pen.id);
+        assert_eq!(inventory.get_product_by_barcode("036000291452").unwrap().id, pen.id);
+        assert_eq!(inventory.get_product_by_sku("pen-01").unwrap().id, pen.id);
+        assert!(matches!(inventory.add_barcode(ink.id, "4006381333931"), Err(InventoryError::DuplicateBarcode(_))));
+        assert!(inventory.get_product_by_barcode("4006381333932").is_none());
+
+        // Sửa SKU và mã vạch cập nhật chỉ mục, kể cả sau khi nạp lại từ SQLite
+        assert!(matches!(inventory.update_sku(ink.id, "Pen-01".to_string()), Err(InventoryError::DuplicateSku(_))));
+        inventory.update_sku(pen.id, "PEN-BLUE".to_string()).unwrap();
+        inventory.remove_barcode(pen.id, "4006381333931").unwrap();
+        inventory.add_barcode(ink.id, "4006381333931").unwrap();
+        inventory.reload().unwrap();
+        assert!(inventory.get_product_by_sku("PEN-01").is_none());
+        assert_eq!(inventory.get_product_by_sku("pen-blue").unwrap().id, pen.id);
+        assert_eq!(inventory.get_product_by_barcode("4006381333931").unwrap().id, ink.id);
+        assert_eq!(inventory.get_product(pen.id).unwrap().barcodes, vec!["0036000291452".to_string()]);
+        assert!(matches!(inventory.remove_barcode(pen.id, "4006381333931"), Err(InventoryError::BarcodeNotFound(_))));
+        inventory.add_product("Ink refill".to_string(), "PEN-01".to_string(), String::new(), 1.0, 1, String::new()).unwrap();
+    }
+
+    #[test]
+    fn test_stock_per_location_and_transfers() {
+        use retailchain::inventory::{InventoryError, storage::{InventoryStore, StoreChange}};
+
+        let db = TempDatabase::new("locations");
+        let mut inventory = db.open_inventory(5);
+        inventory.add_location(Location::new("WH".to_string(), "Warehouse".to_string(), LocationKind::Warehouse)).unwrap();
+        inventory.add_location(
+            Location::new("SHOP-1".to_string(), "Shop 1".to_string(), LocationKind::Store).with_low_stock_threshold(2),
+        ).unwrap();
+        inventory.add_location(Location::new("TRUCK".to_string(), "Truck".to_string(), LocationKind::InTransit)).unwrap();
+        assert!(matches!(
+            inventory.add_location(Location::new("WH".to_string(), "Again".to_string(), LocationKind::Warehouse)),
+            Err(InventoryError::DuplicateLocation(_))
+        ));
+
+        let tea = inventory.add_product(
+            "Tea".to_string(), "TEA-1".to_string(), String::new(), 4.0, 3, "Leaf Co".to_string(),
+        ).unwrap();
+        inventory.restock_product(tea.id, "WH", 40, None, STORE_CLERK).unwrap();
+        inventory.transfer_stock(tea.id, "WH", "TRUCK", 10, STORE_CLERK).unwrap();
+        inventory.transfer_stock(tea.id, "TRUCK", "SHOP-1", 10, STORE_CLERK).unwrap();
+        inventory.sell_product(tea.id, "SHOP-1", 8, tea.price, STORE_CLERK, None).unwrap();
+
+        assert_eq!(inventory.get_stock_at(tea.id, DEFAULT_LOCATION), 3);
+        assert_eq!(inventory.get_stock_at(tea.id, "WH"), 30);
+        assert_eq!(inventory.get_stock_at(tea.id, "TRUCK"), 0);
+        assert_eq!(inventory.get_stock_at(tea.id, "SHOP-1"), 2);
+        assert_eq!(inventory.get_product(tea.id).unwrap().quantity, 35);
+
+        // Bán ở cửa hàng không được lấy hàng của kho
+        assert!(matches!(inventory.sell_product(tea.id, "SHOP-1", 3, tea.price, STORE_CLERK, None), Err(InventoryError::InsufficientStock)));
+        assert!(matches!(inventory.transfer_stock(tea.id, "WH", "SHOP-1", 31, STORE_CLERK), Err(InventoryError::InsufficientStock)));
+        assert!(matches!(inventory.transfer_stock(tea.id, "WH", "NOWHERE", 1, STORE_CLERK), Err(InventoryError::LocationNotFound(_))));
+        assert!(matches!(inventory.transfer_stock(tea.id, "WH", "WH", 1, STORE_CLERK), Err(InventoryError::InvalidTransfer)));
+        assert!(matches!(
+            inventory.add_location(Location::new(" ".to_string(), "Blank".to_string(), LocationKind::Store)),
+            Err(InventoryError::InvalidLocation)
+        ));
+        assert_eq!(inventory.get_stock_at(tea.id, "WH"), 30);
+
+        let low_at_shop: Vec<_> = inventory.get_low_stock_products_at("SHOP-1").unwrap()
+            .into_iter().map(|(product, quantity)| (product.id, quantity)).collect();
+        assert_eq!(low_at_shop, vec![(tea.id, 2)]);
+        assert_eq!(inventory.get_low_stock_products_at(DEFAULT_LOCATION).unwrap().len(), 1);
+        assert!(inventory.get_low_stock_products_at("WH").unwrap().is_empty());
+        assert!(inventory.get_low_stock_products().is_empty());
+
+        // Chuyển kho là một transaction: lỗi ở bước sau thì bước trước cũng được rollback
+        let mut store = db.open();
+        let result = store.apply(&[
+            StoreChange::SetStockLevel { product_id: tea.id, location_id: "WH".to_string(), expected: 30, quantity: 25 },
+            StoreChange::SetStockLevel { product_id: tea.id, location_id: "SHOP-1".to_string(), expected: 99, quantity: 7 },
+        ]);
+        assert!(result.is_err());
+
+        let reopened = InventoryManager::with_store(5, Box::new(store)).unwrap();
+        assert_eq!(reopened.get_stock_at(tea.id, "WH"), 30);
+        let levels: Vec<_> = reopened.get_stock_levels(tea.id).into_iter()
+            .map(|(location, quantity)| (location.id.as_str(), quantity)).collect();
+        assert_eq!(levels, vec![("MAIN", 3), ("SHOP-1", 2), ("TRUCK", 0), ("WH", 30)]);
+        assert_eq!(reopened.get_location("TRUCK").unwrap().kind, LocationKind::InTransit);
+        assert_eq!(reopened.get_location("SHOP-1").unwrap().low_stock_threshold, Some(2));
+
+        drop(inventory);
+        drop(reopened);
+    }
+
+    #[test]
+    fn test_stock_movement_ledger() {
+        use retailchain::inventory::{InventoryError, ledger::{MovementReason, SYSTEM_USER}};
+
+        let db = TempDatabase::new("ledger");
+        let mut inventory = db.open_inventory(5);
+        inventory.add_location(Location::new("WH".to_string(), "Warehouse".to_string(), LocationKind::Warehouse)).unwrap();
+        let soap = inventory.add_product(
+            "Soap".to_string(), "SOAP-1".to_string(), String::new(), 2.5, 10, "Acme".to_string(),
+        ).unwrap();
+
+        inventory.restock_product(soap.id, "WH", 30, None, "receiver").unwrap();
+        inventory.transfer_stock(soap.id, "WH", DEFAULT_LOCATION, 12, "receiver").unwrap();
+        inventory.sell_product(soap.id, DEFAULT_LOCATION, 4, soap.price, STORE_CLERK, Some("order-41".to_string())).unwrap();
+        inventory.record_stock_movement(
+            soap.id, DEFAULT_LOCATION, 1, MovementReason::Return, STORE_CLERK, Some("order-42".to_string()),
+        ).unwrap();
+        inventory.record_stock_movement(soap.id, "WH", -2, MovementReason::Shrinkage, "auditor", None).unwrap();
+        inventory.update_stock(soap.id, "WH", 15, "auditor").unwrap();
+        // Kiểm kê khớp số thì không ghi dòng nào
+        inventory.update_stock(soap.id, "WH", 15, "auditor").unwrap();
+
+        assert!(matches!(
+            inventory.record_stock_movement(soap.id, "WH", 0, MovementReason::Adjustment, "auditor", None),
+            Err(InventoryError::InvalidQuantity)
+        ));
+        assert!(matches!(inventory.sell_product(soap.id, "WH", 1, soap.price, " ", None), Err(InventoryError::MissingUser)));
+        assert!(matches!(
+            inventory.record_stock_movement(soap.id, "WH", -1, MovementReason::Transfer, "auditor", None),
+            Err(InventoryError::InvalidTransfer)
+        ));
+        assert!(matches!(
+            inventory.record_stock_movement(soap.id, "WH", 5, MovementReason::Sale, STORE_CLERK, None),
+            Err(InventoryError::InvalidMovementDirection(MovementReason::Sale, 5))
+        ));
+        assert!(matches!(
+            inventory.record_stock_movement(soap.id, "WH", -3, MovementReason::Receipt, "auditor", None),
+            Err(InventoryError::InvalidMovementDirection(MovementReason::Receipt, -3))
+        ));
+        assert!(matches!(inventory.sell_product(soap.id, DEFAULT_LOCATION, 100, soap.price, STORE_CLERK, None), Err(InventoryError::InsufficientStock)));
+
+        let history = inventory.get_movement_history(soap.id, None).unwrap();
+        let reasons: Vec<MovementReason> = history.iter().map(|movement| movement.reason).collect();
+        assert_eq!(reasons, vec![
+            MovementReason::Receipt, MovementReason::Receipt, MovementReason::Transfer, MovementReason::Transfer,
+            MovementReason::Sale, MovementReason::Return, MovementReason::Shrinkage, MovementReason::Adjustment,
+        ]);
+        assert_eq!(history[0].user, SYSTEM_USER);
+        assert_eq!(history[2].reference, history[3].reference);
+        assert_eq!(history[4].user, STORE_CLERK);
+        assert_eq!(history[4].reference.as_deref(), Some("order-41"));
+        assert_eq!(history[7].quantity_change, -1);
+
+        let main_history = inventory.get_movement_history(soap.id, Some(DEFAULT_LOCATION)).unwrap();
+        assert_eq!(main_history.len(), 4);
+
+        // Tồn kho suy ra từ sổ kho khớp với số lượng đang lưu, kể cả sau khi mở lại
+        drop(inventory);
+        let inventory = db.open_inventory(5);
+        for location in [DEFAULT_LOCATION, "WH"] {
+            assert_eq!(
+                inventory.quantity_from_ledger(soap.id, Some(location)).unwrap(),
+                i64::from(inventory.get_stock_at(soap.id, location)),
+            );
+        }
+        assert_eq!(inventory.quantity_from_ledger(soap.id, None).unwrap(), 34);
+        assert_eq!(inventory.get_product(soap.id).unwrap().quantity, 34);
+        assert_eq!(inventory.get_movement_history(soap.id, None).unwrap(), history);
+
+        // Sổ kho không thể bị sửa hay xóa trực tiếp trong cơ sở dữ liệu
+        let connection = rusqlite::Connection::open(&db.path).unwrap();
+        assert!(connection.execute("UPDATE stock_movements SET quantity_change = 100", []).is_err());
+        assert!(connection.execute("DELETE FROM stock_movements", []).is_err());
+
+        // Cơ sở dữ liệu cũ có dòng số dư đầu kỳ ghi thời điểm dạng `...Z`: migration 10 đưa về định dạng chung,
+        // nên dòng này vẫn đứng trước các dòng khác trong cùng giây
+        let opened_at = history[0].timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        connection.execute(
+            "INSERT INTO stock_movements (id, product_id, location_id, quantity_change, reason, user, timestamp, reference)
+             VALUES (?1, ?2, ?3, 1, 'Adjustment', 'migration', ?4, 'opening balance')",
+            rusqlite::params![uuid::Uuid::new_v4().to_string(), soap.id.to_string(), DEFAULT_LOCATION, opened_at],
+        ).unwrap();
+        connection.execute("DELETE FROM schema_migrations WHERE version = 10", []).unwrap();
+        drop(connection);
+        drop(inventory);
+        let inventory = db.open_inventory(5);
+        let migrated = inventory.get_movement_history(soap.id, None).unwrap();
+        assert_eq!(migrated[0].reference.as_deref(), Some("opening balance"));
+        assert_eq!(migrated[1..], history[..]);
+    }
+
+    #[test]
+    fn test_reorder_points_and_suggested_purchase_orders() {
+        use retailchain::inventory::InventoryError;
+
+        let db = TempDatabase::new("reorder");
+        let mut inventory = db.open_inventory(5);
+        let add = |inventory: &mut InventoryManager, sku: &str, quantity: u32, manufacturer: &str| {
+            inventory.add_product(
+                sku.to_lowercase(), sku.to_string(), String::new(), 1.0, quantity, manufacturer.to_string(),
+            ).unwrap()
+        };
+        let milk = add(&mut inventory, "MILK-1", 12, "Vinamilk");
+        let yogurt = add(&mut inventory, "YOG-1", 3, "Vinamilk");
+        let rice = add(&mut inventory, "RICE-5KG", 40, "Loc Troi");
+        let coffee = add(&mut inventory, "COF-1", 8, "Trung Nguyen");
+        let tea = add(&mut inventory, "TEA-1", 2, "Phuc Long");
+
+        // Sữa có điểm đặt lại cao hơn ngưỡng chung; gạo được đặt qua nhà phân phối
+        inventory.set_reorder_policy(ReorderPolicy::new(milk.id, 20, 48)).unwrap();
+        inventory.set_reorder_policy(ReorderPolicy::new(yogurt.id, 5, 24)).unwrap();
+        inventory.set_reorder_policy(
+            ReorderPolicy::new(rice.id, 50, 100).with_supplier(" Saigon Co.op ".to_string()),
+        ).unwrap();
+        inventory.set_reorder_policy(ReorderPolicy::new(coffee.id, 5, 10)).unwrap();
+        assert!(matches!(
+            inventory.set_reorder_policy(ReorderPolicy::new(tea.id, 5, 0)),
+            Err(InventoryError::InvalidQuantity)
+        ));
+        assert!(matches!(
+            inventory.set_reorder_policy(ReorderPolicy::new(uuid::Uuid::new_v4(), 5, 10)),
+            Err(InventoryError::ProductNotFound)
+        ));
+
+        assert_eq!(inventory.get_supplier(rice.id), Some("Saigon Co.op"));
+        assert_eq!(inventory.get_supplier(milk.id), Some("Vinamilk"));
+
+        // Trà không có điểm đặt lại: theo ngưỡng chung, đặt vừa đủ để vượt ngưỡng (5 + 1 - 2)
+        let mut low: Vec<&str> = inventory.get_low_stock_products().iter().map(|p| p.sku.as_str()).collect();
+        low.sort();
+        assert_eq!(low, vec!["MILK-1", "RICE-5KG", "TEA-1", "YOG-1"]);
+
+        let orders = inventory.suggest_purchase_orders(&std::collections::HashMap::new());
+        let summary: Vec<(&str, Vec<(&str, u32)>)> = orders.iter()
+            .map(|order| {
+                (order.supplier.as_str(), order.lines.iter().map(|line| (line.sku.as_str(), line.quantity)).collect())
+            })
+            .collect();
+        assert_eq!(summary, vec![
+            ("Phuc Long", vec![("TEA-1", 4)]),
+            ("Saigon Co.op", vec![("RICE-5KG", 100)]),
+            ("Vinamilk", vec![("MILK-1", 48), ("YOG-1", 24)]),
+        ]);
+        assert_eq!(orders[2].total_units(), 72);
+
+        // Hàng đang về theo đơn mua đã gửi được tính vào tồn kho: sữa chua (3 + 24 > 5) không còn được
+        // đề xuất, sữa đặt thêm ít (12 + 5) vẫn dưới điểm đặt lại
+        let mut purchase_orders = PurchaseOrderManager::new();
+        let order_id = purchase_orders.create_draft("Vinamilk".to_string(), DEFAULT_LOCATION, &inventory).unwrap();
+        purchase_orders.add_line(order_id, &inventory, milk.id, 5, 0.8).unwrap();
+        purchase_orders.add_line(order_id, &inventory, yogurt.id, 24, 0.5).unwrap();
+        assert!(purchase_orders.open_quantities().is_empty());
+        purchase_orders.send(order_id).unwrap();
+        let on_order = purchase_orders.open_quantities();
+        assert_eq!(on_order.get(&yogurt.id), Some(&24));
+        let orders = inventory.suggest_purchase_orders(&on_order);
+        let vinamilk = orders.iter().find(|order| order.supplier == "Vinamilk").unwrap();
+        assert_eq!(vinamilk.lines.len(), 1);
+        assert_eq!((vinamilk.lines[0].sku.as_str(), vinamilk.lines[0].on_order), ("MILK-1", 5));
+
+        // Bán cà phê xuống tới điểm đặt lại thì cà phê xuất hiện trong đề xuất
+        inventory.sell_product(coffee.id, DEFAULT_LOCATION, 3, coffee.price, STORE_CLERK, None).unwrap();
+        assert!(inventory.suggest_purchase_orders(&on_order).iter().any(|order| order.supplier == "Trung Nguyen"));
+
+        // Điểm đặt lại được lưu lại qua các lần mở
+        drop(inventory);
+        let inventory = db.open_inventory(5);
+        assert_eq!(inventory.get_reorder_policy(rice.id).unwrap().supplier.as_deref(), Some("Saigon Co.op"));
+        assert_eq!(inventory.reorder_point(milk.id), 20);
+        assert_eq!(inventory.reorder_point(tea.id), 5);
+    }
+
+    #[test]
+    fn test_purchase_order_lifecycle() {
+        use retailchain::inventory::{InventoryError, ledger::MovementReason};
+        use retailchain::purchasing::{PurchaseOrderError, PurchaseOrderStatus};
+
+        let mut inventory = InventoryManager::new(5);
+        let mut supply_chain = SupplyChainManager::new();
+        let mut orders = PurchaseOrderManager::new();
+        inventory.add_location(Location::new("WH".to_string(), "Warehouse".to_string(), LocationKind::Warehouse)).unwrap();
+        let cable = inventory.add_product(
+            "Cable".to_string(), "CBL-1".to_string(), String::new(), 9.0, 0, "Anker".to_string(),
+        ).unwrap();
+        let adapter = inventory.add_product(
+            "Adapter".to_string(), "ADP-1".to_string(), String::new(), 25.0, 2, "Anker".to_string(),
+        ).unwrap();
+        supply_chain.add_product(cable.clone());
+        supply_chain.add_product(adapter.clone());
+
+        let order_id = orders.create_draft("Anker".to_string(), "WH", &inventory).unwrap();
+        assert!(matches!(orders.send(order_id), Err(PurchaseOrderError::EmptyOrder)));
+        orders.add_line(order_id, &inventory, cable.id, 50, 3.0).unwrap();
+        orders.add_line(order_id, &inventory, adapter.id, 10, 12.0).unwrap();
+        orders.add_line(order_id, &inventory, cable.id, 10, 2.5).unwrap();
+        assert_eq!(orders.get_purchase_order(order_id).unwrap().lines[0].quantity_ordered, 60);
+        assert_eq!(orders.get_purchase_order(order_id).unwrap().total_cost(), 270.0);
+
+        // Chưa gửi thì chưa nhận được hàng
+        assert!(matches!(
+            orders.receive(order_id, &[(cable.id, 1)], STORE_CLERK, &mut inventory, &mut supply_chain),
+            Err(PurchaseOrderError::InvalidStatus(PurchaseOrderStatus::Draft))
+        ));
+        orders.send(order_id).unwrap();
+        assert!(matches!(
+            orders.add_line(order_id, &inventory, cable.id, 1, 2.5),
+            Err(PurchaseOrderError::InvalidStatus(PurchaseOrderStatus::Sent))
+        ));
+
+        // Lần nhận đầu: cáp giao thiếu, adapter giao thừa
+        orders.receive(order_id, &[(cable.id, 40), (adapter.id, 12)], STORE_CLERK, &mut inventory, &mut supply_chain).unwrap();
+        let order = orders.get_purchase_order(order_id).unwrap();
+        assert_eq!(order.status, PurchaseOrderStatus::PartiallyReceived);
+        assert_eq!(inventory.get_stock_at(cable.id, "WH"), 40);
+        assert_eq!(inventory.get_product(adapter.id).unwrap().quantity, 14);
+        assert_eq!(supply_chain.get_current_location(cable.id).as_deref(), Some("WH"));
+        let history = supply_chain.get_product_history(adapter.id).unwrap();
+        assert!(matches!(history[0].action, SupplyChainAction::Received));
+        assert_eq!(history[0].metadata["quantity"], 12);
+        let receipt = inventory.get_movement_history(cable.id, Some("WH")).unwrap().pop().unwrap();
+        assert_eq!(receipt.reason, MovementReason::Receipt);
+        assert_eq!(receipt.reference, Some(order.reference()));
+
+        let report = orders.discrepancy_report(order_id).unwrap();
+        let short: Vec<(&str, i64)> = report.short_lines().map(|line| (line.sku.as_str(), line.difference)).collect();
+        let over: Vec<(&str, i64)> = report.over_lines().map(|line| (line.sku.as_str(), line.difference)).collect();
+        assert_eq!(short, vec![("CBL-1", -20)]);
+        assert_eq!(over, vec![("ADP-1", 2)]);
+
+        // Sản phẩm không có trong đơn bị từ chối, không thay đổi tồn kho
+        let stray = inventory.add_product(
+            "Stray".to_string(), "STR-1".to_string(), String::new(), 1.0, 0, "Other".to_string(),
+        ).unwrap();
+        assert!(matches!(
+            orders.receive(order_id, &[(cable.id, 5), (stray.id, 1)], STORE_CLERK, &mut inventory, &mut supply_chain),
+            Err(PurchaseOrderError::ProductNotOnOrder(id)) if id == stray.id
+        ));
+        assert_eq!(inventory.get_stock_at(cable.id, "WH"), 40);
+        assert!(matches!(orders.cancel(order_id), Err(PurchaseOrderError::InvalidStatus(_))));
+
+        // Nhận đủ phần còn lại thì đơn hoàn tất; dòng lặp lại trong phiếu được gộp
+        let receipt = orders.receive(
+            order_id, &[(cable.id, 15), (cable.id, 5)], STORE_CLERK, &mut inventory, &mut supply_chain,
+        ).unwrap();
+        assert_eq!(receipt.lines, vec![(cable.id, 20)]);
+        assert_eq!(inventory.get_movement_history(cable.id, Some("WH")).unwrap().len(), 2);
+        let order = orders.get_purchase_order(order_id).unwrap();
+        assert_eq!(order.status, PurchaseOrderStatus::Received);
+        assert_eq!(order.receipts.len(), 2);
+        assert!(order.closed_at.is_some());
+        assert_eq!(orders.discrepancy_report(order_id).unwrap().lines.len(), 1);
+
+        // Một dòng lỗi thì không dòng nào vào kho và đơn không đổi
+        let bulk_id = orders.create_draft("Anker".to_string(), "WH", &inventory).unwrap();
+        orders.add_line(bulk_id, &inventory, cable.id, 5, 2.5).unwrap();
+        orders.add_line(bulk_id, &inventory, adapter.id, u32::MAX, 12.0).unwrap();
+        orders.send(bulk_id).unwrap();
+        assert!(matches!(
+            orders.receive(bulk_id, &[(cable.id, 5), (adapter.id, u32::MAX)], STORE_CLERK, &mut inventory, &mut supply_chain),
+            Err(PurchaseOrderError::Inventory(InventoryError::QuantityOverflow))
+        ));
+        assert_eq!(inventory.get_stock_at(cable.id, "WH"), 60);
+        let bulk = orders.get_purchase_order(bulk_id).unwrap();
+        assert_eq!(bulk.status, PurchaseOrderStatus::Sent);
+        assert_eq!(bulk.lines[0].quantity_received, 0);
+        assert!(bulk.receipts.is_empty());
+
+        // Đơn nhận thiếu được đóng lại, đơn chưa nhận được hủy
+        let short_id = orders.create_draft("Anker".to_string(), DEFAULT_LOCATION, &inventory).unwrap();
+        orders.add_line(short_id, &inventory, cable.id, 10, 3.0).unwrap();
+        orders.send(short_id).unwrap();
+        orders.receive(short_id, &[(cable.id, 7)], STORE_CLERK, &mut inventory, &mut supply_chain).unwrap();
+        orders.close_short(short_id).unwrap();
+        assert_eq!(orders.get_purchase_order(short_id).unwrap().status, PurchaseOrderStatus::Received);
+        assert_eq!(orders.discrepancy_report(short_id).unwrap().short_lines().count(), 1);
+
+        let cancelled_id = orders.create_draft("Anker".to_string(), "WH", &inventory).unwrap();
+        orders.cancel(cancelled_id).unwrap();
+        assert_eq!(orders.get_purchase_orders_by_status(PurchaseOrderStatus::Cancelled).len(), 1);
+        assert!(orders.create_draft("Anker".to_string(), "NOWHERE", &inventory).is_err());
+    }
+
+    #[test]
+    fn test_stock_reservations_for_pending_orders() {
+        use retailchain::inventory::{InventoryError, ledger::MovementReason};
+        use retailchain::payment::{PaymentError, request::PaymentRequestStatus};
+
+        let mut processor = PaymentProcessor::new();
+        let mut inventory = InventoryManager::new(0);
+        let watch = inventory.add_product(
+            "Watch".to_string(), "WATCH-1".to_string(), String::new(), 25.0, 3, "Acme".to_string(),
+        ).unwrap();
+
+        // Giá bán chốt trên đơn có thể khác giá niêm yết, mỗi lần giữ một giá
+        inventory.reserve_stock("ORDER-1", watch.id, DEFAULT_LOCATION, 1, 20.0, chrono::Duration::minutes(15)).unwrap();
+        inventory.reserve_stock("ORDER-1", watch.id, DEFAULT_LOCATION, 1, 30.0, chrono::Duration::minutes(15)).unwrap();
+        assert_eq!(inventory.get_reserved_at(watch.id, DEFAULT_LOCATION), 2);
+        assert_eq!(inventory.get_available_quantity(watch.id), 1);
+        assert_eq!(inventory.get_product(watch.id).unwrap().quantity, 3);
+
+        // Chiếc cuối cùng không bị giữ nên chỉ còn bán/giữ được đúng một chiếc
+        assert!(matches!(
+            inventory.reserve_stock("ORDER-2", watch.id, DEFAULT_LOCATION, 2, watch.price, chrono::Duration::minutes(15)),
+            Err(InventoryError::InsufficientStock)
+        ));
+        inventory.reserve_stock("ORDER-2", watch.id, DEFAULT_LOCATION, 1, watch.price, chrono::Duration::minutes(15)).unwrap();
+        assert!(matches!(inventory.sell_product(watch.id, DEFAULT_LOCATION, 1, watch.price, STORE_CLERK, None), Err(InventoryError::InsufficientStock)));
+
+        // Hủy đơn thì trả lại hàng
+        assert_eq!(inventory.release_reservation("ORDER-2").unwrap().len(), 1);
+        assert!(matches!(inventory.release_reservation("ORDER-2"), Err(InventoryError::ReservationNotFound(_))));
+        assert_eq!(inventory.get_available_quantity(watch.id), 1);
+
+        // Giữ chỗ hết hạn không còn được tính, rồi được dọn
+        inventory.reserve_stock("ORDER-3", watch.id, DEFAULT_LOCATION, 1, watch.price, chrono::Duration::zero()).unwrap();
+        assert_eq!(inventory.get_available_quantity(watch.id), 1);
+        assert!(matches!(inventory.commit_reservation("ORDER-3", STORE_CLERK), Err(InventoryError::ReservationExpired(_))));
+        let expired = inventory.release_expired_reservations(chrono::Utc::now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order_id, "ORDER-3");
+        assert!(inventory.get_reservations("ORDER-1").len() == 2);
+
+        // Thanh toán khớp yêu cầu thì hàng đang giữ được bán với mã đơn, gộp thành một dòng sổ kho
+        processor.create_payment_request(
+            RETAILER_WALLET.to_string(), 50.0, Currency::USDT, "ORDER-1".to_string(), None,
+        ).unwrap();
+        let payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 50.0, Currency::USDT,
+        ).unwrap();
+        let request = processor.reconcile_payment_request_with_reservations(&payment, &mut inventory).unwrap().unwrap();
+        assert_eq!(request.reference, "ORDER-1");
+        assert_eq!(inventory.get_product(watch.id).unwrap().quantity, 1);
+        assert_eq!(inventory.get_reserved_at(watch.id, DEFAULT_LOCATION), 0);
+        assert!(inventory.get_reservations("ORDER-1").is_empty());
+        let sale = inventory.get_movement_history(watch.id, None).unwrap().pop().unwrap();
+        assert_eq!(sale.reason, MovementReason::Sale);
+        assert_eq!(sale.quantity_change, -2);
+        assert_eq!(sale.reference.as_deref(), Some("ORDER-1"));
+        assert_eq!(sale.unit_price, Some(25.0));
+
+        // Giữ chỗ hết hạn trước khi tiền về: yêu cầu vẫn mở, không bán hàng
+        inventory.reserve_stock("ORDER-4", watch.id, DEFAULT_LOCATION, 1, watch.price, chrono::Duration::zero()).unwrap();
+        let request_id = processor.create_payment_request(
+            RETAILER_WALLET.to_string(), 25.0, Currency::USDT, "ORDER-4".to_string(), None,
+        ).unwrap().id;
+        let payment = processor.process_payment(
+            CUSTOMER_WALLET.to_string(), RETAILER_WALLET.to_string(), 25.0, Currency::USDT,
+        ).unwrap();
+        assert!(matches!(
+            processor.reconcile_payment_request_with_reservations(&payment, &mut inventory),
+            Err(PaymentError::Inventory(InventoryError::ReservationExpired(_)))
+        ));
+        assert_eq!(processor.get_payment_request(request_id).unwrap().status, PaymentRequestStatus::Open);
+        assert_eq!(inventory.get_product(watch.id).unwrap().quantity, 1);
+        inventory.release_expired_reservations(chrono::Utc::now());
+
+        inventory.sell_product(watch.id, DEFAULT_LOCATION, 1, watch.price, STORE_CLERK, None).unwrap();
+        assert_eq!(inventory.get_available_quantity(watch.id), 0);
+    }
+
+    #[test]
+    fn test_lot_tracking_fefo_and_recall() {
+        use retailchain::inventory::{InventoryError, ledger::MovementReason};
+        use retailchain::inventory::lot::LotInfo;
+
+        let today = chrono::Utc::now().date_naive();
+        let days = chrono::Duration::days;
+        let db = TempDatabase::new("lots");
+        let mut inventory = db.open_inventory(0);
+        inventory.add_location(Location::new("WH".to_string(), "Warehouse".to_string(), LocationKind::Warehouse)).unwrap();
+        // 2 hộp sữa cũ không theo lô
+        let milk = inventory.add_product(
+            "Milk".to_string(), "MILK-1L".to_string(), String::new(), 1.5, 2, "Vinamilk".to_string(),
+        ).unwrap();
+
+        let lot = |number: &str, made: i64, expires: i64| {
+            LotInfo::new(number.to_string(), today - days(made)).with_expiry(today + days(expires))
+        };
+        inventory.receive_lot(milk.id, DEFAULT_LOCATION, lot("L-LATE", 2, 20), 10, STORE_CLERK, None).unwrap();
+        inventory.receive_lot(milk.id, DEFAULT_LOCATION, lot("L-SOON", 10, 3), 4, STORE_CLERK, None).unwrap();
+        inventory.receive_lot(milk.id, DEFAULT_LOCATION, lot("L-OLD", 30, -1), 5, STORE_CLERK, None).unwrap();
+        inventory.receive_lot(milk.id, "WH", lot("L-LATE", 2, 20), 6, STORE_CLERK, None).unwrap();
+        assert_eq!(inventory.get_product(milk.id).unwrap().quantity, 27);
+
+        assert!(matches!(
+            inventory.receive_lot(milk.id, "WH", lot("L-LATE", 3, 20), 1, STORE_CLERK, None),
+            Err(InventoryError::InvalidLot(_))
+        ));
+        assert!(matches!(
+            inventory.receive_lot(milk.id, "WH", lot("L-BAD", 0, -5), 1, STORE_CLERK, None),
+            Err(InventoryError::InvalidLot(_))
+        ));
+
+        // Báo cáo sắp hết hạn: kể cả lô đã hết hạn, chưa tới lô còn 20 ngày
+        let expiring: Vec<&str> = inventory.get_expiring_lots(today, 7).iter().map(|lot| lot.lot_number.as_str()).collect();
+        assert_eq!(expiring, vec!["L-OLD", "L-SOON"]);
+
+        // FEFO: bỏ qua lô đã hết hạn, lấy hết lô sắp hết hạn rồi mới tới lô sau
+        inventory.sell_product(milk.id, DEFAULT_LOCATION, 6, milk.price, STORE_CLERK, None).unwrap();
+        let sale: Vec<(Option<String>, i64)> = inventory.get_movement_history(milk.id, Some(DEFAULT_LOCATION)).unwrap()
+            .into_iter()
+            .filter(|movement| movement.reason == MovementReason::Sale)
+            .map(|movement| (movement.lot_number, movement.quantity_change))
+            .collect();
+        assert_eq!(sale, vec![(Some("L-SOON".to_string()), -4), (Some("L-LATE".to_string()), -2)]);
+
+        // Đơn giữ chỗ cũng lấy theo FEFO; hết lô còn hạn thì tới hàng không theo lô,
+        // còn lô hết hạn thì không bán được
+        inventory.reserve_stock("ORDER-9", milk.id, DEFAULT_LOCATION, 10, milk.price, chrono::Duration::minutes(10)).unwrap();
+        let committed = inventory.commit_reservation("ORDER-9", STORE_CLERK).unwrap();
+        assert_eq!(committed.iter().map(|m| m.lot_number.clone()).collect::<Vec<_>>(), vec![Some("L-LATE".to_string()), None]);
+        assert!(matches!(inventory.sell_product(milk.id, DEFAULT_LOCATION, 1, milk.price, STORE_CLERK, None), Err(InventoryError::InsufficientStock)));
+        assert_eq!(inventory.get_stock_at(milk.id, DEFAULT_LOCATION), 5);
+
+        // Hàng hết hạn được hủy dưới dạng hao hụt
+        let written_off = inventory.record_stock_movement(
+            milk.id, DEFAULT_LOCATION, -5, MovementReason::Shrinkage, "auditor", Some("expired".to_string()),
+        ).unwrap();
+        assert_eq!(written_off[0].lot_number.as_deref(), Some("L-OLD"));
+
+        // Lô đi theo hàng khi chuyển kho
+        inventory.transfer_stock(milk.id, "WH", DEFAULT_LOCATION, 4, "receiver").unwrap();
+        let lots: Vec<(&str, &str, u32)> = inventory.get_lots(milk.id).iter()
+            .map(|lot| (lot.lot_number.as_str(), lot.location_id.as_str(), lot.quantity))
+            .collect();
+        assert_eq!(lots, vec![("L-LATE", DEFAULT_LOCATION, 4), ("L-LATE", "WH", 2)]);
+
+        // Thu hồi: tìm các lần bán có hàng của lô, kể cả sau khi mở lại
+        drop(inventory);
+        let inventory = db.open_inventory(0);
+        let recalled = inventory.find_sales_by_lot(milk.id, "L-LATE").unwrap();
+        assert_eq!(recalled.len(), 2);
+        assert_eq!(recalled[1].reference.as_deref(), Some("ORDER-9"));
+        assert_eq!(recalled.iter().map(|m| -m.quantity_change).sum::<i64>(), 10);
+        assert!(inventory.find_sales_by_lot(milk.id, "L-OLD").unwrap().is_empty());
+        assert!(matches!(inventory.find_sales_by_lot(milk.id, "NOPE"), Err(InventoryError::LotNotFound(_))));
+        assert_eq!(inventory.get_lots(milk.id).len(), 2);
+        assert_eq!(inventory.quantity_from_ledger(milk.id, None).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_serialized_units_with_blockchain_anchor() {
+        use retailchain::inventory::{InventoryError, ledger::MovementReason, serial::{SerializedUnit, UnitStatus}};
+
+        let db = TempDatabase::new("serials");
+        let mut inventory = db.open_inventory(0);
+        let mut supply_chain = SupplyChainManager::new();
+        let mut blockchain = Blockchain::new();
+        inventory.add_location(Location::new("SHOP-1".to_string(), "Shop 1".to_string(), LocationKind::Store)).unwrap();
+        let phone = inventory.add_product(
+            "Phone".to_string(), "PHONE-1".to_string(), String::new(), 500.0, 1, "Acme".to_string(),
+        ).unwrap();
+        supply_chain.add_product(phone.clone());
+
+        let units = inventory.receive_serialized_units(
+            phone.id, DEFAULT_LOCATION, &["490154203237518", " sn-abc-1 "], 600.0, STORE_CLERK, &mut supply_chain,
+        ).unwrap();
+        assert_eq!(units[1].serial_number, "SN-ABC-1");
+        assert_eq!(inventory.get_product(phone.id).unwrap().quantity, 3);
+        assert_ne!(units[0].id, units[1].id);
+
+        assert!(matches!(
+            inventory.receive_serialized_units(phone.id, DEFAULT_LOCATION, &["SN-ABC-1"], 600.0, STORE_CLERK, &mut supply_chain),
+            Err(InventoryError::DuplicateSerial(_))
+        ));
+        // IMEI sai chữ số kiểm tra
+        assert!(matches!(
+            inventory.receive_serialized_units(phone.id, DEFAULT_LOCATION, &["490154203237519"], 600.0, STORE_CLERK, &mut supply_chain),
+            Err(InventoryError::InvalidSerial(_))
+        ));
+        // Địa điểm sai bị từ chối trước khi ghi gì vào tồn kho hay chuỗi cung ứng
+        assert!(matches!(
+            inventory.receive_serialized_units(phone.id, "NOWHERE", &["SN-XYZ-9"], 600.0, STORE_CLERK, &mut supply_chain),
+            Err(InventoryError::LocationNotFound(_))
+        ));
+        assert_eq!(inventory.get_product(phone.id).unwrap().quantity, 3);
+        assert!(inventory.get_unit("SN-XYZ-9").is_none());
+
+        // Chỉ giữ chỗ được phần hàng không theo serial
+        assert!(matches!(
+            inventory.reserve_stock("ORDER-76", phone.id, DEFAULT_LOCATION, 2, phone.price, chrono::Duration::minutes(15)),
+            Err(InventoryError::SerialNumberRequired)
+        ));
+        inventory.reserve_stock("ORDER-76", phone.id, DEFAULT_LOCATION, 1, phone.price, chrono::Duration::minutes(15)).unwrap();
+        inventory.release_reservation("ORDER-76").unwrap();
+
+        // Hàng theo serial không bị lấy khi bán không chỉ rõ serial
+        inventory.sell_product(phone.id, DEFAULT_LOCATION, 1, phone.price, STORE_CLERK, None).unwrap();
+        assert!(matches!(
+            inventory.sell_product(phone.id, DEFAULT_LOCATION, 1, phone.price, STORE_CLERK, None),
+            Err(InventoryError::SerialNumberRequired)
+        ));
+
+        inventory.transfer_serialized_unit("sn-abc-1", "SHOP-1", STORE_CLERK, &mut supply_chain).unwrap();
+        assert_eq!(inventory.get_stock_at(phone.id, "SHOP-1"), 1);
+        assert_eq!(inventory.get_unit("SN-ABC-1").unwrap().location_id, "SHOP-1");
+
+        let sold = inventory.sell_serialized_unit(
+            "490154203237518", phone.price, STORE_CLERK, Some("ORDER-77".to_string()), &mut supply_chain, &mut blockchain,
+        ).unwrap();
+        assert_eq!(sold.status, UnitStatus::Sold);
+        assert!(matches!(
+            inventory.sell_serialized_unit("490154203237518", phone.price, STORE_CLERK, None, &mut supply_chain, &mut blockchain),
+            Err(InventoryError::UnitAlreadySold(_))
+        ));
+        assert!(matches!(
+            inventory.sell_serialized_unit("NOPE-1", phone.price, STORE_CLERK, None, &mut supply_chain, &mut blockchain),
+            Err(InventoryError::SerialNotFound(_))
+        ));
+
+        let sale = inventory.get_movement_history(phone.id, Some(DEFAULT_LOCATION)).unwrap().pop().unwrap();
+        assert_eq!(sale.reason, MovementReason::Sale);
+        assert_eq!(sale.serial_number.as_deref(), Some("490154203237518"));
+        assert_eq!(sale.reference.as_deref(), Some("ORDER-77"));
+        // Thiết bị nhập theo serial mang giá nhập của lần nhận hàng
+        assert_eq!(sale.unit_cost, Some(600.0));
+        assert_eq!(sale.unit_price, Some(phone.price));
+
+        // Mỗi thiết bị có lịch sử chuỗi cung ứng riêng
+        let actions = |unit_id| -> Vec<String> {
+            supply_chain.get_unit_history(unit_id).unwrap().iter().map(|record| format!("{:?}", record.action)).collect()
+        };
+        assert_eq!(actions(units[0].id), vec!["Received", "Sold"]);
+        assert_eq!(actions(units[1].id), vec!["Received", "Shipped", "Received"]);
+
+        // Lần bán được neo lên block kế tiếp và kiểm tra được bằng nội dung của thiết bị
+        let block = blockchain.mine_block().unwrap();
+        assert_eq!(block.anchors.len(), 1);
+        assert!(blockchain.is_chain_valid());
+        let (anchored_in, anchor) = blockchain.find_anchor(&sold.id.to_string()).unwrap();
+        assert_eq!(anchored_in.unwrap().index, block.index);
+        assert_eq!(Some(&anchor.digest), sold.sale_anchor.as_ref());
+        assert!(blockchain.verify_anchor(&sold.id.to_string(), &sold.sale_payload()));
+        let tampered = SerializedUnit { sale_reference: Some("ORDER-78".to_string()), ..sold.clone() };
+        assert!(!blockchain.verify_anchor(&sold.id.to_string(), &tampered.sale_payload()));
+
+        // Kiểm kê không giảm được tồn kho theo serial; thiết bị mất được loại theo số serial
+        assert!(matches!(
+            inventory.update_stock(phone.id, "SHOP-1", 0, "auditor"),
+            Err(InventoryError::SerialNumberRequired)
+        ));
+        let lost = inventory.write_off_serialized_unit("SN-ABC-1", "auditor", Some("count-2024-03".to_string())).unwrap();
+        assert_eq!(lost.status, UnitStatus::WrittenOff);
+        assert_eq!(inventory.get_stock_at(phone.id, "SHOP-1"), 0);
+        let shrinkage = inventory.get_movement_history(phone.id, Some("SHOP-1")).unwrap().pop().unwrap();
+        assert_eq!(shrinkage.reason, MovementReason::Shrinkage);
+        assert_eq!(shrinkage.serial_number.as_deref(), Some("SN-ABC-1"));
+        assert!(matches!(
+            inventory.sell_serialized_unit("SN-ABC-1", phone.price, STORE_CLERK, None, &mut supply_chain, &mut blockchain),
+            Err(InventoryError::UnitWrittenOff(_))
+        ));
+
+        // Thiết bị được lưu lại qua các lần mở
+        drop(inventory);
+        let inventory = db.open_inventory(0);
+        assert_eq!(inventory.get_unit("490154203237518").unwrap(), &sold);
+        assert_eq!(inventory.get_unit("SN-ABC-1").unwrap().status, UnitStatus::WrittenOff);
+        assert!(inventory.get_units(phone.id, Some(UnitStatus::InStock)).is_empty());
+    }
+
+    #[test]
+    fn test_inventory_valuation_and_gross_margin() {
+        use retailchain::inventory::{InventoryError, ledger::MovementReason, valuation::{OpeningStock, ValuationMethod}};
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        let db = TempDatabase::new("valuation");
+        let mut inventory = db.open_inventory(0);
+        let coffee = inventory.add_product(
+            "Coffee Beans".to_string(), "CF-1KG".to_string(), String::new(), 20.0, 0, "Trung Nguyen".to_string(),
+        ).unwrap();
+
+        inventory.receive_stock(coffee.id, DEFAULT_LOCATION, 10, 8.0, STORE_CLERK, Some("PO-1".to_string())).unwrap();
+        let after_first_receipt = chrono::Utc::now();
+        inventory.receive_stock(coffee.id, DEFAULT_LOCATION, 10, 11.0, STORE_CLERK, Some("PO-2".to_string())).unwrap();
+        assert!(matches!(
+            inventory.receive_stock(coffee.id, DEFAULT_LOCATION, 1, -1.0, STORE_CLERK, None),
+            Err(InventoryError::InvalidUnitCost)
+        ));
+        assert!(close(inventory.get_inventory_value(coffee.id), 190.0));
+
+        // FIFO: 10 đơn vị giá 8 rồi 5 đơn vị giá 11, bán theo giá khuyến mãi chứ không theo giá niêm yết
+        assert!(matches!(
+            inventory.sell_product(coffee.id, DEFAULT_LOCATION, 1, -18.0, STORE_CLERK, None),
+            Err(InventoryError::InvalidUnitPrice)
+        ));
+        assert!(matches!(
+            inventory.record_stock_movement(coffee.id, DEFAULT_LOCATION, -1, MovementReason::Sale, STORE_CLERK, None),
+            Err(InventoryError::SaleWithoutPrice)
+        ));
+        let sold = inventory.sell_product(coffee.id, DEFAULT_LOCATION, 15, 18.0, STORE_CLERK, None).unwrap();
+        assert!(close(sold.iter().map(|movement| movement.total_cost()).sum(), 135.0));
+        assert!(sold.iter().all(|movement| movement.unit_price == Some(18.0)));
+        assert!(close(inventory.get_inventory_value(coffee.id), 55.0));
+
+        // Hàng trả lại nhập lại theo giá vốn (9) và giá bán của lần bán, được trừ khỏi lãi gộp
+        inventory.record_stock_movement(coffee.id, DEFAULT_LOCATION, 1, MovementReason::Return, STORE_CLERK, None).unwrap();
+        let since = coffee.created_at;
+        let until = chrono::Utc::now() + chrono::Duration::seconds(1);
+        let margin = inventory.gross_margin(coffee.id, since, until).unwrap();
+        assert_eq!(margin.units_sold, 14);
+        assert!(close(margin.revenue, 252.0));
+        assert!(close(margin.cost_of_goods_sold, 126.0));
+        assert!(close(margin.gross_profit, 126.0));
+        assert!(close(margin.margin_percent.unwrap(), 50.0));
+        assert_eq!(inventory.gross_margin_report(since, until).unwrap().len(), 1);
+
+        // Báo cáo tại một thời điểm trong quá khứ chỉ tính các dòng sổ kho trước đó
+        let report = inventory.valuation_at(after_first_receipt).unwrap();
+        assert_eq!(report.lines.len(), 1);
+        assert_eq!(report.lines[0].quantity, 10);
+        assert!(close(report.total_value, 80.0));
+        assert!(close(inventory.valuation_at(until).unwrap().total_value, 64.0));
+
+        // Bình quân gia quyền: 20 đơn vị giá 9.5, xuất 15, nhập lại 1 giá 9
+        inventory.set_valuation_method(ValuationMethod::WeightedAverage).unwrap();
+        assert!(close(inventory.get_inventory_value(coffee.id), 56.5));
+        assert_eq!(inventory.valuation_at(until).unwrap().method, ValuationMethod::WeightedAverage);
+        assert!(close(inventory.gross_margin(coffee.id, since, until).unwrap().cost_of_goods_sold, 126.0));
+
+        // Tồn kho ban đầu và hàng nhập bổ sung mang giá nhập riêng thay vì giá 0
+        assert!(matches!(
+            inventory.add_product_with_opening_stock(
+                "Tea".to_string(), "TEA-1".to_string(), String::new(), 9.0, "Phuc Long".to_string(), OpeningStock::new(4, -5.0),
+            ),
+            Err(InventoryError::InvalidUnitCost)
+        ));
+        let tea = inventory.add_product_with_opening_stock(
+            "Tea".to_string(), "TEA-1".to_string(), String::new(), 9.0, "Phuc Long".to_string(), OpeningStock::new(4, 5.0),
+        ).unwrap();
+        assert!(close(inventory.get_inventory_value(tea.id), 20.0));
+        inventory.restock_product(tea.id, DEFAULT_LOCATION, 2, Some(8.0), STORE_CLERK).unwrap();
+        assert!(close(inventory.get_average_cost(tea.id), 6.0));
+
+        // Phương pháp đã chọn được lưu lại; lớp giá vốn được dựng lại từ sổ kho khi mở lại
+        drop(inventory);
+        let inventory = db.open_inventory(0);
+        assert_eq!(inventory.valuation_method(), ValuationMethod::WeightedAverage);
+        assert!(close(inventory.get_inventory_value(coffee.id), 56.5));
+        assert!(close(inventory.get_average_cost(coffee.id), 56.5 / 6.0));
+        assert!(close(inventory.gross_margin(coffee.id, since, until).unwrap().gross_profit, 126.0));
+        assert!(close(inventory.get_inventory_value(tea.id), 36.0));
+    }
+
     #[test]
     fn test_currency_equality() {
         let btc1 = Currency::BTC;
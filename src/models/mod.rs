@@ -11,6 +11,9 @@ pub struct Product {
     pub price: f64,
     pub quantity: u32,
     pub manufacturer: String,
+    // Mã vạch EAN-13 đã chuẩn hóa; UPC-A được lưu kèm số 0 ở đầu
+    #[serde(default)]
+    pub barcodes: Vec<String>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -23,6 +26,14 @@ pub struct Transaction {
     pub currency: Currency,
     pub timestamp: DateTime<Utc>,
     pub status: TransactionStatus,
+    // Tỷ giá so với USDT tại thời điểm giao dịch, dùng để hoàn tiền theo giá trị fiat
+    pub exchange_rate: f64,
+    // Giao dịch gốc nếu đây là giao dịch hoàn tiền
+    pub refund_of: Option<Uuid>,
+    // Phí merchant phải trả cho giao dịch, cùng đơn vị với `currency`
+    pub fee: f64,
+    // Chữ ký ECDSA (hex) của ví gửi, nếu giao dịch được ký
+    pub signature: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +44,19 @@ pub struct Block {
     pub previous_hash: String,
     pub hash: String,
     pub nonce: u64,
+    // Dữ liệu ngoài giao dịch được neo vào block bằng mã băm, ví dụ lần bán một thiết bị theo số serial
+    #[serde(default)]
+    pub anchors: Vec<ChainAnchor>,
+}
+
+// Neo một bản ghi lên chain: chỉ lưu mã băm SHA-256 của nội dung, nội dung nằm ở hệ thống gốc
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainAnchor {
+    pub id: Uuid,
+    // Mã của bản ghi được neo, ví dụ id của thiết bị
+    pub reference: String,
+    pub digest: String,
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,64 +69,48 @@ pub struct SupplyChainRecord {
     pub metadata: serde_json::Value,
 }
 
-// Sửa lại Currency - loại bỏ f64 khỏi các derive
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// RETAIL chỉ mang ký hiệu token, thông tin token nằm trong TokenRegistry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Currency {
     BTC,
     ETH,
     USDT,
-    RETAIL(RetailToken),
+    // Tiền mặt tại quầy, tính theo USD
+    CASH,
+    RETAIL(String),
 }
 
-// Manual implementation của Eq, PartialEq, Hash cho Currency
-impl PartialEq for Currency {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Currency::BTC, Currency::BTC) => true,
-            (Currency::ETH, Currency::ETH) => true,
-            (Currency::USDT, Currency::USDT) => true,
-            (Currency::RETAIL(a), Currency::RETAIL(b)) => a.symbol == b.symbol,
-            _ => false,
+impl Currency {
+    pub fn symbol(&self) -> String {
+        match self {
+            Currency::BTC => "BTC".to_string(),
+            Currency::ETH => "ETH".to_string(),
+            Currency::USDT => "USDT".to_string(),
+            Currency::CASH => "CASH".to_string(),
+            Currency::RETAIL(symbol) => symbol.clone(),
         }
     }
-}
-
-impl Eq for Currency {}
 
-impl std::hash::Hash for Currency {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        match self {
-            Currency::BTC => "BTC".hash(state),
-            Currency::ETH => "ETH".hash(state),
-            Currency::USDT => "USDT".hash(state),
-            Currency::RETAIL(token) => {
-                "RETAIL".hash(state);
-                token.symbol.hash(state);
-            }
+    // Chỉ nhận các loại tiền cố định; token RETAIL phải được tra trong danh sách token đã đăng ký
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol.trim().to_ascii_uppercase().as_str() {
+            "BTC" => Some(Currency::BTC),
+            "ETH" => Some(Currency::ETH),
+            "USDT" => Some(Currency::USDT),
+            "CASH" => Some(Currency::CASH),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Token do retailer phát hành, số lượng tính theo đơn vị nhỏ nhất (10^-decimals)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RetailToken {
     pub symbol: String,
-    pub amount: f64,
-    pub loyalty_points: u32,
-}
-
-// Manual implementation cho RetailToken
-impl PartialEq for RetailToken {
-    fn eq(&self, other: &Self) -> bool {
-        self.symbol == other.symbol
-    }
-}
-
-impl Eq for RetailToken {}
-
-impl std::hash::Hash for RetailToken {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.symbol.hash(state);
-    }
+    pub name: String,
+    pub decimals: u8,
+    pub issuer: String,
+    pub total_supply: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -110,6 +118,8 @@ pub enum TransactionStatus {
     Pending,
     Completed,
     Failed,
+    PartiallyRefunded,
+    Refunded,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
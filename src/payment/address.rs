@@ -0,0 +1,115 @@
+use crate::models::Currency;
+use crate::wallet::{to_checksum_address, BTC_ADDRESS_HRP, RETAIL_ADDRESS_HRP};
+use bech32::{FromBase32, Variant};
+
+const BTC_P2PKH_VERSION: u8 = 0x00;
+const BTC_P2SH_VERSION: u8 = 0x05;
+
+pub fn validate_address(address: &str, currency: &Currency) -> Result<(), AddressError> {
+    if address.is_empty() {
+        return Err(AddressError::Empty);
+    }
+
+    match currency {
+        Currency::BTC => validate_btc_address(address),
+        Currency::ETH | Currency::USDT => validate_eth_address(address),
+        Currency::RETAIL(_) => validate_retail_address(address),
+        // Tiền mặt dùng mã két/quầy, không có định dạng on-chain
+        Currency::CASH => Ok(()),
+    }
+}
+
+fn validate_btc_address(address: &str) -> Result<(), AddressError> {
+    if address.to_ascii_lowercase().starts_with(&format!("{}1", BTC_ADDRESS_HRP)) {
+        return validate_segwit_address(address);
+    }
+
+    let payload = bs58::decode(address)
+        .with_check(None)
+        .into_vec()
+        .map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+    if payload.len() != 21 {
+        return Err(AddressError::InvalidLength);
+    }
+    match payload[0] {
+        BTC_P2PKH_VERSION | BTC_P2SH_VERSION => Ok(()),
+        version => Err(AddressError::UnsupportedVersion(version)),
+    }
+}
+
+// BIP173/BIP350: witness v0 dùng Bech32, v1 trở lên dùng Bech32m
+fn validate_segwit_address(address: &str) -> Result<(), AddressError> {
+    let (hrp, data, variant) = bech32::decode(address).map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+    if hrp != BTC_ADDRESS_HRP {
+        return Err(AddressError::WrongPrefix(hrp));
+    }
+
+    let (version, program) = data.split_first().ok_or(AddressError::InvalidLength)?;
+    let version = version.to_u8();
+    if version > 16 {
+        return Err(AddressError::UnsupportedVersion(version));
+    }
+    let program = Vec::<u8>::from_base32(program).map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+
+    let expected_variant = if version == 0 { Variant::Bech32 } else { Variant::Bech32m };
+    if variant != expected_variant {
+        return Err(AddressError::ChecksumMismatch);
+    }
+    if program.len() < 2 || program.len() > 40 || (version == 0 && program.len() != 20 && program.len() != 32) {
+        return Err(AddressError::InvalidLength);
+    }
+    Ok(())
+}
+
+// EIP-55: địa chỉ toàn chữ thường/hoa không có checksum, chữ hoa/thường lẫn lộn phải khớp checksum
+fn validate_eth_address(address: &str) -> Result<(), AddressError> {
+    let hex_part = address.strip_prefix("0x").ok_or_else(|| AddressError::WrongPrefix(address.chars().take(2).collect()))?;
+    if hex_part.len() != 40 {
+        return Err(AddressError::InvalidLength);
+    }
+
+    let mut bytes = [0u8; 20];
+    hex::decode_to_slice(hex_part, &mut bytes).map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+
+    let is_lower = hex_part.chars().all(|c| !c.is_ascii_uppercase());
+    let is_upper = hex_part.chars().all(|c| !c.is_ascii_lowercase());
+    if is_lower || is_upper {
+        return Ok(());
+    }
+    if to_checksum_address(&bytes) != address {
+        return Err(AddressError::ChecksumMismatch);
+    }
+    Ok(())
+}
+
+fn validate_retail_address(address: &str) -> Result<(), AddressError> {
+    let (hrp, data, variant) = bech32::decode(address).map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+    if hrp != RETAIL_ADDRESS_HRP {
+        return Err(AddressError::WrongPrefix(hrp));
+    }
+    if variant != Variant::Bech32m {
+        return Err(AddressError::ChecksumMismatch);
+    }
+
+    let program = Vec::<u8>::from_base32(&data).map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+    if program.len() != 20 {
+        return Err(AddressError::InvalidLength);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AddressError {
+    #[error("Address is empty")]
+    Empty,
+    #[error("Invalid encoding: {0}")]
+    InvalidEncoding(String),
+    #[error("Unexpected prefix {0}")]
+    WrongPrefix(String),
+    #[error("Invalid address length")]
+    InvalidLength,
+    #[error("Unsupported address version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+}
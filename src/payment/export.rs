@@ -0,0 +1,227 @@
+use crate::models::{Currency, Transaction, TransactionStatus};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+// Tỷ giá lưu trong giao dịch là so với USDT, nên giá trị fiat được ghi theo USD
+pub const FIAT_CURRENCY: &str = "USD";
+pub const SALES_ACCOUNT: &str = "Revenue:Sales";
+pub const SALES_RETURNS_ACCOUNT: &str = "Revenue:Sales Returns";
+pub const PAYMENT_FEES_ACCOUNT: &str = "Expenses:Payment Fees";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EntryKind {
+    Payment,
+    Refund,
+}
+
+// Một dòng giao dịch trong file CSV xuất cho kế toán
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    pub timestamp: DateTime<Utc>,
+    pub transaction_id: Uuid,
+    pub kind: EntryKind,
+    pub refund_of: Option<Uuid>,
+    pub from_address: String,
+    pub to_address: String,
+    pub currency: Currency,
+    pub amount: f64,
+    pub fee: f64,
+    pub exchange_rate: f64,
+    pub fiat_amount: f64,
+    pub fiat_fee: f64,
+    pub status: TransactionStatus,
+}
+
+// Một dòng bút toán kép; mỗi giao dịch sinh ra các dòng có tổng nợ bằng tổng có
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalLine {
+    pub date: NaiveDate,
+    pub transaction_id: Uuid,
+    pub account: String,
+    pub currency: Currency,
+    pub debit: f64,
+    pub credit: f64,
+    pub fiat_debit: f64,
+    pub fiat_credit: f64,
+    pub memo: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountingExport {
+    pub merchant_address: String,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub rows: Vec<ExportRow>,
+}
+
+impl AccountingExport {
+    // Khoảng ngày [start, end] tính cả hai đầu; bỏ qua giao dịch đang chờ hoặc thất bại
+    pub fn build<'a>(
+        merchant_address: &str,
+        start: NaiveDate,
+        end: NaiveDate,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Self {
+        let mut rows: Vec<ExportRow> = transactions.into_iter()
+            .filter(|transaction| {
+                let date = transaction.timestamp.date_naive();
+                date >= start && date <= end
+            })
+            .filter(|transaction| {
+                !matches!(transaction.status, TransactionStatus::Pending | TransactionStatus::Failed)
+            })
+            .filter_map(|transaction| {
+                let kind = if transaction.refund_of.is_none() && transaction.to_address == merchant_address {
+                    EntryKind::Payment
+                } else if transaction.refund_of.is_some() && transaction.from_address == merchant_address {
+                    EntryKind::Refund
+                } else {
+                    return None;
+                };
+
+                Some(ExportRow {
+                    timestamp: transaction.timestamp,
+                    transaction_id: transaction.id,
+                    kind,
+                    refund_of: transaction.refund_of,
+                    from_address: transaction.from_address.clone(),
+                    to_address: transaction.to_address.clone(),
+                    currency: transaction.currency.clone(),
+                    amount: transaction.amount,
+                    fee: transaction.fee,
+                    exchange_rate: transaction.exchange_rate,
+                    fiat_amount: transaction.amount * transaction.exchange_rate,
+                    fiat_fee: transaction.fee * transaction.exchange_rate,
+                    status: transaction.status.clone(),
+                })
+            })
+            .collect();
+        rows.sort_by_key(|row| (row.timestamp, row.transaction_id));
+
+        Self {
+            merchant_address: merchant_address.to_string(),
+            start,
+            end,
+            rows,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "timestamp,transaction_id,type,refund_of,from_address,to_address,currency,amount,fee,exchange_rate,fiat_currency,fiat_amount,fiat_fee,status\n",
+        );
+        for row in &self.rows {
+            let fields = [
+                row.timestamp.to_rfc3339(),
+                row.transaction_id.to_string(),
+                format!("{:?}", row.kind),
+                row.refund_of.map(|id| id.to_string()).unwrap_or_default(),
+                row.from_address.clone(),
+                row.to_address.clone(),
+                row.currency.symbol(),
+                row.amount.to_string(),
+                row.fee.to_string(),
+                row.exchange_rate.to_string(),
+                FIAT_CURRENCY.to_string(),
+                format_fiat(row.fiat_amount),
+                format_fiat(row.fiat_fee),
+                format!("{:?}", row.status),
+            ];
+            push_csv_record(&mut csv, &fields);
+        }
+        csv
+    }
+
+    // Thanh toán: Nợ tài sản (số nhận thực) + Nợ phí / Có doanh thu.
+    // Hoàn tiền: Nợ hàng bán bị trả lại / Có tài sản
+    pub fn journal_lines(&self) -> Vec<JournalLine> {
+        let mut lines = Vec::new();
+
+        for row in &self.rows {
+            let date = row.timestamp.date_naive();
+            let asset_account = asset_account(&row.currency);
+            let line = |account: &str, debit: f64, credit: f64, memo: String| JournalLine {
+                date,
+                transaction_id: row.transaction_id,
+                account: account.to_string(),
+                currency: row.currency.clone(),
+                debit,
+                credit,
+                fiat_debit: debit * row.exchange_rate,
+                fiat_credit: credit * row.exchange_rate,
+                memo,
+            };
+
+            match row.kind {
+                EntryKind::Payment => {
+                    let memo = format!("Payment from {}", row.from_address);
+                    lines.push(line(&asset_account, row.amount - row.fee, 0.0, memo.clone()));
+                    if row.fee > 0.0 {
+                        lines.push(line(PAYMENT_FEES_ACCOUNT, row.fee, 0.0, memo.clone()));
+                    }
+                    lines.push(line(SALES_ACCOUNT, 0.0, row.amount, memo));
+                }
+                EntryKind::Refund => {
+                    let memo = match row.refund_of {
+                        Some(original_id) => format!("Refund of {} to {}", original_id, row.to_address),
+                        None => format!("Refund to {}", row.to_address),
+                    };
+                    lines.push(line(SALES_RETURNS_ACCOUNT, row.amount, 0.0, memo.clone()));
+                    lines.push(line(&asset_account, 0.0, row.amount, memo));
+                }
+            }
+        }
+
+        lines
+    }
+
+    pub fn journal_to_csv(&self) -> String {
+        let mut csv = String::from(
+            "date,transaction_id,account,currency,debit,credit,fiat_currency,fiat_debit,fiat_credit,memo\n",
+        );
+        for line in self.journal_lines() {
+            let fields = [
+                line.date.to_string(),
+                line.transaction_id.to_string(),
+                line.account,
+                line.currency.symbol(),
+                line.debit.to_string(),
+                line.credit.to_string(),
+                FIAT_CURRENCY.to_string(),
+                format_fiat(line.fiat_debit),
+                format_fiat(line.fiat_credit),
+                line.memo,
+            ];
+            push_csv_record(&mut csv, &fields);
+        }
+        csv
+    }
+}
+
+pub fn asset_account(currency: &Currency) -> String {
+    match currency {
+        Currency::CASH => "Assets:Cash".to_string(),
+        Currency::RETAIL(symbol) => format!("Assets:Tokens:{}", symbol),
+        _ => format!("Assets:Crypto:{}", currency.symbol()),
+    }
+}
+
+fn format_fiat(amount: f64) -> String {
+    format!("{:.2}", amount)
+}
+
+// RFC 4180: trường chứa dấu phẩy, ngoặc kép hoặc xuống dòng được bọc trong ngoặc kép
+fn push_csv_record(csv: &mut String, fields: &[String]) {
+    let escaped: Vec<String> = fields.iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    csv.push_str(&escaped.join(","));
+    csv.push('\n');
+}
@@ -0,0 +1,63 @@
+use crate::models::Currency;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeRule {
+    // Phí cố định, tính theo đơn vị của tiền tệ thanh toán
+    pub flat: f64,
+    // Phí theo phần trăm, ví dụ 1.5 nghĩa là 1.5%
+    pub percentage: f64,
+}
+
+impl FeeRule {
+    pub fn new(flat: f64, percentage: f64) -> Self {
+        Self { flat, percentage }
+    }
+}
+
+// Phí cố định có đơn vị tiền tệ nên chỉ khai báo được theo từng loại tiền;
+// loại tiền không có quy tắc riêng chỉ chịu phí phần trăm mặc định
+#[derive(Debug, Clone, Default)]
+pub struct FeeSchedule {
+    default_percentage: f64,
+    currency_rules: HashMap<Currency, FeeRule>,
+    network_fee_estimates: HashMap<Currency, f64>,
+}
+
+impl FeeSchedule {
+    pub fn new(default_percentage: f64) -> Self {
+        Self {
+            default_percentage,
+            currency_rules: HashMap::new(),
+            network_fee_estimates: HashMap::new(),
+        }
+    }
+
+    pub fn with_currency_rule(mut self, currency: Currency, rule: FeeRule) -> Self {
+        self.currency_rules.insert(currency, rule);
+        self
+    }
+
+    pub fn with_network_fee(mut self, currency: Currency, estimate: f64) -> Self {
+        self.network_fee_estimates.insert(currency, estimate);
+        self
+    }
+
+    pub fn get_rule(&self, currency: &Currency) -> FeeRule {
+        self.currency_rules.get(currency)
+            .cloned()
+            .unwrap_or_else(|| FeeRule::new(0.0, self.default_percentage))
+    }
+
+    pub fn get_network_fee(&self, currency: &Currency) -> f64 {
+        self.network_fee_estimates.get(currency).copied().unwrap_or(0.0)
+    }
+
+    // Phí không bao giờ vượt quá số tiền thanh toán
+    pub fn calculate_fee(&self, amount: f64, currency: &Currency) -> f64 {
+        let rule = self.get_rule(currency);
+        let fee = rule.flat + amount * rule.percentage / 100.0 + self.get_network_fee(currency);
+        fee.clamp(0.0, amount)
+    }
+}
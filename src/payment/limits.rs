@@ -0,0 +1,50 @@
+use crate::models::Currency;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+// Hạn mức cuốn chiếu: 24 giờ và 30 ngày tính ngược từ thời điểm thanh toán
+pub const DAILY_WINDOW_HOURS: i64 = 24;
+pub const MONTHLY_WINDOW_DAYS: i64 = 30;
+
+// Hạn mức chi tiêu của một địa chỉ cho một loại tiền, tính theo tiền tệ gốc
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendingLimit {
+    pub base_currency: Currency,
+    pub per_transaction: Option<f64>,
+    pub daily: Option<f64>,
+    pub monthly: Option<f64>,
+}
+
+impl SpendingLimit {
+    pub fn new(base_currency: Currency) -> Self {
+        Self {
+            base_currency,
+            per_transaction: None,
+            daily: None,
+            monthly: None,
+        }
+    }
+
+    pub fn with_per_transaction(mut self, limit: f64) -> Self {
+        self.per_transaction = Some(limit);
+        self
+    }
+
+    pub fn with_daily_cap(mut self, limit: f64) -> Self {
+        self.daily = Some(limit);
+        self
+    }
+
+    pub fn with_monthly_cap(mut self, limit: f64) -> Self {
+        self.monthly = Some(limit);
+        self
+    }
+
+    pub fn daily_window() -> Duration {
+        Duration::hours(DAILY_WINDOW_HOURS)
+    }
+
+    pub fn monthly_window() -> Duration {
+        Duration::days(MONTHLY_WINDOW_DAYS)
+    }
+}
@@ -1,11 +1,72 @@
-use crate::models::{Transaction, TransactionStatus, Currency, RetailToken};
+pub mod address;
+pub mod export;
+pub mod fees;
+pub mod limits;
+pub mod query;
+pub mod request;
+pub mod settlement;
+
+use crate::models::{Transaction, TransactionStatus, Currency};
+use crate::inventory::{InventoryManager, InventoryError};
+use crate::loyalty::{LoyaltyProgram, LoyaltyError, PurchaseLine};
+use crate::wallet::{Wallet, WalletError};
+use address::{validate_address, AddressError};
+use crate::risk::{format_reasons, PaymentContext, ReviewItem, RiskAction, RiskAssessment, RiskEngine, RiskReason, RiskRules};
+use export::AccountingExport;
+use fees::FeeSchedule;
+use limits::SpendingLimit;
+use query::{TransactionPage, TransactionQuery};
+use request::{PaymentRequest, PaymentRequestStatus, parse_payment_uri};
+use settlement::SettlementReport;
 use uuid::Uuid;
-use chrono::Utc;
-use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::{HashMap, HashSet};
+
+// Sai số cho phép khi so sánh số tiền kiểu f64
+const AMOUNT_EPSILON: f64 = 1e-9;
+// Sai số làm tròn khi cộng các hình thức thanh toán của một đơn hàng
+const TENDER_ROUNDING_TOLERANCE: f64 = 0.01;
 
 pub struct PaymentProcessor {
     transactions: HashMap<Uuid, Transaction>,
     exchange_rates: HashMap<Currency, f64>,
+    refunded_amounts: HashMap<Uuid, f64>,
+    idempotency_keys: HashMap<String, Uuid>,
+    fee_schedule: FeeSchedule,
+    payment_requests: HashMap<Uuid, PaymentRequest>,
+    risk_engine: RiskEngine,
+    spending_limits: HashMap<(String, Currency), SpendingLimit>,
+    // Thanh toán dùng điểm đang chờ duyệt: điểm được cộng hoặc hoàn lại khi có kết quả duyệt
+    pending_loyalty: HashMap<Uuid, PendingLoyalty>,
+}
+
+struct PendingLoyalty {
+    customer: String,
+    paid_value: f64,
+    points_used: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tender {
+    pub from_address: String,
+    // Mỗi loại tiền có định dạng địa chỉ nhận riêng
+    pub to_address: String,
+    pub amount: f64,
+    pub currency: Currency,
+}
+
+impl Tender {
+    pub fn new(from_address: String, to_address: String, amount: f64, currency: Currency) -> Self {
+        Self { from_address, to_address, amount, currency }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundMethod {
+    // Trả lại đúng số coin đã nhận
+    OriginalCurrency,
+    // Trả lại giá trị fiat ban đầu, quy đổi sang coin gốc theo tỷ giá hiện tại
+    FiatValue,
 }
 
 impl PaymentProcessor {
@@ -14,19 +75,20 @@ impl PaymentProcessor {
         rates.insert(Currency::BTC, 45000.0);
         rates.insert(Currency::ETH, 3000.0);
         rates.insert(Currency::USDT, 1.0);
+        rates.insert(Currency::CASH, 1.0);
         
-        rates.insert(
-            Currency::RETAIL(RetailToken {
-                symbol: "RETAIL".to_string(),
-                amount: 0.0,
-                loyalty_points: 0,
-            }), 
-            1.0
-        );
+        rates.insert(Currency::RETAIL("RETAIL".to_string()), 1.0);
         
         Self {
             transactions: HashMap::new(),
             exchange_rates: rates,
+            refunded_amounts: HashMap::new(),
+            idempotency_keys: HashMap::new(),
+            fee_schedule: FeeSchedule::default(),
+            payment_requests: HashMap::new(),
+            risk_engine: RiskEngine::default(),
+            spending_limits: HashMap::new(),
+            pending_loyalty: HashMap::new(),
         }
     }
 
@@ -37,23 +99,171 @@ impl PaymentProcessor {
         amount: f64,
         currency: Currency,
     ) -> Result<Transaction, PaymentError> {
-        self.validate_payment(from_address.as_str(), amount, &currency)?;
+        self.process_payment_at(from_address, to_address, amount, currency, Utc::now())
+    }
+
+    // Thanh toán tại một thời điểm cho trước (ví dụ lần đến hạn của thanh toán định kỳ):
+    // hạn mức và quy tắc rủi ro được tính theo thời điểm này thay vì đồng hồ hệ thống
+    pub fn process_payment_at(
+        &mut self,
+        from_address: String,
+        to_address: String,
+        amount: f64,
+        currency: Currency,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Transaction, PaymentError> {
+        let (transaction, assessment) =
+            self.prepare_transaction(from_address, to_address, amount, currency, timestamp, &[])?;
+        self.record_transaction(&transaction, assessment);
+
+        Ok(transaction)
+    }
+
+    // Giao dịch chi tiền từ ví của cửa hàng, được ký trước khi ghi nhận
+    pub fn process_signed_payment(
+        &mut self,
+        wallet: &Wallet,
+        from_address: String,
+        to_address: String,
+        amount: f64,
+        currency: Currency,
+    ) -> Result<Transaction, PaymentError> {
+        let (mut transaction, assessment) =
+            self.prepare_transaction(from_address, to_address, amount, currency, Utc::now(), &[])?;
+        wallet.sign_transaction(&mut transaction)?;
+        self.record_transaction(&transaction, assessment);
+
+        Ok(transaction)
+    }
+
+    // Thanh toán một đơn hàng bằng nhiều hình thức; ghi nhận tất cả giao dịch hoặc không ghi gì
+    pub fn process_order_payment(
+        &mut self,
+        order_total: f64,
+        order_currency: &Currency,
+        tenders: Vec<Tender>,
+    ) -> Result<Vec<Transaction>, PaymentError> {
+        if tenders.is_empty() || order_total <= 0.0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        let mut received = 0.0;
+        for tender in &tenders {
+            received += self.convert_currency(tender.amount, &tender.currency, order_currency)?;
+        }
+        if (received - order_total).abs() > TENDER_ROUNDING_TOLERANCE {
+            return Err(PaymentError::TenderMismatch { expected: order_total, received });
+        }
+
+        // Mỗi hình thức thanh toán được đánh giá cùng các hình thức trước đó của đơn, dù chưa ghi nhận
+        let timestamp = Utc::now();
+        let mut transactions: Vec<Transaction> = Vec::with_capacity(tenders.len());
+        let mut assessments = Vec::with_capacity(tenders.len());
+        for tender in tenders {
+            let (transaction, assessment) = self.prepare_transaction(
+                tender.from_address,
+                tender.to_address,
+                tender.amount,
+                tender.currency,
+                timestamp,
+                &transactions,
+            )?;
+            transactions.push(transaction);
+            assessments.push(assessment);
+        }
+
+        for (transaction, assessment) in transactions.iter().zip(assessments) {
+            self.record_transaction(transaction, assessment);
+        }
+        println!("🧾 Order paid with {} tenders: {} {:?}", transactions.len(), order_total, order_currency);
+
+        Ok(transactions)
+    }
+
+    // `pending` là các giao dịch đã chuẩn bị nhưng chưa ghi nhận của cùng đơn hàng
+    fn prepare_transaction(
+        &self,
+        from_address: String,
+        to_address: String,
+        amount: f64,
+        currency: Currency,
+        timestamp: DateTime<Utc>,
+        pending: &[Transaction],
+    ) -> Result<(Transaction, RiskAssessment), PaymentError> {
+        let assessment = self.validate_payment(&from_address, &to_address, amount, &currency, timestamp, pending)?;
+        let exchange_rate = self.get_exchange_rate(&currency)
+            .ok_or(PaymentError::UnsupportedCurrency)?;
+        let fee = self.fee_schedule.calculate_fee(amount, &currency);
+
+        // Giao dịch cần xem xét được giữ ở trạng thái Pending cho tới khi có người duyệt
+        let status = if assessment.action == RiskAction::Review {
+            TransactionStatus::Pending
+        } else {
+            TransactionStatus::Completed
+        };
 
         let transaction = Transaction {
             id: Uuid::new_v4(),
-            from_address: from_address.clone(),
-            to_address: to_address.clone(),
+            from_address,
+            to_address,
             amount,
-            currency: currency.clone(),
-            timestamp: Utc::now(),
-            status: TransactionStatus::Completed,
+            currency,
+            timestamp,
+            status,
+            exchange_rate,
+            refund_of: None,
+            fee,
+            signature: None,
         };
+        Ok((transaction, assessment))
+    }
 
+    fn record_transaction(&mut self, transaction: &Transaction, assessment: RiskAssessment) {
         self.transactions.insert(transaction.id, transaction.clone());
-        
-        println!("✅ Payment processed: {} {:?} from {} to {}", 
-                 amount, currency, from_address, to_address);
-        
+        // Giao dịch 0 đồng (đơn trả hết bằng điểm) không tính vào tần suất thanh toán
+        if transaction.amount > 0.0 {
+            self.risk_engine.record_payment(&transaction.from_address, transaction.timestamp);
+        }
+        if assessment.action == RiskAction::Review {
+            self.risk_engine.flag_for_review(transaction.id, assessment.reasons);
+        }
+
+        println!("✅ Payment processed: {} {:?} from {} to {} ({:?})", 
+                 transaction.amount, transaction.currency, transaction.from_address, transaction.to_address,
+                 transaction.status);
+    }
+
+    // POS gửi lại cùng key khi retry: trả về giao dịch cũ thay vì trừ tiền lần nữa
+    pub fn process_payment_idempotent(
+        &mut self,
+        idempotency_key: String,
+        from_address: String,
+        to_address: String,
+        amount: f64,
+        currency: Currency,
+    ) -> Result<Transaction, PaymentError> {
+        if idempotency_key.is_empty() {
+            return Err(PaymentError::InvalidIdempotencyKey);
+        }
+
+        if let Some(existing_id) = self.idempotency_keys.get(&idempotency_key) {
+            let existing = self.transactions.get(existing_id)
+                .ok_or(PaymentError::TransactionNotFound)?;
+
+            let same_request = existing.from_address == from_address
+                && existing.to_address == to_address
+                && existing.amount == amount
+                && existing.currency == currency;
+            if !same_request {
+                return Err(PaymentError::IdempotencyConflict(idempotency_key));
+            }
+
+            println!("🔁 Idempotent replay for key {}", idempotency_key);
+            return Ok(existing.clone());
+        }
+
+        let transaction = self.process_payment(from_address, to_address, amount, currency)?;
+        self.idempotency_keys.insert(idempotency_key, transaction.id);
         Ok(transaction)
     }
 
@@ -78,70 +288,687 @@ impl PaymentProcessor {
     fn validate_payment(
         &self,
         from_address: &str,
+        to_address: &str,
         amount: f64,
-        _currency: &Currency,
-    ) -> Result<(), PaymentError> {
+        currency: &Currency,
+        timestamp: DateTime<Utc>,
+        pending: &[Transaction],
+    ) -> Result<RiskAssessment, PaymentError> {
         if amount <= 0.0 {
             return Err(PaymentError::InvalidAmount);
         }
 
-        if from_address.is_empty() {
-            return Err(PaymentError::InvalidAddress);
-        }
+        validate_address(from_address, currency).map_err(PaymentError::InvalidSenderAddress)?;
+        validate_address(to_address, currency).map_err(PaymentError::InvalidRecipientAddress)?;
+
+        self.check_spending_limits(from_address, amount, currency, timestamp, pending)?;
 
         let simulated_balance = 1000.0;
         if amount > simulated_balance {
             return Err(PaymentError::InsufficientFunds);
         }
 
+        self.assess_risk(from_address, to_address, amount, currency, timestamp, pending)
+    }
+
+    // Đánh giá quy tắc rủi ro; thanh toán bị từ chối thì trả lỗi, cần xem xét thì trả kết quả để gắn cờ
+    fn assess_risk(
+        &self,
+        from_address: &str,
+        to_address: &str,
+        amount: f64,
+        currency: &Currency,
+        timestamp: DateTime<Utc>,
+        pending: &[Transaction],
+    ) -> Result<RiskAssessment, PaymentError> {
+        let pending_from_sender = pending.iter()
+            .filter(|transaction| transaction.from_address == from_address)
+            .count();
+        let assessment = self.risk_engine.evaluate_with_pending(&PaymentContext {
+            from_address,
+            to_address,
+            amount,
+            currency,
+            timestamp,
+        }, pending_from_sender);
+        if assessment.action == RiskAction::Deny {
+            return Err(PaymentError::PaymentDenied(assessment.reasons));
+        }
+
+        Ok(assessment)
+    }
+
+    pub fn set_spending_limit(&mut self, address: String, currency: Currency, limit: SpendingLimit) {
+        self.spending_limits.insert((address, currency), limit);
+    }
+
+    pub fn get_spending_limit(&self, address: &str, currency: &Currency) -> Option<&SpendingLimit> {
+        self.spending_limits.get(&(address.to_string(), currency.clone()))
+    }
+
+    pub fn remove_spending_limit(&mut self, address: &str, currency: &Currency) -> Option<SpendingLimit> {
+        self.spending_limits.remove(&(address.to_string(), currency.clone()))
+    }
+
+    // Tổng đã chi (trừ phần đã hoàn) của địa chỉ bằng loại tiền này trong khoảng (since, until]
+    pub fn get_spent_amount(
+        &self,
+        address: &str,
+        currency: &Currency,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> f64 {
+        self.spent_transactions(address, currency, since, until)
+            .map(|(transaction, refunded)| transaction.amount - refunded)
+            .sum()
+    }
+
+    // Các giao dịch chi của địa chỉ trong khoảng (since, until], kèm phần đã hoàn
+    fn spent_transactions<'a>(
+        &'a self,
+        address: &'a str,
+        currency: &'a Currency,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> impl Iterator<Item = (&'a Transaction, f64)> + 'a {
+        self.transactions.values()
+            .filter(move |transaction| {
+                transaction.refund_of.is_none()
+                    && transaction.status != TransactionStatus::Failed
+                    && transaction.from_address == address
+                    && &transaction.currency == currency
+                    && transaction.timestamp > since
+                    && transaction.timestamp <= until
+            })
+            .map(|transaction| (transaction, self.refunded_amounts.get(&transaction.id).copied().unwrap_or(0.0)))
+    }
+
+    // Giá trị đã chi trong khoảng theo tiền tệ gốc của hạn mức. Mỗi giao dịch được quy đổi theo tỷ giá
+    // đã lưu lúc giao dịch; các phần trước đó của cùng đơn (`pending`) cũng được tính
+    fn spent_value(
+        &self,
+        address: &str,
+        currency: &Currency,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        pending: &[Transaction],
+        base_rate: f64,
+    ) -> f64 {
+        let recorded: f64 = self.spent_transactions(address, currency, since, until)
+            .map(|(transaction, refunded)| (transaction.amount - refunded) * transaction.exchange_rate)
+            .sum();
+        let in_order: f64 = pending.iter()
+            .filter(|transaction| transaction.from_address == address && &transaction.currency == currency)
+            .map(|transaction| transaction.amount * transaction.exchange_rate)
+            .sum();
+        (recorded + in_order) / base_rate
+    }
+
+    fn check_spending_limits(
+        &self,
+        from_address: &str,
+        amount: f64,
+        currency: &Currency,
+        timestamp: DateTime<Utc>,
+        pending: &[Transaction],
+    ) -> Result<(), PaymentError> {
+        let Some(limit) = self.get_spending_limit(from_address, currency) else {
+            return Ok(());
+        };
+        let base_currency = &limit.base_currency;
+        let attempted = self.convert_currency(amount, currency, base_currency)?;
+        let base_rate = self.get_exchange_rate(base_currency)
+            .ok_or(PaymentError::UnsupportedCurrency)?;
+
+        if let Some(max) = limit.per_transaction {
+            if attempted > max + AMOUNT_EPSILON {
+                return Err(PaymentError::TransactionLimitExceeded {
+                    currency: base_currency.clone(),
+                    limit: max,
+                    attempted,
+                });
+            }
+        }
+
+        if let Some(cap) = limit.daily {
+            let since = timestamp - SpendingLimit::daily_window();
+            let spent = self.spent_value(from_address, currency, since, timestamp, pending, base_rate);
+            if spent + attempted > cap + AMOUNT_EPSILON {
+                return Err(PaymentError::DailyLimitExceeded {
+                    currency: base_currency.clone(),
+                    limit: cap,
+                    spent,
+                    attempted,
+                });
+            }
+        }
+
+        if let Some(cap) = limit.monthly {
+            let since = timestamp - SpendingLimit::monthly_window();
+            let spent = self.spent_value(from_address, currency, since, timestamp, pending, base_rate);
+            if spent + attempted > cap + AMOUNT_EPSILON {
+                return Err(PaymentError::MonthlyLimitExceeded {
+                    currency: base_currency.clone(),
+                    limit: cap,
+                    spent,
+                    attempted,
+                });
+            }
+        }
+
         Ok(())
     }
 
+    pub fn set_risk_rules(&mut self, rules: RiskRules) {
+        self.risk_engine.set_rules(rules);
+    }
+
+    pub fn get_review_queue(&self) -> Vec<&ReviewItem> {
+        self.risk_engine.get_pending_reviews()
+    }
+
+    // Người duyệt chấp nhận hoặc từ chối giao dịch bị gắn cờ. Thanh toán có dùng điểm phải duyệt qua
+    // `resolve_flagged_payment_with_loyalty` để điểm được cộng hoặc hoàn lại
+    pub fn resolve_flagged_payment(
+        &mut self,
+        transaction_id: Uuid,
+        approved: bool,
+        reviewer: String,
+    ) -> Result<Transaction, PaymentError> {
+        if self.pending_loyalty.contains_key(&transaction_id) {
+            return Err(PaymentError::LoyaltyReviewRequired(transaction_id));
+        }
+        self.resolve_review(transaction_id, approved, reviewer)
+    }
+
+    // Duyệt thanh toán có dùng điểm: được chấp nhận thì cộng điểm cho phần đã trả,
+    // bị từ chối thì hoàn lại điểm đã đổi bằng một dòng điều chỉnh gắn với giao dịch
+    pub fn resolve_flagged_payment_with_loyalty(
+        &mut self,
+        loyalty: &mut LoyaltyProgram,
+        transaction_id: Uuid,
+        approved: bool,
+        reviewer: String,
+    ) -> Result<Transaction, PaymentError> {
+        let transaction = self.resolve_review(transaction_id, approved, reviewer)?;
+        let Some(pending) = self.pending_loyalty.remove(&transaction_id) else {
+            return Ok(transaction);
+        };
+
+        let now = Utc::now();
+        if approved {
+            let purchase = [PurchaseLine::new(None, pending.paid_value)];
+            if loyalty.calculate_points(&pending.customer, &purchase, now) > 0 {
+                loyalty.earn_points(&pending.customer, &purchase, Some(transaction_id), now)?;
+            }
+        } else if pending.points_used > 0 {
+            loyalty.adjust_points(
+                &pending.customer,
+                pending.points_used as i64,
+                "Redemption reversed: payment rejected".to_string(),
+                Some(transaction_id),
+                now,
+            )?;
+        }
+        Ok(transaction)
+    }
+
+    fn resolve_review(
+        &mut self,
+        transaction_id: Uuid,
+        approved: bool,
+        reviewer: String,
+    ) -> Result<Transaction, PaymentError> {
+        self.risk_engine.resolve_review(transaction_id, approved, reviewer)
+            .ok_or(PaymentError::ReviewNotFound)?;
+
+        let transaction = self.transactions.get_mut(&transaction_id)
+            .ok_or(PaymentError::TransactionNotFound)?;
+        transaction.status = if approved {
+            TransactionStatus::Completed
+        } else {
+            TransactionStatus::Failed
+        };
+
+        println!("🔎 Flagged payment {} {}", transaction_id, if approved { "approved" } else { "rejected" });
+        Ok(transaction.clone())
+    }
+
     #[allow(dead_code)]
     pub fn get_transaction(&self, id: Uuid) -> Option<&Transaction> {
         self.transactions.get(&id)
     }
 
+    pub fn search_transactions(&self, query: &TransactionQuery) -> Result<TransactionPage, PaymentError> {
+        query.execute(self.transactions.values())
+    }
+
     pub fn get_all_transactions(&self) -> Vec<&Transaction> {
         self.transactions.values().collect()
     }
 
+    // Khách dùng điểm tích lũy để giảm giá, phần còn lại trả bằng RETAIL và được cộng điểm.
+    // Điểm được trừ và cộng trước khi ghi nhận giao dịch, nên lỗi loyalty không để lại khoản đã trả.
+    // Giao dịch cần xem xét vẫn giữ điểm đã đổi nhưng chưa được cộng điểm cho tới khi được duyệt
     pub fn process_payment_with_loyalty(
         &mut self,
+        loyalty: &mut LoyaltyProgram,
         from_address: String,
         to_address: String,
         amount: f64,
         loyalty_points: u32,
     ) -> Result<Transaction, PaymentError> {
-        let retail_token = RetailToken {
-            symbol: "RETAIL".to_string(),
-            amount,
-            loyalty_points,
+        let now = Utc::now();
+        let redemption = if loyalty_points > 0 {
+            Some(loyalty.preview_redemption(&from_address, amount, loyalty_points as u64, now)?)
+        } else {
+            None
+        };
+        let amount_due = redemption.as_ref().map(|r| r.amount_due).unwrap_or(amount);
+        let points_used = redemption.as_ref().map(|r| r.points_used).unwrap_or(0);
+
+        let currency = Currency::RETAIL("RETAIL".to_string());
+        let (transaction, assessment) = if amount_due > AMOUNT_EPSILON {
+            self.prepare_transaction(from_address.clone(), to_address, amount_due, currency, now, &[])?
+        } else {
+            self.prepare_redemption_transaction(from_address.clone(), to_address, currency, now)?
+        };
+
+        let paid_value = transaction.amount * transaction.exchange_rate;
+        let purchase = [PurchaseLine::new(None, paid_value)];
+        // Số tiền quá nhỏ hoặc trả hết bằng điểm thì có thể không được cộng điểm
+        let earns_points = loyalty.calculate_points(&from_address, &purchase, now) > 0;
+        if points_used > 0 {
+            loyalty.redeem_points(&from_address, amount, points_used, Some(transaction.id), now)?;
+        }
+        match transaction.status {
+            TransactionStatus::Completed if earns_points => {
+                loyalty.earn_points(&from_address, &purchase, Some(transaction.id), now)?;
+            }
+            TransactionStatus::Pending if points_used > 0 || earns_points => {
+                self.pending_loyalty.insert(transaction.id, PendingLoyalty {
+                    customer: from_address,
+                    paid_value,
+                    points_used,
+                });
+            }
+            _ => {}
+        }
+        self.record_transaction(&transaction, assessment);
+
+        Ok(transaction)
+    }
+
+    // Đơn được trả hết bằng điểm: không có phần thanh toán bằng coin, chỉ ghi một giao dịch 0 đồng
+    // để lần đổi điểm có mã tham chiếu. Quy tắc rủi ro vẫn áp dụng, nên địa chỉ bị chặn không đổi được điểm
+    fn prepare_redemption_transaction(
+        &self,
+        from_address: String,
+        to_address: String,
+        currency: Currency,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(Transaction, RiskAssessment), PaymentError> {
+        validate_address(&from_address, &currency).map_err(PaymentError::InvalidSenderAddress)?;
+        validate_address(&to_address, &currency).map_err(PaymentError::InvalidRecipientAddress)?;
+        let assessment = self.assess_risk(&from_address, &to_address, 0.0, &currency, timestamp, &[])?;
+        let exchange_rate = self.get_exchange_rate(&currency)
+            .ok_or(PaymentError::UnsupportedCurrency)?;
+        let status = if assessment.action == RiskAction::Review {
+            TransactionStatus::Pending
+        } else {
+            TransactionStatus::Completed
+        };
+
+        let transaction = Transaction {
+            id: Uuid::new_v4(),
+            from_address,
+            to_address,
+            amount: 0.0,
+            currency,
+            timestamp,
+            status,
+            exchange_rate,
+            refund_of: None,
+            fee: 0.0,
+            signature: None,
         };
+        Ok((transaction, assessment))
+    }
 
-        self.process_payment(from_address, to_address, amount, Currency::RETAIL(retail_token))
+    // Tìm loại tiền theo ký hiệu trong các loại tiền có tỷ giá, gồm cả token RETAIL đã đăng ký
+    pub fn find_currency(&self, symbol: &str) -> Option<Currency> {
+        Currency::from_symbol(symbol).or_else(|| {
+            self.exchange_rates.keys()
+                .find(|currency| matches!(currency, Currency::RETAIL(token) if token.eq_ignore_ascii_case(symbol.trim())))
+                .cloned()
+        })
     }
 
     pub fn get_exchange_rate(&self, currency: &Currency) -> Option<f64> {
         self.exchange_rates.get(currency).copied()
     }
 
+    pub fn set_exchange_rate(&mut self, currency: Currency, rate: f64) -> Result<(), PaymentError> {
+        if rate <= 0.0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        self.exchange_rates.insert(currency, rate);
+        Ok(())
+    }
+
+    // `amount` là phần của giao dịch gốc được hoàn, tính theo đơn vị coin gốc
+    pub fn refund_payment(
+        &mut self,
+        original_id: Uuid,
+        amount: f64,
+        method: RefundMethod,
+    ) -> Result<Transaction, PaymentError> {
+        let refund = self.prepare_refund(original_id, amount, method)?;
+        self.record_refund(&refund, amount);
+        Ok(refund)
+    }
+
+    // Kiểm tra và tạo giao dịch hoàn tiền, chưa ghi nhận gì
+    fn prepare_refund(
+        &self,
+        original_id: Uuid,
+        amount: f64,
+        method: RefundMethod,
+    ) -> Result<Transaction, PaymentError> {
+        let original = self.transactions.get(&original_id)
+            .ok_or(PaymentError::TransactionNotFound)?;
+
+        if original.refund_of.is_some() {
+            return Err(PaymentError::NotRefundable);
+        }
+        if !matches!(original.status, TransactionStatus::Completed | TransactionStatus::PartiallyRefunded) {
+            return Err(PaymentError::NotRefundable);
+        }
+        if amount <= 0.0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        let already_refunded = self.refunded_amounts.get(&original_id).copied().unwrap_or(0.0);
+        let remaining = original.amount - already_refunded;
+        if amount > remaining + AMOUNT_EPSILON {
+            return Err(PaymentError::RefundExceedsPayment { remaining });
+        }
+
+        let current_rate = self.get_exchange_rate(&original.currency)
+            .ok_or(PaymentError::UnsupportedCurrency)?;
+        let refund_amount = match method {
+            RefundMethod::OriginalCurrency => amount,
+            RefundMethod::FiatValue => amount * original.exchange_rate / current_rate,
+        };
+
+        Ok(Transaction {
+            id: Uuid::new_v4(),
+            from_address: original.to_address.clone(),
+            to_address: original.from_address.clone(),
+            amount: refund_amount,
+            currency: original.currency.clone(),
+            timestamp: Utc::now(),
+            status: TransactionStatus::Completed,
+            exchange_rate: current_rate,
+            refund_of: Some(original_id),
+            fee: 0.0,
+            signature: None,
+        })
+    }
+
+    // `amount` như trong `refund_payment`, theo đơn vị coin của giao dịch gốc
+    fn record_refund(&mut self, refund: &Transaction, amount: f64) {
+        let Some(original_id) = refund.refund_of else {
+            return;
+        };
+        let total_refunded = self.refunded_amounts.get(&original_id).copied().unwrap_or(0.0) + amount;
+        if let Some(original) = self.transactions.get_mut(&original_id) {
+            let fully_refunded = total_refunded >= original.amount - AMOUNT_EPSILON;
+            original.status = if fully_refunded {
+                TransactionStatus::Refunded
+            } else {
+                TransactionStatus::PartiallyRefunded
+            };
+        }
+        self.refunded_amounts.insert(original_id, total_refunded);
+        self.transactions.insert(refund.id, refund.clone());
+
+        println!("↩️  Refund processed: {} {:?} to {} (original {})",
+                 refund.amount, refund.currency, refund.to_address, original_id);
+    }
+
+    // Hoàn tiền và nhập lại hàng vào kho. Hàng được nhập lại trong một lần ghi trước khi ghi nhận
+    // hoàn tiền, nên lỗi kho không để lại khoản hoàn tiền hay hàng nhập dở dang
+    pub fn refund_payment_with_restock(
+        &mut self,
+        original_id: Uuid,
+        amount: f64,
+        method: RefundMethod,
+        inventory: &mut InventoryManager,
+        location_id: &str,
+        items: &[(Uuid, u32)],
+    ) -> Result<Transaction, PaymentError> {
+        let refund = self.prepare_refund(original_id, amount, method)?;
+        inventory.restock_returns(location_id, items, &refund.from_address, Some(refund.id.to_string()))?;
+        self.record_refund(&refund, amount);
+
+        Ok(refund)
+    }
+
+    pub fn get_refundable_amount(&self, original_id: Uuid) -> Option<f64> {
+        let original = self.transactions.get(&original_id)?;
+        let refunded = self.refunded_amounts.get(&original_id).copied().unwrap_or(0.0);
+        Some(original.amount - refunded)
+    }
+
+    pub fn get_refunds(&self, original_id: Uuid) -> Vec<&Transaction> {
+        self.transactions.values()
+            .filter(|transaction| transaction.refund_of == Some(original_id))
+            .collect()
+    }
+
+    // Tổng đã nhận sau khi trừ các khoản hoàn tiền
     #[allow(dead_code)]
     pub fn get_total_processed_amount(&self) -> f64 {
         self.transactions.values()
-            .map(|transaction| transaction.amount)
+            .map(|transaction| match transaction.refund_of {
+                Some(_) => -transaction.amount,
+                None => transaction.amount,
+            })
             .sum()
     }
+
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.fee_schedule = fee_schedule;
+    }
+
+    pub fn get_fee_schedule(&self) -> &FeeSchedule {
+        &self.fee_schedule
+    }
+
+    pub fn settlement_report(&self, merchant_address: &str, date: NaiveDate) -> SettlementReport {
+        SettlementReport::build(merchant_address, date, self.transactions.values())
+    }
+
+    pub fn accounting_export(&self, merchant_address: &str, start: NaiveDate, end: NaiveDate) -> AccountingExport {
+        AccountingExport::build(merchant_address, start, end, self.transactions.values())
+    }
+
+    pub fn create_payment_request(
+        &mut self,
+        address: String,
+        amount: f64,
+        currency: Currency,
+        reference: String,
+        label: Option<String>,
+    ) -> Result<PaymentRequest, PaymentError> {
+        if self.get_exchange_rate(&currency).is_none() {
+            return Err(PaymentError::UnsupportedCurrency);
+        }
+
+        let request = PaymentRequest::new(address, amount, currency, reference, label)?;
+        // Hai yêu cầu đang mở không mang reference trên cùng một địa chỉ thì không phân biệt được
+        let address_in_use = !request.uri_has_reference() && self.payment_requests.values().any(|open| {
+            !open.is_paid() && open.address == request.address && open.currency == request.currency
+        });
+        if address_in_use {
+            return Err(PaymentError::AddressInUse(request.address));
+        }
+        self.payment_requests.insert(request.id, request.clone());
+        println!("🧾 Payment request {} created: {}", request.reference, request.to_uri());
+
+        Ok(request)
+    }
+
+    // Yêu cầu thanh toán nhận tiền vào một địa chỉ mới sinh từ ví cho riêng đơn này,
+    // để giao dịch tới địa chỉ đó luôn khớp đúng yêu cầu kể cả khi URI không mang reference
+    pub fn create_order_payment_request(
+        &mut self,
+        wallet: &mut Wallet,
+        amount: f64,
+        currency: Currency,
+        reference: String,
+        label: Option<String>,
+    ) -> Result<PaymentRequest, PaymentError> {
+        if self.get_exchange_rate(&currency).is_none() {
+            return Err(PaymentError::UnsupportedCurrency);
+        }
+        let derived = wallet.new_order_address(&reference, &currency)?;
+        self.create_payment_request(derived.address, amount, currency, reference, label)
+    }
+
+    pub fn get_payment_request(&self, id: Uuid) -> Option<&PaymentRequest> {
+        self.payment_requests.get(&id)
+    }
+
+    pub fn find_payment_request(&self, reference: &str) -> Option<&PaymentRequest> {
+        self.payment_requests.values().find(|request| request.reference == reference)
+    }
+
+    // Tìm yêu cầu thanh toán từ URI khách quét/gửi lại: ưu tiên reference, sau đó địa chỉ + loại tiền
+    pub fn find_payment_request_by_uri(&self, uri: &str) -> Result<Option<&PaymentRequest>, PaymentError> {
+        let parsed = parse_payment_uri(uri)?;
+        if let Some(reference) = &parsed.reference {
+            return Ok(self.find_payment_request(reference));
+        }
+
+        Ok(self.payment_requests.values()
+            .filter(|request| request.address == parsed.address && request.currency == parsed.currency)
+            .filter(|request| parsed.amount.is_none_or(|amount| (amount - request.amount).abs() < 1e-9))
+            .min_by_key(|request| request.created_at))
+    }
+
+    // Đánh dấu yêu cầu thanh toán cũ nhất khớp với giao dịch vừa nhận là đã trả.
+    // Giao dịch đang chờ duyệt không được tính cho tới khi được chấp nhận
+    pub fn reconcile_payment_request(&mut self, transaction: &Transaction) -> Option<PaymentRequest> {
+        let id = self.find_matching_request(transaction)?;
+        Some(self.mark_request_paid(id, transaction))
+    }
+
+    // Như `reconcile_payment_request`, đồng thời bán phần hàng đang giữ cho đơn của yêu cầu thanh toán (nếu có).
+    // Hàng được bán trước khi đánh dấu yêu cầu đã trả: giữ chỗ đã hết hạn hoặc không bán được thì trả lỗi và
+    // yêu cầu vẫn mở, để cửa hàng giữ lại hàng (hoặc hoàn tiền) rồi đối soát lại
+    pub fn reconcile_payment_request_with_reservations(
+        &mut self,
+        transaction: &Transaction,
+        inventory: &mut InventoryManager,
+    ) -> Result<Option<PaymentRequest>, PaymentError> {
+        let Some(id) = self.find_matching_request(transaction) else {
+            return Ok(None);
+        };
+        let request = &self.payment_requests[&id];
+        if !inventory.get_reservations(&request.reference).is_empty() {
+            inventory.commit_reservation(&request.reference, &request.address)?;
+        }
+        Ok(Some(self.mark_request_paid(id, transaction)))
+    }
+
+    // Yêu cầu đang mở cũ nhất khớp với giao dịch
+    fn find_matching_request(&self, transaction: &Transaction) -> Option<Uuid> {
+        self.payment_requests.values()
+            .filter(|request| request.matches_transaction(transaction))
+            .min_by_key(|request| request.created_at)
+            .map(|request| request.id)
+    }
+
+    fn mark_request_paid(&mut self, id: Uuid, transaction: &Transaction) -> PaymentRequest {
+        let request = self.payment_requests.get_mut(&id).expect("payment request exists");
+        request.status = PaymentRequestStatus::Paid(transaction.id);
+        println!("✅ Payment request {} paid by {}", request.reference, transaction.id);
+        request.clone()
+    }
+
+    // Một báo cáo cho mỗi cửa hàng có nhận tiền hoặc hoàn tiền trong ngày
+    pub fn daily_settlement_reports(&self, date: NaiveDate) -> Vec<SettlementReport> {
+        let merchants: HashSet<&str> = self.transactions.values()
+            .filter(|transaction| transaction.timestamp.date_naive() == date)
+            .map(|transaction| match transaction.refund_of {
+                Some(_) => transaction.from_address.as_str(),
+                None => transaction.to_address.as_str(),
+            })
+            .collect();
+
+        let mut reports: Vec<SettlementReport> = merchants.into_iter()
+            .map(|merchant| self.settlement_report(merchant, date))
+            .collect();
+        reports.sort_by(|a, b| a.merchant_address.cmp(&b.merchant_address));
+        reports
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum PaymentError {
     #[error("Invalid amount")]
     InvalidAmount,
-    #[error("Invalid address")]
-    InvalidAddress,
+    #[error("Invalid sender address: {0}")]
+    InvalidSenderAddress(AddressError),
+    #[error("Invalid recipient address: {0}")]
+    InvalidRecipientAddress(AddressError),
     #[error("Insufficient funds")]
     InsufficientFunds,
     #[error("Unsupported currency")]
     UnsupportedCurrency,
+    #[error("Unknown currency {0}")]
+    UnknownCurrency(String),
+    #[error("Tenders total {received} does not match order total {expected}")]
+    TenderMismatch { expected: f64, received: f64 },
+    #[error("Invalid payment URI")]
+    InvalidPaymentUri,
+    #[error("QR code error: {0}")]
+    QrCode(String),
+    #[error("Address {0} already has an open payment request")]
+    AddressInUse(String),
+    #[error("Per-transaction limit of {limit} {} exceeded: attempted {attempted}", .currency.symbol())]
+    TransactionLimitExceeded { currency: Currency, limit: f64, attempted: f64 },
+    #[error("Daily limit of {limit} {} exceeded: {spent} already spent, attempted {attempted}", .currency.symbol())]
+    DailyLimitExceeded { currency: Currency, limit: f64, spent: f64, attempted: f64 },
+    #[error("Monthly limit of {limit} {} exceeded: {spent} already spent, attempted {attempted}", .currency.symbol())]
+    MonthlyLimitExceeded { currency: Currency, limit: f64, spent: f64, attempted: f64 },
+    #[error("Payment denied by risk rules: {}", format_reasons(.0))]
+    PaymentDenied(Vec<RiskReason>),
+    #[error("No pending review for this transaction")]
+    ReviewNotFound,
+    #[error("Payment {0} used loyalty points and must be resolved together with the loyalty program")]
+    LoyaltyReviewRequired(Uuid),
+    #[error("Invalid pagination cursor")]
+    InvalidCursor,
+    #[error("Page size {0} is out of range")]
+    InvalidPageSize(usize),
+    #[error("Transaction not found")]
+    TransactionNotFound,
+    #[error("Transaction cannot be refunded")]
+    NotRefundable,
+    #[error("Refund exceeds remaining refundable amount ({remaining})")]
+    RefundExceedsPayment { remaining: f64 },
+    #[error("Invalid idempotency key")]
+    InvalidIdempotencyKey,
+    #[error("Idempotency key {0} was already used with different parameters")]
+    IdempotencyConflict(String),
+    #[error("Loyalty error: {0}")]
+    Loyalty(#[from] LoyaltyError),
+    #[error("Wallet error: {0}")]
+    Wallet(#[from] WalletError),
+    #[error("Inventory error: {0}")]
+    Inventory(#[from] InventoryError),
 }
\ No newline at end of file
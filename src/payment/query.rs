@@ -0,0 +1,127 @@
+use crate::models::{Currency, Transaction, TransactionStatus};
+use super::PaymentError;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+// Bộ lọc giao dịch; các điều kiện để trống thì không lọc. Khoảng thời gian là [since, until)
+#[derive(Debug, Clone, Default)]
+pub struct TransactionQuery {
+    // Khớp với địa chỉ gửi hoặc địa chỉ nhận
+    pub address: Option<String>,
+    pub currency: Option<Currency>,
+    pub status: Option<TransactionStatus>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    // Cursor lấy từ `next_cursor` của trang trước
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    pub next_cursor: Option<String>,
+}
+
+impl TransactionQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_address(mut self, address: String) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_status(mut self, status: TransactionStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_date_range(mut self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Self {
+        self.since = since;
+        self.until = until;
+        self
+    }
+
+    pub fn with_amount_range(mut self, min_amount: Option<f64>, max_amount: Option<f64>) -> Self {
+        self.min_amount = min_amount;
+        self.max_amount = max_amount;
+        self
+    }
+
+    pub fn with_cursor(mut self, cursor: String) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.address.as_ref().is_none_or(|address| {
+            &transaction.from_address == address || &transaction.to_address == address
+        })
+            && self.currency.as_ref().is_none_or(|currency| &transaction.currency == currency)
+            && self.status.as_ref().is_none_or(|status| &transaction.status == status)
+            && self.since.is_none_or(|since| transaction.timestamp >= since)
+            && self.until.is_none_or(|until| transaction.timestamp < until)
+            && self.min_amount.is_none_or(|min| transaction.amount >= min)
+            && self.max_amount.is_none_or(|max| transaction.amount <= max)
+    }
+
+    // Sắp xếp theo (timestamp, id) để thứ tự ổn định khi nhiều giao dịch cùng thời điểm
+    pub fn execute<'a, I>(&self, transactions: I) -> Result<TransactionPage, PaymentError>
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(PaymentError::InvalidPageSize(limit));
+        }
+        let after = self.cursor.as_deref().map(decode_cursor).transpose()?;
+
+        let mut matching: Vec<&Transaction> = transactions.into_iter()
+            .filter(|transaction| self.matches(transaction))
+            .filter(|transaction| after.is_none_or(|key| (transaction.timestamp, transaction.id) > key))
+            .collect();
+        matching.sort_by_key(|transaction| (transaction.timestamp, transaction.id));
+
+        let has_more = matching.len() > limit;
+        let transactions: Vec<Transaction> = matching.into_iter().take(limit).cloned().collect();
+        let next_cursor = if has_more {
+            transactions.last().map(encode_cursor)
+        } else {
+            None
+        };
+
+        Ok(TransactionPage { transactions, next_cursor })
+    }
+}
+
+// Cursor là "<timestamp nano giây>.<id>" của giao dịch cuối cùng trong trang
+fn encode_cursor(transaction: &Transaction) -> String {
+    let nanos = transaction.timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX);
+    format!("{}.{}", nanos, transaction.id.simple())
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), PaymentError> {
+    let (nanos, id) = cursor.split_once('.').ok_or(PaymentError::InvalidCursor)?;
+    let nanos: i64 = nanos.parse().map_err(|_| PaymentError::InvalidCursor)?;
+    let timestamp = DateTime::from_timestamp_nanos(nanos);
+    let id = Uuid::parse_str(id).map_err(|_| PaymentError::InvalidCursor)?;
+    Ok((timestamp, id))
+}
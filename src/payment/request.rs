@@ -0,0 +1,282 @@
+use crate::models::{Currency, Transaction, TransactionStatus};
+use super::PaymentError;
+use super::address::validate_address;
+use chrono::{DateTime, Utc};
+use qrcode::QrCode;
+use qrcode::render::svg;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use uuid::Uuid;
+
+// Hợp đồng USDT (ERC-20) trên Ethereum mainnet
+pub const USDT_CONTRACT_ADDRESS: &str = "0xdAC17F958D2ee523a2206206994597C13D831ec7";
+pub const ETHEREUM_CHAIN_ID: u64 = 1;
+const USDT_DECIMALS: u32 = 6;
+const QR_MIN_SIZE: u32 = 240;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PaymentRequestStatus {
+    Open,
+    Paid(Uuid),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentRequest {
+    pub id: Uuid,
+    pub reference: String,
+    pub address: String,
+    pub amount: f64,
+    pub currency: Currency,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub status: PaymentRequestStatus,
+}
+
+// Kết quả đọc một URI thanh toán; reference chỉ có với BIP21 và URI RetailChain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedPaymentUri {
+    pub address: String,
+    pub amount: Option<f64>,
+    pub currency: Currency,
+    pub reference: Option<String>,
+    pub label: Option<String>,
+}
+
+impl PaymentRequest {
+    pub fn new(
+        address: String,
+        amount: f64,
+        currency: Currency,
+        reference: String,
+        label: Option<String>,
+    ) -> Result<Self, PaymentError> {
+        if amount <= 0.0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+        if matches!(currency, Currency::CASH) {
+            return Err(PaymentError::UnsupportedCurrency);
+        }
+        validate_address(&address, &currency).map_err(PaymentError::InvalidRecipientAddress)?;
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            reference,
+            address,
+            amount,
+            currency,
+            label,
+            created_at: Utc::now(),
+            status: PaymentRequestStatus::Open,
+        })
+    }
+
+    pub fn to_uri(&self) -> String {
+        match &self.currency {
+            Currency::BTC => {
+                let mut uri = format!("bitcoin:{}?amount={}", self.address, format_decimal(self.amount, 8));
+                if let Some(label) = &self.label {
+                    uri.push_str(&format!("&label={}", percent_encode(label)));
+                }
+                uri.push_str(&format!("&message={}", percent_encode(&self.reference)));
+                uri
+            }
+            Currency::ETH => format!(
+                "ethereum:{}@{}?value={}",
+                self.address,
+                ETHEREUM_CHAIN_ID,
+                to_base_units(self.amount, 18),
+            ),
+            Currency::USDT => format!(
+                "ethereum:{}@{}/transfer?address={}&uint256={}",
+                USDT_CONTRACT_ADDRESS,
+                ETHEREUM_CHAIN_ID,
+                self.address,
+                to_base_units(self.amount, USDT_DECIMALS),
+            ),
+            Currency::RETAIL(symbol) => {
+                let mut uri = format!(
+                    "retailchain:{}?token={}&amount={}&ref={}",
+                    self.address,
+                    percent_encode(symbol),
+                    format_decimal(self.amount, 8),
+                    percent_encode(&self.reference),
+                );
+                if let Some(label) = &self.label {
+                    uri.push_str(&format!("&label={}", percent_encode(label)));
+                }
+                uri
+            }
+            // `new` không cho phép tạo yêu cầu thanh toán tiền mặt
+            Currency::CASH => String::new(),
+        }
+    }
+
+    pub fn to_qr_svg(&self) -> Result<String, PaymentError> {
+        let code = QrCode::new(self.to_uri().as_bytes())
+            .map_err(|e| PaymentError::QrCode(e.to_string()))?;
+
+        Ok(code.render::<svg::Color>()
+            .min_dimensions(QR_MIN_SIZE, QR_MIN_SIZE)
+            .build())
+    }
+
+    pub fn to_qr_png(&self) -> Result<Vec<u8>, PaymentError> {
+        let code = QrCode::new(self.to_uri().as_bytes())
+            .map_err(|e| PaymentError::QrCode(e.to_string()))?;
+        let image = code.render::<image::Luma<u8>>()
+            .min_dimensions(QR_MIN_SIZE, QR_MIN_SIZE)
+            .build();
+
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .map_err(|e| PaymentError::QrCode(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    pub fn is_paid(&self) -> bool {
+        matches!(self.status, PaymentRequestStatus::Paid(_))
+    }
+
+    // URI EIP-681 (ETH, USDT) không mang reference, nên chỉ nhận ra yêu cầu qua địa chỉ nhận
+    pub fn uri_has_reference(&self) -> bool {
+        matches!(self.currency, Currency::BTC | Currency::RETAIL(_))
+    }
+
+    // Giao dịch khớp nếu đã hoàn tất (không còn chờ duyệt), cùng địa chỉ nhận, cùng loại tiền và đủ số tiền
+    pub fn matches_transaction(&self, transaction: &Transaction) -> bool {
+        !self.is_paid()
+            && transaction.status == TransactionStatus::Completed
+            && transaction.refund_of.is_none()
+            && transaction.to_address == self.address
+            && transaction.currency == self.currency
+            && transaction.amount + 1e-9 >= self.amount
+    }
+}
+
+pub fn parse_payment_uri(uri: &str) -> Result<ParsedPaymentUri, PaymentError> {
+    let (scheme, rest) = uri.split_once(':').ok_or(PaymentError::InvalidPaymentUri)?;
+    let (target, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let params: Vec<(String, String)> = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((key.to_string(), percent_decode(value)?))
+        })
+        .collect::<Result<_, PaymentError>>()?;
+    let param = |name: &str| params.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+
+    if target.is_empty() {
+        return Err(PaymentError::InvalidPaymentUri);
+    }
+
+    match scheme.to_ascii_lowercase().as_str() {
+        "bitcoin" => Ok(ParsedPaymentUri {
+            address: target.to_string(),
+            amount: param("amount").map(|value| parse_amount(&value)).transpose()?,
+            currency: Currency::BTC,
+            reference: param("message"),
+            label: param("label"),
+        }),
+        "ethereum" => {
+            let (contract, function) = target.split_once('/').unwrap_or((target, ""));
+            let contract = contract.split('@').next().unwrap_or(contract);
+
+            if function.is_empty() {
+                let amount = param("value").map(|value| from_base_units(&value, 18)).transpose()?;
+                return Ok(ParsedPaymentUri {
+                    address: contract.to_string(),
+                    amount,
+                    currency: Currency::ETH,
+                    reference: None,
+                    label: None,
+                });
+            }
+
+            if function != "transfer" || !contract.eq_ignore_ascii_case(USDT_CONTRACT_ADDRESS) {
+                return Err(PaymentError::UnsupportedCurrency);
+            }
+            let address = param("address").ok_or(PaymentError::InvalidPaymentUri)?;
+            let amount = param("uint256").map(|value| from_base_units(&value, USDT_DECIMALS)).transpose()?;
+            Ok(ParsedPaymentUri {
+                address,
+                amount,
+                currency: Currency::USDT,
+                reference: None,
+                label: None,
+            })
+        }
+        "retailchain" => Ok(ParsedPaymentUri {
+            address: target.to_string(),
+            amount: param("amount").map(|value| parse_amount(&value)).transpose()?,
+            currency: Currency::RETAIL(param("token").unwrap_or_else(|| "RETAIL".to_string())),
+            reference: param("ref"),
+            label: param("label"),
+        }),
+        _ => Err(PaymentError::InvalidPaymentUri),
+    }
+}
+
+fn parse_amount(value: &str) -> Result<f64, PaymentError> {
+    value.parse::<f64>()
+        .ok()
+        .filter(|amount| amount.is_finite() && *amount >= 0.0)
+        .ok_or(PaymentError::InvalidPaymentUri)
+}
+
+fn format_decimal(amount: f64, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, amount);
+    if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        formatted
+    }
+}
+
+// Làm tròn tới 9 chữ số thập phân trước để tránh sai số f64 ở các đơn vị rất nhỏ (wei)
+fn to_base_units(amount: f64, decimals: u32) -> String {
+    let precision = decimals.min(9);
+    let scaled = (amount * 10f64.powi(precision as i32)).round() as u128;
+    let value = scaled * 10u128.pow(decimals - precision);
+    value.to_string()
+}
+
+fn from_base_units(value: &str, decimals: u32) -> Result<f64, PaymentError> {
+    let units: u128 = value.parse().map_err(|_| PaymentError::InvalidPaymentUri)?;
+    Ok(units as f64 / 10f64.powi(decimals as i32))
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, PaymentError> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = value.get(i + 1..i + 3).ok_or(PaymentError::InvalidPaymentUri)?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| PaymentError::InvalidPaymentUri)?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| PaymentError::InvalidPaymentUri)
+}
@@ -0,0 +1,81 @@
+use crate::models::{Currency, Transaction, TransactionStatus};
+use chrono::NaiveDate;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettlementLine {
+    pub currency: Currency,
+    pub payment_count: usize,
+    pub gross: f64,
+    pub refunds: f64,
+    pub fees: f64,
+    pub net: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettlementReport {
+    pub merchant_address: String,
+    pub date: NaiveDate,
+    pub lines: Vec<SettlementLine>,
+}
+
+impl SettlementReport {
+    pub fn build<'a>(
+        merchant_address: &str,
+        date: NaiveDate,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+    ) -> Self {
+        let mut lines: Vec<SettlementLine> = Vec::new();
+
+        for transaction in transactions {
+            if transaction.timestamp.date_naive() != date {
+                continue;
+            }
+            if matches!(transaction.status, TransactionStatus::Pending | TransactionStatus::Failed) {
+                continue;
+            }
+
+            let is_payment = transaction.refund_of.is_none() && transaction.to_address == merchant_address;
+            let is_refund = transaction.refund_of.is_some() && transaction.from_address == merchant_address;
+            if !is_payment && !is_refund {
+                continue;
+            }
+
+            let line = match lines.iter().position(|line| line.currency == transaction.currency) {
+                Some(index) => &mut lines[index],
+                None => {
+                    lines.push(SettlementLine {
+                        currency: transaction.currency.clone(),
+                        payment_count: 0,
+                        gross: 0.0,
+                        refunds: 0.0,
+                        fees: 0.0,
+                        net: 0.0,
+                    });
+                    lines.last_mut().unwrap()
+                }
+            };
+
+            if is_payment {
+                line.payment_count += 1;
+                line.gross += transaction.amount;
+                line.fees += transaction.fee;
+            } else {
+                line.refunds += transaction.amount;
+            }
+            line.net = line.gross - line.refunds - line.fees;
+        }
+
+        lines.sort_by_key(|line| line.currency.symbol());
+
+        Self {
+            merchant_address: merchant_address.to_string(),
+            date,
+            lines,
+        }
+    }
+
+    pub fn get_line(&self, currency: &Currency) -> Option<&SettlementLine> {
+        self.lines.iter().find(|line| &line.currency == currency)
+    }
+}
@@ -0,0 +1,388 @@
+use crate::inventory::{InventoryError, InventoryManager};
+use crate::models::SupplyChainAction;
+use crate::supply_chain::{SupplyChainError, SupplyChainManager};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PurchaseOrderStatus {
+    Draft,
+    Sent,
+    PartiallyReceived,
+    Received,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurchaseOrderLine {
+    pub product_id: Uuid,
+    pub sku: String,
+    pub quantity_ordered: u32,
+    pub quantity_received: u32,
+    pub unit_cost: f64,
+}
+
+// Một lần nhận hàng theo đơn, có thể nhiều lần cho một đơn
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoodsReceipt {
+    pub id: Uuid,
+    pub purchase_order_id: Uuid,
+    pub received_by: String,
+    pub received_at: DateTime<Utc>,
+    pub lines: Vec<(Uuid, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurchaseOrder {
+    pub id: Uuid,
+    pub supplier: String,
+    // Địa điểm nhận hàng trong InventoryManager
+    pub location_id: String,
+    pub lines: Vec<PurchaseOrderLine>,
+    pub status: PurchaseOrderStatus,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub receipts: Vec<GoodsReceipt>,
+}
+
+impl PurchaseOrder {
+    pub fn total_cost(&self) -> f64 {
+        self.lines.iter().map(|line| line.unit_cost * f64::from(line.quantity_ordered)).sum()
+    }
+
+    fn is_fully_received(&self) -> bool {
+        self.lines.iter().all(|line| line.quantity_received >= line.quantity_ordered)
+    }
+
+    // Mã tham chiếu ghi vào sổ kho cho các lần nhận hàng theo đơn này
+    pub fn reference(&self) -> String {
+        format!("purchase_order:{}", self.id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscrepancyLine {
+    pub product_id: Uuid,
+    pub sku: String,
+    pub ordered: u32,
+    pub received: u32,
+    // Dương là giao thừa, âm là giao thiếu
+    pub difference: i64,
+}
+
+// Chênh lệch giữa số đặt và số đã nhận; chỉ liệt kê các dòng không khớp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscrepancyReport {
+    pub purchase_order_id: Uuid,
+    pub supplier: String,
+    pub status: PurchaseOrderStatus,
+    pub lines: Vec<DiscrepancyLine>,
+}
+
+impl DiscrepancyReport {
+    pub fn short_lines(&self) -> impl Iterator<Item = &DiscrepancyLine> {
+        self.lines.iter().filter(|line| line.difference < 0)
+    }
+
+    pub fn over_lines(&self) -> impl Iterator<Item = &DiscrepancyLine> {
+        self.lines.iter().filter(|line| line.difference > 0)
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+pub struct PurchaseOrderManager {
+    orders: HashMap<Uuid, PurchaseOrder>,
+}
+
+impl PurchaseOrderManager {
+    pub fn new() -> Self {
+        Self {
+            orders: HashMap::new(),
+        }
+    }
+
+    pub fn create_draft(
+        &mut self,
+        supplier: String,
+        location_id: &str,
+        inventory: &InventoryManager,
+    ) -> Result<Uuid, PurchaseOrderError> {
+        if inventory.get_location(location_id).is_none() {
+            return Err(InventoryError::LocationNotFound(location_id.to_string()).into());
+        }
+
+        let order = PurchaseOrder {
+            id: Uuid::new_v4(),
+            supplier,
+            location_id: location_id.to_string(),
+            lines: Vec::new(),
+            status: PurchaseOrderStatus::Draft,
+            created_at: Utc::now(),
+            sent_at: None,
+            closed_at: None,
+            receipts: Vec::new(),
+        };
+        let id = order.id;
+        self.orders.insert(id, order);
+        Ok(id)
+    }
+
+    // Thêm sản phẩm vào đơn nháp; sản phẩm đã có trong đơn thì cộng dồn số lượng và lấy giá mới
+    pub fn add_line(
+        &mut self,
+        order_id: Uuid,
+        inventory: &InventoryManager,
+        product_id: Uuid,
+        quantity: u32,
+        unit_cost: f64,
+    ) -> Result<(), PurchaseOrderError> {
+        if quantity == 0 {
+            return Err(PurchaseOrderError::InvalidQuantity);
+        }
+        if !unit_cost.is_finite() || unit_cost < 0.0 {
+            return Err(PurchaseOrderError::InvalidUnitCost);
+        }
+        let product = inventory.get_product(product_id).ok_or(InventoryError::ProductNotFound)?;
+        let order = self.draft_mut(order_id)?;
+
+        match order.lines.iter_mut().find(|line| line.product_id == product_id) {
+            Some(line) => {
+                line.quantity_ordered = line.quantity_ordered.checked_add(quantity)
+                    .ok_or(PurchaseOrderError::InvalidQuantity)?;
+                line.unit_cost = unit_cost;
+            }
+            None => order.lines.push(PurchaseOrderLine {
+                product_id,
+                sku: product.sku.clone(),
+                quantity_ordered: quantity,
+                quantity_received: 0,
+                unit_cost,
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn remove_line(&mut self, order_id: Uuid, product_id: Uuid) -> Result<(), PurchaseOrderError> {
+        let order = self.draft_mut(order_id)?;
+        let before = order.lines.len();
+        order.lines.retain(|line| line.product_id != product_id);
+        if order.lines.len() == before {
+            return Err(PurchaseOrderError::ProductNotOnOrder(product_id));
+        }
+        Ok(())
+    }
+
+    pub fn send(&mut self, order_id: Uuid) -> Result<(), PurchaseOrderError> {
+        let order = self.draft_mut(order_id)?;
+        if order.lines.is_empty() {
+            return Err(PurchaseOrderError::EmptyOrder);
+        }
+
+        order.status = PurchaseOrderStatus::Sent;
+        order.sent_at = Some(Utc::now());
+        println!("📤 Purchase order {} sent to {}", order.id, order.supplier);
+        Ok(())
+    }
+
+    // Nhận hàng: tăng tồn kho tại địa điểm nhận của đơn và ghi `Received` vào chuỗi cung ứng.
+    // Mọi dòng được kiểm tra trước khi ghi để một dòng lỗi không làm nhận dở dang
+    pub fn receive(
+        &mut self,
+        order_id: Uuid,
+        received: &[(Uuid, u32)],
+        received_by: &str,
+        inventory: &mut InventoryManager,
+        supply_chain: &mut SupplyChainManager,
+    ) -> Result<GoodsReceipt, PurchaseOrderError> {
+        let order = self.orders.get_mut(&order_id).ok_or(PurchaseOrderError::OrderNotFound)?;
+        if !matches!(order.status, PurchaseOrderStatus::Sent | PurchaseOrderStatus::PartiallyReceived) {
+            return Err(PurchaseOrderError::InvalidStatus(order.status));
+        }
+        if received.is_empty() {
+            return Err(PurchaseOrderError::EmptyOrder);
+        }
+
+        // Cùng sản phẩm xuất hiện nhiều lần trong phiếu được gộp thành một dòng
+        let mut lines: Vec<(Uuid, u32)> = Vec::new();
+        for &(product_id, quantity) in received {
+            if quantity == 0 {
+                return Err(PurchaseOrderError::InvalidQuantity);
+            }
+            if !order.lines.iter().any(|line| line.product_id == product_id) {
+                return Err(PurchaseOrderError::ProductNotOnOrder(product_id));
+            }
+            if !supply_chain.is_tracked(product_id) {
+                return Err(SupplyChainError::ProductNotFound.into());
+            }
+            match lines.iter_mut().find(|(id, _)| *id == product_id) {
+                Some((_, total)) => {
+                    *total = total.checked_add(quantity).ok_or(InventoryError::QuantityOverflow)?;
+                }
+                None => lines.push((product_id, quantity)),
+            }
+        }
+
+        // Hàng nhập theo đơn mua được ghi giá vốn bằng giá trên dòng đơn; mọi dòng vào kho trong một lần ghi
+        let reference = order.reference();
+        let stock_lines: Vec<(Uuid, u32, f64)> = lines.iter()
+            .map(|&(product_id, quantity)| {
+                let unit_cost = order.lines.iter()
+                    .find(|line| line.product_id == product_id)
+                    .map_or(0.0, |line| line.unit_cost);
+                (product_id, quantity, unit_cost)
+            })
+            .collect();
+        inventory.receive_stock_lines(&order.location_id, &stock_lines, received_by, Some(reference))?;
+
+        // Cập nhật đơn ngay sau khi hàng vào kho, trước khi ghi chuỗi cung ứng
+        for &(product_id, quantity) in &lines {
+            if let Some(line) = order.lines.iter_mut().find(|line| line.product_id == product_id) {
+                line.quantity_received = line.quantity_received.saturating_add(quantity);
+            }
+        }
+        let receipt = GoodsReceipt {
+            id: Uuid::new_v4(),
+            purchase_order_id: order.id,
+            received_by: received_by.to_string(),
+            received_at: Utc::now(),
+            lines,
+        };
+        order.receipts.push(receipt.clone());
+        if order.is_fully_received() {
+            order.status = PurchaseOrderStatus::Received;
+            order.closed_at = Some(receipt.received_at);
+        } else {
+            order.status = PurchaseOrderStatus::PartiallyReceived;
+        }
+
+        for &(product_id, quantity) in &receipt.lines {
+            supply_chain.record_movement(
+                product_id,
+                order.location_id.clone(),
+                received_by.to_string(),
+                SupplyChainAction::Received,
+                json!({
+                    "purchase_order": order.id.to_string(),
+                    "supplier": order.supplier,
+                    "quantity": quantity,
+                }),
+            )?;
+        }
+        Ok(receipt)
+    }
+
+    // Đóng đơn đã nhận một phần khi nhà cung cấp không giao thêm; phần thiếu còn trong báo cáo chênh lệch
+    pub fn close_short(&mut self, order_id: Uuid) -> Result<(), PurchaseOrderError> {
+        let order = self.orders.get_mut(&order_id).ok_or(PurchaseOrderError::OrderNotFound)?;
+        if order.status != PurchaseOrderStatus::PartiallyReceived {
+            return Err(PurchaseOrderError::InvalidStatus(order.status));
+        }
+
+        order.status = PurchaseOrderStatus::Received;
+        order.closed_at = Some(Utc::now());
+        Ok(())
+    }
+
+    // Chỉ hủy được đơn chưa nhận hàng
+    pub fn cancel(&mut self, order_id: Uuid) -> Result<(), PurchaseOrderError> {
+        let order = self.orders.get_mut(&order_id).ok_or(PurchaseOrderError::OrderNotFound)?;
+        if !matches!(order.status, PurchaseOrderStatus::Draft | PurchaseOrderStatus::Sent) {
+            return Err(PurchaseOrderError::InvalidStatus(order.status));
+        }
+
+        order.status = PurchaseOrderStatus::Cancelled;
+        order.closed_at = Some(Utc::now());
+        println!("🛑 Purchase order {} cancelled", order.id);
+        Ok(())
+    }
+
+    pub fn discrepancy_report(&self, order_id: Uuid) -> Result<DiscrepancyReport, PurchaseOrderError> {
+        let order = self.orders.get(&order_id).ok_or(PurchaseOrderError::OrderNotFound)?;
+
+        Ok(DiscrepancyReport {
+            purchase_order_id: order.id,
+            supplier: order.supplier.clone(),
+            status: order.status,
+            lines: order.lines.iter()
+                .filter(|line| line.quantity_received != line.quantity_ordered)
+                .map(|line| DiscrepancyLine {
+                    product_id: line.product_id,
+                    sku: line.sku.clone(),
+                    ordered: line.quantity_ordered,
+                    received: line.quantity_received,
+                    difference: i64::from(line.quantity_received) - i64::from(line.quantity_ordered),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn get_purchase_order(&self, order_id: Uuid) -> Option<&PurchaseOrder> {
+        self.orders.get(&order_id)
+    }
+
+    pub fn get_purchase_orders_by_status(&self, status: PurchaseOrderStatus) -> Vec<&PurchaseOrder> {
+        let mut orders: Vec<&PurchaseOrder> = self.orders.values()
+            .filter(|order| order.status == status)
+            .collect();
+        orders.sort_by_key(|order| order.created_at);
+        orders
+    }
+
+    // Số lượng còn chờ về theo sản phẩm, cộng trên các đơn đã gửi hoặc mới nhận một phần
+    pub fn open_quantities(&self) -> HashMap<Uuid, u32> {
+        let mut quantities: HashMap<Uuid, u32> = HashMap::new();
+        let open = self.orders.values().filter(|order| {
+            matches!(order.status, PurchaseOrderStatus::Sent | PurchaseOrderStatus::PartiallyReceived)
+        });
+        for order in open {
+            for line in &order.lines {
+                let outstanding = line.quantity_ordered.saturating_sub(line.quantity_received);
+                let total = quantities.entry(line.product_id).or_default();
+                *total = total.saturating_add(outstanding);
+            }
+        }
+        quantities.retain(|_, quantity| *quantity > 0);
+        quantities
+    }
+
+    fn draft_mut(&mut self, order_id: Uuid) -> Result<&mut PurchaseOrder, PurchaseOrderError> {
+        let order = self.orders.get_mut(&order_id).ok_or(PurchaseOrderError::OrderNotFound)?;
+        if order.status != PurchaseOrderStatus::Draft {
+            return Err(PurchaseOrderError::InvalidStatus(order.status));
+        }
+        Ok(order)
+    }
+}
+
+impl Default for PurchaseOrderManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PurchaseOrderError {
+    #[error("Purchase order not found")]
+    OrderNotFound,
+    #[error("Action not allowed while purchase order is {0:?}")]
+    InvalidStatus(PurchaseOrderStatus),
+    #[error("Purchase order has no lines")]
+    EmptyOrder,
+    #[error("Quantity must be greater than zero")]
+    InvalidQuantity,
+    #[error("Unit cost must be a non-negative number")]
+    InvalidUnitCost,
+    #[error("Product {0} is not on this purchase order")]
+    ProductNotOnOrder(Uuid),
+    #[error("Inventory error: {0}")]
+    Inventory(#[from] InventoryError),
+    #[error("Supply chain error: {0}")]
+    SupplyChain(#[from] SupplyChainError),
+}
@@ -0,0 +1,248 @@
+use crate::models::Currency;
+use chrono::{DateTime, Duration, FixedOffset, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RiskAction {
+    Allow,
+    Review,
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RiskReason {
+    BlockedAddress(String),
+    VelocityExceeded { address: String, count: usize, window_minutes: i64 },
+    AmountAboveLimit { currency: Currency, limit: f64, amount: f64 },
+    UnusualHour(u32),
+}
+
+impl fmt::Display for RiskReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskReason::BlockedAddress(address) => write!(f, "address {} is blocked", address),
+            RiskReason::VelocityExceeded { address, count, window_minutes } => {
+                write!(f, "{} payments from {} within {} minutes", count, address, window_minutes)
+            }
+            RiskReason::AmountAboveLimit { currency, limit, amount } => {
+                write!(f, "{} {} exceeds limit {}", amount, currency.symbol(), limit)
+            }
+            RiskReason::UnusualHour(hour) => write!(f, "payment at unusual hour {}:00", hour),
+        }
+    }
+}
+
+pub fn format_reasons(reasons: &[RiskReason]) -> String {
+    reasons.iter().map(|reason| reason.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskAssessment {
+    pub action: RiskAction,
+    pub reasons: Vec<RiskReason>,
+}
+
+impl RiskAssessment {
+    fn add(&mut self, action: RiskAction, reason: RiskReason) {
+        self.action = self.action.max(action);
+        self.reasons.push(reason);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentContext<'a> {
+    pub from_address: &'a str,
+    pub to_address: &'a str,
+    pub amount: f64,
+    pub currency: &'a Currency,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VelocityRule {
+    pub max_payments: usize,
+    pub window: Duration,
+    pub action: RiskAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct AmountRule {
+    pub limit: f64,
+    pub action: RiskAction,
+}
+
+// Khung giờ bất thường [start_hour, end_hour) theo múi giờ của cửa hàng
+#[derive(Debug, Clone)]
+pub struct UnusualHoursRule {
+    pub start_hour: u32,
+    pub end_hour: u32,
+    pub timezone: FixedOffset,
+    pub action: RiskAction,
+}
+
+impl UnusualHoursRule {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start_hour <= self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RiskRules {
+    pub velocity: Option<VelocityRule>,
+    pub max_amounts: HashMap<Currency, AmountRule>,
+    pub blocked_addresses: HashSet<String>,
+    pub unusual_hours: Option<UnusualHoursRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewItem {
+    pub transaction_id: Uuid,
+    pub reasons: Vec<RiskReason>,
+    pub flagged_at: DateTime<Utc>,
+    pub status: ReviewStatus,
+    pub reviewer: Option<String>,
+}
+
+pub struct RiskEngine {
+    rules: RiskRules,
+    payment_history: HashMap<String, Vec<DateTime<Utc>>>,
+    review_queue: Vec<ReviewItem>,
+}
+
+impl RiskEngine {
+    pub fn new(rules: RiskRules) -> Self {
+        Self {
+            rules,
+            payment_history: HashMap::new(),
+            review_queue: Vec::new(),
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: RiskRules) {
+        self.rules = rules;
+    }
+
+    pub fn get_rules(&self) -> &RiskRules {
+        &self.rules
+    }
+
+    pub fn evaluate(&self, context: &PaymentContext) -> RiskAssessment {
+        self.evaluate_with_pending(context, 0)
+    }
+
+    // `pending_payments` là số thanh toán khác của cùng địa chỉ gửi đang được xử lý cùng lúc
+    // (ví dụ các hình thức thanh toán trước đó của cùng đơn) nhưng chưa được ghi nhận
+    pub fn evaluate_with_pending(&self, context: &PaymentContext, pending_payments: usize) -> RiskAssessment {
+        let mut assessment = RiskAssessment {
+            action: RiskAction::Allow,
+            reasons: Vec::new(),
+        };
+
+        for address in [context.from_address, context.to_address] {
+            if self.rules.blocked_addresses.contains(address) {
+                assessment.add(RiskAction::Deny, RiskReason::BlockedAddress(address.to_string()));
+            }
+        }
+
+        if let Some(rule) = self.rules.max_amounts.get(context.currency) {
+            if context.amount > rule.limit {
+                assessment.add(rule.action, RiskReason::AmountAboveLimit {
+                    currency: context.currency.clone(),
+                    limit: rule.limit,
+                    amount: context.amount,
+                });
+            }
+        }
+
+        // Tính cả giao dịch đang xét vào số lần thanh toán trong cửa sổ thời gian
+        if let Some(rule) = &self.rules.velocity {
+            let window_start = context.timestamp - rule.window;
+            let count = self.payment_history.get(context.from_address)
+                .map(|timestamps| timestamps.iter().filter(|at| **at > window_start).count())
+                .unwrap_or(0) + pending_payments + 1;
+            if count > rule.max_payments {
+                assessment.add(rule.action, RiskReason::VelocityExceeded {
+                    address: context.from_address.to_string(),
+                    count,
+                    window_minutes: rule.window.num_minutes(),
+                });
+            }
+        }
+
+        if let Some(rule) = &self.rules.unusual_hours {
+            let hour = context.timestamp.with_timezone(&rule.timezone).hour();
+            if rule.contains(hour) {
+                assessment.add(rule.action, RiskReason::UnusualHour(hour));
+            }
+        }
+
+        assessment
+    }
+
+    // Lịch sử chỉ dùng cho quy tắc tần suất nên chỉ giữ trong cửa sổ của quy tắc đó
+    pub fn record_payment(&mut self, from_address: &str, timestamp: DateTime<Utc>) {
+        let Some(rule) = &self.rules.velocity else {
+            return;
+        };
+        let history = self.payment_history.entry(from_address.to_string()).or_default();
+        history.push(timestamp);
+
+        let window_start = timestamp - rule.window;
+        history.retain(|at| *at > window_start);
+    }
+
+    pub fn flag_for_review(&mut self, transaction_id: Uuid, reasons: Vec<RiskReason>) {
+        println!("🚩 Payment {} flagged for review: {}", transaction_id, format_reasons(&reasons));
+        self.review_queue.push(ReviewItem {
+            transaction_id,
+            reasons,
+            flagged_at: Utc::now(),
+            status: ReviewStatus::Pending,
+            reviewer: None,
+        });
+    }
+
+    pub fn get_pending_reviews(&self) -> Vec<&ReviewItem> {
+        self.review_queue.iter()
+            .filter(|item| item.status == ReviewStatus::Pending)
+            .collect()
+    }
+
+    pub fn get_review(&self, transaction_id: Uuid) -> Option<&ReviewItem> {
+        self.review_queue.iter().find(|item| item.transaction_id == transaction_id)
+    }
+
+    pub fn resolve_review(
+        &mut self,
+        transaction_id: Uuid,
+        approved: bool,
+        reviewer: String,
+    ) -> Option<&ReviewItem> {
+        let item = self.review_queue.iter_mut()
+            .find(|item| item.transaction_id == transaction_id && item.status == ReviewStatus::Pending)?;
+
+        item.status = if approved { ReviewStatus::Approved } else { ReviewStatus::Rejected };
+        item.reviewer = Some(reviewer);
+        Some(item)
+    }
+}
+
+impl Default for RiskEngine {
+    fn default() -> Self {
+        Self::new(RiskRules::default())
+    }
+}
@@ -0,0 +1,119 @@
+use super::SchedulerError;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// Giới hạn tìm kiếm lần chạy kế tiếp, đủ cho biểu thức chỉ khớp ngày 29/2
+const SEARCH_DAYS: i64 = 366 * 8;
+
+// Biểu thức cron 5 trường (UTC): phút giờ ngày-trong-tháng tháng thứ-trong-tuần.
+// Mỗi trường hỗ trợ `*`, số, danh sách `a,b`, khoảng `a-b` và bước `*/n`, `a-b/n`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    // 0 = Chủ nhật; 7 cũng được hiểu là Chủ nhật
+    days_of_week: BTreeSet<u32>,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, SchedulerError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(SchedulerError::InvalidCron(format!("expected 5 fields, got {}", fields.len())));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        if days_of_week.remove(&7) {
+            days_of_week.insert(0);
+        }
+
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            day_of_month_restricted: !fields[2].starts_with('*'),
+            day_of_week_restricted: !fields[4].starts_with('*'),
+        })
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    // Như cron chuẩn: nếu cả ngày-trong-tháng và thứ đều bị giới hạn thì khớp một trong hai
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+        let day_of_month = self.days_of_month.contains(&date.day());
+        let day_of_week = self.days_of_week.contains(&date.weekday().num_days_from_sunday());
+        match (self.day_of_month_restricted, self.day_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            (true, false) => day_of_month,
+            (false, true) => day_of_week,
+            (false, false) => true,
+        }
+    }
+
+    // Lần chạy đầu tiên sau `after` (không tính chính `after`)
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start_date = after.date_naive();
+        for offset in 0..SEARCH_DAYS {
+            let date = start_date + Duration::days(offset);
+            if !self.matches_date(date) {
+                continue;
+            }
+            for hour in &self.hours {
+                for minute in &self.minutes {
+                    let time = NaiveTime::from_hms_opt(*hour, *minute, 0)?;
+                    let candidate = Utc.from_utc_datetime(&date.and_time(time));
+                    if candidate > after {
+                        return Some(candidate);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, SchedulerError> {
+    let invalid = || SchedulerError::InvalidCron(format!("invalid field '{}'", field));
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().map_err(|_| invalid())?, end.parse().map_err(|_| invalid())?)
+        } else {
+            let value: u32 = range.parse().map_err(|_| invalid())?;
+            // "5/15" nghĩa là từ 5 tới hết khoảng, mỗi 15 đơn vị
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,376 @@
+pub mod cron;
+
+use crate::models::{Currency, TransactionStatus};
+use crate::payment::{PaymentProcessor, Tender};
+use crate::payment::address::{validate_address, AddressError};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use cron::CronSchedule;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+// Nguồn thời gian của bộ lập lịch; test dùng ManualClock để điều khiển thời điểm
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now: Mutex::new(now) }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().expect("clock lock poisoned") = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("clock lock poisoned") += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().expect("clock lock poisoned")
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+// Mọi mốc giờ đều tính theo UTC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    Once(DateTime<Utc>),
+    Weekly { weekday: Weekday, time: NaiveTime },
+    // Ngày lớn hơn số ngày của tháng được dời về ngày cuối tháng
+    Monthly { day: u32, time: NaiveTime },
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Once(at) => (*at > after).then_some(*at),
+            Schedule::Weekly { weekday, time } => (0..=7)
+                .map(|offset| after.date_naive() + Duration::days(offset))
+                .filter(|date| date.weekday() == *weekday)
+                .map(|date| Utc.from_utc_datetime(&date.and_time(*time)))
+                .find(|candidate| *candidate > after),
+            Schedule::Monthly { day, time } => {
+                let first_of_month = after.date_naive().with_day(1)?;
+                (0..=12)
+                    .filter_map(|offset| first_of_month.checked_add_months(Months::new(offset)))
+                    .filter_map(|month_start| {
+                        let date = month_start.with_day((*day).min(days_in_month(month_start)))?;
+                        Some(Utc.from_utc_datetime(&date.and_time(*time)))
+                    })
+                    .find(|candidate| *candidate > after)
+            }
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+
+    // Lịch một lần đã quá hạn vẫn được chạy ngay ở lần kiểm tra đầu tiên
+    fn first_occurrence(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Once(at) => Some(*at),
+            _ => self.next_after(now),
+        }
+    }
+}
+
+fn days_in_month(month_start: NaiveDate) -> u32 {
+    month_start.checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .map(|last| last.day())
+        .unwrap_or(28)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    // Tổng số lần thử cho mỗi lần đến hạn, tính cả lần đầu
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, retry_delay: Duration) -> Self {
+        Self { max_attempts, retry_delay }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, Duration::minutes(15))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ScheduleStatus {
+    Active,
+    Completed,
+    // Lịch một lần đã hết số lần thử
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RunOutcome {
+    Paid(Uuid),
+    // Giao dịch đã tạo nhưng bị giữ ở trạng thái Pending chờ người duyệt
+    PendingReview(Uuid),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledRun {
+    pub schedule_id: Uuid,
+    pub due_at: DateTime<Utc>,
+    pub attempted_at: DateTime<Utc>,
+    pub attempt: u32,
+    pub outcome: RunOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledPayment {
+    pub id: Uuid,
+    pub label: String,
+    pub from_address: String,
+    pub to_address: String,
+    pub amount: f64,
+    pub currency: Currency,
+    pub schedule: Schedule,
+    pub retry_policy: RetryPolicy,
+    pub status: ScheduleStatus,
+    // Thời điểm đến hạn của lần thanh toán hiện tại
+    pub next_run: Option<DateTime<Utc>>,
+    pub retry_at: Option<DateTime<Utc>>,
+    // Số lần đã thử thất bại cho lần đến hạn hiện tại
+    pub attempts: u32,
+    pub runs: Vec<ScheduledRun>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ScheduledPayment {
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.status == ScheduleStatus::Active
+            && self.retry_at.or(self.next_run).is_some_and(|at| at <= now)
+    }
+
+    pub fn failed_runs(&self) -> impl Iterator<Item = &ScheduledRun> {
+        self.runs.iter().filter(|run| matches!(run.outcome, RunOutcome::Failed(_)))
+    }
+
+    fn advance(&mut self, due_at: DateTime<Utc>) {
+        self.attempts = 0;
+        self.retry_at = None;
+        self.next_run = self.schedule.next_after(due_at);
+    }
+}
+
+pub struct PaymentScheduler {
+    clock: Box<dyn Clock>,
+    schedules: HashMap<Uuid, ScheduledPayment>,
+}
+
+impl PaymentScheduler {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            schedules: HashMap::new(),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn schedule_payment(
+        &mut self,
+        label: String,
+        tender: Tender,
+        schedule: Schedule,
+        retry_policy: RetryPolicy,
+    ) -> Result<Uuid, SchedulerError> {
+        if tender.amount <= 0.0 {
+            return Err(SchedulerError::InvalidAmount);
+        }
+        if retry_policy.max_attempts == 0 {
+            return Err(SchedulerError::InvalidRetryPolicy);
+        }
+        validate_address(&tender.from_address, &tender.currency).map_err(SchedulerError::InvalidAddress)?;
+        validate_address(&tender.to_address, &tender.currency).map_err(SchedulerError::InvalidAddress)?;
+
+        let now = self.clock.now();
+        let next_run = schedule.first_occurrence(now).ok_or(SchedulerError::NoUpcomingRun)?;
+
+        let payment = ScheduledPayment {
+            id: Uuid::new_v4(),
+            label,
+            from_address: tender.from_address,
+            to_address: tender.to_address,
+            amount: tender.amount,
+            currency: tender.currency,
+            schedule,
+            retry_policy,
+            status: ScheduleStatus::Active,
+            next_run: Some(next_run),
+            retry_at: None,
+            attempts: 0,
+            runs: Vec::new(),
+            created_at: now,
+        };
+
+        println!("⏰ Scheduled payment '{}': {} {:?}, first run at {}",
+                 payment.label, payment.amount, payment.currency, next_run);
+        let id = payment.id;
+        self.schedules.insert(id, payment);
+        Ok(id)
+    }
+
+    // Chạy mọi lần thanh toán đã đến hạn, kể cả các lần bị lỡ, theo thứ tự thời gian.
+    // Lần thất bại được thử lại sau `retry_delay`; hết số lần thử thì chuyển sang lần đến hạn kế tiếp
+    pub fn run_due(&mut self, processor: &mut PaymentProcessor) -> Vec<ScheduledRun> {
+        let now = self.clock.now();
+        let mut ids: Vec<Uuid> = self.schedules.values()
+            .filter(|payment| payment.is_due(now))
+            .map(|payment| payment.id)
+            .collect();
+        ids.sort_by_key(|id| self.schedules[id].retry_at.or(self.schedules[id].next_run));
+
+        let mut runs = Vec::new();
+        for id in ids {
+            let payment = self.schedules.get_mut(&id).expect("schedule exists");
+
+            while payment.is_due(now) {
+                let Some(due_at) = payment.next_run else { break };
+                let attempt = payment.attempts + 1;
+                // Giao dịch mang thời điểm đến hạn của lần chạy (hoặc lần thử lại), không phải giờ hệ thống
+                let scheduled_at = payment.retry_at.unwrap_or(due_at);
+                let result = processor.process_payment_at(
+                    payment.from_address.clone(),
+                    payment.to_address.clone(),
+                    payment.amount,
+                    payment.currency.clone(),
+                    scheduled_at,
+                );
+
+                let outcome = match result {
+                    Ok(transaction) => {
+                        payment.advance(due_at);
+                        if transaction.status == TransactionStatus::Pending {
+                            RunOutcome::PendingReview(transaction.id)
+                        } else {
+                            RunOutcome::Paid(transaction.id)
+                        }
+                    }
+                    Err(e) => {
+                        println!("⚠️ Scheduled payment '{}' attempt {} failed: {}", payment.label, attempt, e);
+                        if attempt >= payment.retry_policy.max_attempts {
+                            payment.advance(due_at);
+                        } else {
+                            payment.attempts = attempt;
+                            payment.retry_at = Some(now + payment.retry_policy.retry_delay);
+                        }
+                        RunOutcome::Failed(e.to_string())
+                    }
+                };
+
+                if payment.next_run.is_none() {
+                    payment.status = match outcome {
+                        RunOutcome::Paid(_) | RunOutcome::PendingReview(_) => ScheduleStatus::Completed,
+                        RunOutcome::Failed(_) => ScheduleStatus::Failed,
+                    };
+                }
+
+                let run = ScheduledRun {
+                    schedule_id: id,
+                    due_at,
+                    attempted_at: now,
+                    attempt,
+                    outcome,
+                };
+                payment.runs.push(run.clone());
+                runs.push(run);
+            }
+        }
+
+        runs
+    }
+
+    pub fn cancel(&mut self, id: Uuid) -> Result<(), SchedulerError> {
+        let payment = self.schedules.get_mut(&id).ok_or(SchedulerError::ScheduleNotFound)?;
+        if payment.status != ScheduleStatus::Active {
+            return Err(SchedulerError::NotActive);
+        }
+
+        payment.status = ScheduleStatus::Cancelled;
+        payment.next_run = None;
+        payment.retry_at = None;
+        println!("🛑 Scheduled payment '{}' cancelled", payment.label);
+        Ok(())
+    }
+
+    pub fn get_schedule(&self, id: Uuid) -> Option<&ScheduledPayment> {
+        self.schedules.get(&id)
+    }
+
+    pub fn get_all_schedules(&self) -> Vec<&ScheduledPayment> {
+        let mut schedules: Vec<&ScheduledPayment> = self.schedules.values().collect();
+        schedules.sort_by_key(|payment| (payment.next_run.is_none(), payment.next_run));
+        schedules
+    }
+
+    // Các lịch đang chờ thử lại sau khi thất bại
+    pub fn get_retrying_schedules(&self) -> Vec<&ScheduledPayment> {
+        self.schedules.values()
+            .filter(|payment| payment.status == ScheduleStatus::Active && payment.attempts > 0)
+            .collect()
+    }
+}
+
+impl Default for PaymentScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchedulerError {
+    #[error("Invalid amount")]
+    InvalidAmount,
+    #[error("Invalid address: {0}")]
+    InvalidAddress(AddressError),
+    #[error("Retry policy must allow at least one attempt")]
+    InvalidRetryPolicy,
+    #[error("Invalid cron expression: {0}")]
+    InvalidCron(String),
+    #[error("Schedule has no upcoming run")]
+    NoUpcomingRun,
+    #[error("Scheduled payment not found")]
+    ScheduleNotFound,
+    #[error("Scheduled payment is not active")]
+    NotActive,
+}
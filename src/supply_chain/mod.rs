@@ -6,6 +6,9 @@ use std::collections::HashMap;
 pub struct SupplyChainManager {
     records: HashMap<Uuid, Vec<SupplyChainRecord>>,
     products: HashMap<Uuid, Product>,
+    // Thiết bị theo số serial -> sản phẩm; lịch sử của từng thiết bị nằm riêng trong `unit_records`
+    units: HashMap<Uuid, Uuid>,
+    unit_records: HashMap<Uuid, Vec<SupplyChainRecord>>,
 }
 
 impl SupplyChainManager {
@@ -13,6 +16,8 @@ impl SupplyChainManager {
         Self {
             records: HashMap::new(),
             products: HashMap::new(),
+            units: HashMap::new(),
+            unit_records: HashMap::new(),
         }
     }
 
@@ -51,6 +56,50 @@ impl SupplyChainManager {
         Ok(record)
     }
 
+    pub fn register_unit(&mut self, unit_id: Uuid, product_id: Uuid) -> Result<(), SupplyChainError> {
+        if !self.products.contains_key(&product_id) {
+            return Err(SupplyChainError::ProductNotFound);
+        }
+        self.units.insert(unit_id, product_id);
+        Ok(())
+    }
+
+    pub fn record_unit_movement(
+        &mut self,
+        unit_id: Uuid,
+        location: String,
+        handler: String,
+        action: SupplyChainAction,
+        metadata: serde_json::Value,
+    ) -> Result<SupplyChainRecord, SupplyChainError> {
+        let product_id = *self.units.get(&unit_id).ok_or(SupplyChainError::UnitNotFound)?;
+
+        let record = SupplyChainRecord {
+            product_id,
+            location: location.clone(),
+            handler,
+            timestamp: Utc::now(),
+            action: action.clone(),
+            metadata,
+        };
+        self.unit_records.entry(unit_id).or_default().push(record.clone());
+
+        println!("📦 Recorded {:?} for unit {} at {}", action, unit_id, location);
+        Ok(record)
+    }
+
+    pub fn get_unit_history(&self, unit_id: Uuid) -> Option<&Vec<SupplyChainRecord>> {
+        self.unit_records.get(&unit_id)
+    }
+
+    pub fn is_unit_tracked(&self, unit_id: Uuid) -> bool {
+        self.units.contains_key(&unit_id)
+    }
+
+    pub fn is_tracked(&self, product_id: Uuid) -> bool {
+        self.products.contains_key(&product_id)
+    }
+
     #[allow(dead_code)]
     pub fn get_product_history(&self, product_id: Uuid) -> Option<&Vec<SupplyChainRecord>> {
         self.records.get(&product_id)
@@ -83,6 +132,8 @@ impl SupplyChainManager {
 pub enum SupplyChainError {
     #[error("Product not found")]
     ProductNotFound,
+    #[error("Unit not found")]
+    UnitNotFound,
     #[allow(dead_code)]
     #[error("Invalid movement")]
     InvalidMovement,
@@ -0,0 +1,230 @@
+use crate::blockchain::Blockchain;
+use crate::models::{Currency, RetailToken, Transaction, TransactionStatus};
+use chrono::Utc;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Địa chỉ quy ước cho giao dịch mint/burn trên chain
+pub const MINT_ADDRESS: &str = "retail-mint";
+pub const BURN_ADDRESS: &str = "retail-burn";
+
+pub struct TokenRegistry {
+    tokens: HashMap<String, RetailToken>,
+    balances: HashMap<String, HashMap<String, u64>>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self {
+            tokens: HashMap::new(),
+            balances: HashMap::new(),
+        }
+    }
+
+    pub fn register_token(
+        &mut self,
+        symbol: String,
+        name: String,
+        decimals: u8,
+        issuer: String,
+    ) -> Result<RetailToken, TokenError> {
+        if symbol.is_empty() || issuer.is_empty() {
+            return Err(TokenError::InvalidToken);
+        }
+        if self.tokens.contains_key(&symbol) {
+            return Err(TokenError::TokenAlreadyExists(symbol));
+        }
+
+        let token = RetailToken {
+            symbol: symbol.clone(),
+            name,
+            decimals,
+            issuer,
+            total_supply: 0,
+        };
+
+        self.tokens.insert(symbol.clone(), token.clone());
+        self.balances.insert(symbol.clone(), HashMap::new());
+        println!("🪙 Registered token {} ({} decimals)", symbol, decimals);
+
+        Ok(token)
+    }
+
+    pub fn get_token(&self, symbol: &str) -> Option<&RetailToken> {
+        self.tokens.get(symbol)
+    }
+
+    pub fn get_all_tokens(&self) -> Vec<&RetailToken> {
+        self.tokens.values().collect()
+    }
+
+    pub fn total_supply(&self, symbol: &str) -> Option<u64> {
+        self.tokens.get(symbol).map(|token| token.total_supply)
+    }
+
+    pub fn balance_of(&self, symbol: &str, address: &str) -> u64 {
+        self.balances.get(symbol)
+            .and_then(|balances| balances.get(address))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Chỉ issuer của token mới được phát hành thêm
+    pub fn mint(
+        &mut self,
+        blockchain: &mut Blockchain,
+        symbol: &str,
+        issuer: &str,
+        to_address: &str,
+        amount: u64,
+    ) -> Result<Transaction, TokenError> {
+        let token = self.tokens.get(symbol)
+            .ok_or_else(|| TokenError::TokenNotFound(symbol.to_string()))?;
+        if token.issuer != issuer {
+            return Err(TokenError::Unauthorized);
+        }
+        if amount == 0 || to_address.is_empty() {
+            return Err(TokenError::InvalidAmount);
+        }
+        let new_supply = token.total_supply.checked_add(amount)
+            .ok_or(TokenError::SupplyOverflow)?;
+
+        self.credit(symbol, to_address, amount)?;
+        if let Some(token) = self.tokens.get_mut(symbol) {
+            token.total_supply = new_supply;
+        }
+
+        Ok(self.record_on_chain(blockchain, symbol, MINT_ADDRESS, to_address, amount))
+    }
+
+    pub fn burn(
+        &mut self,
+        blockchain: &mut Blockchain,
+        symbol: &str,
+        holder: &str,
+        amount: u64,
+    ) -> Result<Transaction, TokenError> {
+        if !self.tokens.contains_key(symbol) {
+            return Err(TokenError::TokenNotFound(symbol.to_string()));
+        }
+        if amount == 0 {
+            return Err(TokenError::InvalidAmount);
+        }
+
+        self.debit(symbol, holder, amount)?;
+        if let Some(token) = self.tokens.get_mut(symbol) {
+            token.total_supply -= amount;
+        }
+
+        Ok(self.record_on_chain(blockchain, symbol, holder, BURN_ADDRESS, amount))
+    }
+
+    pub fn transfer(
+        &mut self,
+        blockchain: &mut Blockchain,
+        symbol: &str,
+        from_address: &str,
+        to_address: &str,
+        amount: u64,
+    ) -> Result<Transaction, TokenError> {
+        if !self.tokens.contains_key(symbol) {
+            return Err(TokenError::TokenNotFound(symbol.to_string()));
+        }
+        if amount == 0 || to_address.is_empty() {
+            return Err(TokenError::InvalidAmount);
+        }
+        if self.balance_of(symbol, from_address) < amount {
+            return Err(TokenError::InsufficientBalance);
+        }
+
+        self.debit(symbol, from_address, amount)?;
+        self.credit(symbol, to_address, amount)?;
+
+        Ok(self.record_on_chain(blockchain, symbol, from_address, to_address, amount))
+    }
+
+    // Chuyển số lượng đơn vị nhỏ nhất sang số lượng hiển thị
+    pub fn to_display_amount(&self, symbol: &str, amount: u64) -> Option<f64> {
+        let token = self.tokens.get(symbol)?;
+        Some(amount as f64 / 10f64.powi(token.decimals as i32))
+    }
+
+    pub fn to_base_units(&self, symbol: &str, amount: f64) -> Option<u64> {
+        let token = self.tokens.get(symbol)?;
+        if amount < 0.0 {
+            return None;
+        }
+        Some((amount * 10f64.powi(token.decimals as i32)).round() as u64)
+    }
+
+    fn credit(&mut self, symbol: &str, address: &str, amount: u64) -> Result<(), TokenError> {
+        let balance = self.balances.entry(symbol.to_string())
+            .or_default()
+            .entry(address.to_string())
+            .or_insert(0);
+        *balance = balance.checked_add(amount).ok_or(TokenError::SupplyOverflow)?;
+        Ok(())
+    }
+
+    fn debit(&mut self, symbol: &str, address: &str, amount: u64) -> Result<(), TokenError> {
+        let balance = self.balances.get_mut(symbol)
+            .and_then(|balances| balances.get_mut(address))
+            .ok_or(TokenError::InsufficientBalance)?;
+        if *balance < amount {
+            return Err(TokenError::InsufficientBalance);
+        }
+
+        *balance -= amount;
+        Ok(())
+    }
+
+    fn record_on_chain(
+        &self,
+        blockchain: &mut Blockchain,
+        symbol: &str,
+        from_address: &str,
+        to_address: &str,
+        amount: u64,
+    ) -> Transaction {
+        let transaction = Transaction {
+            id: Uuid::new_v4(),
+            from_address: from_address.to_string(),
+            to_address: to_address.to_string(),
+            amount: self.to_display_amount(symbol, amount).unwrap_or(0.0),
+            currency: Currency::RETAIL(symbol.to_string()),
+            timestamp: Utc::now(),
+            status: TransactionStatus::Completed,
+            exchange_rate: 1.0,
+            refund_of: None,
+            fee: 0.0,
+            signature: None,
+        };
+
+        blockchain.add_transaction(transaction.clone());
+        transaction
+    }
+}
+
+impl Default for TokenRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("Invalid token definition")]
+    InvalidToken,
+    #[error("Token {0} already exists")]
+    TokenAlreadyExists(String),
+    #[error("Token {0} not found")]
+    TokenNotFound(String),
+    #[error("Only the token issuer can mint")]
+    Unauthorized,
+    #[error("Invalid amount")]
+    InvalidAmount,
+    #[error("Insufficient token balance")]
+    InsufficientBalance,
+    #[error("Token supply overflow")]
+    SupplyOverflow,
+}
@@ -0,0 +1,326 @@
+use super::{Wallet, WalletError, WalletSnapshot};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const KEYSTORE_VERSION: u32 = 1;
+pub const DEFAULT_KDF_ITERATIONS: u32 = 600_000;
+pub const MIN_KDF_ITERATIONS: u32 = 1_000;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    iterations: u32,
+    salt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CipherParams {
+    name: String,
+    nonce: String,
+}
+
+// Định dạng file keystore (JSON). Các tham số KDF được đưa vào AAD nên không sửa được mà không bị phát hiện
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    kdf: KdfParams,
+    cipher: CipherParams,
+    ciphertext: String,
+    password_check: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl KeystoreFile {
+    fn associated_data(&self) -> Vec<u8> {
+        format!("{}|{}|{}|{}", self.version, self.kdf.name, self.kdf.iterations, self.kdf.salt).into_bytes()
+    }
+}
+
+// Khóa dẫn xuất từ mật khẩu: nửa đầu để mã hóa, nửa sau để kiểm tra mật khẩu
+struct DerivedKey {
+    encryption_key: [u8; 32],
+    check_key: [u8; 32],
+}
+
+impl DerivedKey {
+    fn derive(password: &str, salt: &[u8], iterations: u32) -> Self {
+        let mut output = [0u8; 64];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut output);
+
+        let mut encryption_key = [0u8; 32];
+        let mut check_key = [0u8; 32];
+        encryption_key.copy_from_slice(&output[..32]);
+        check_key.copy_from_slice(&output[32..]);
+        output.fill(0);
+
+        Self { encryption_key, check_key }
+    }
+
+    fn password_check(&self) -> String {
+        hex::encode(Sha256::digest(self.check_key))
+    }
+}
+
+impl Drop for DerivedKey {
+    fn drop(&mut self) {
+        self.encryption_key.fill(0);
+        self.check_key.fill(0);
+    }
+}
+
+pub struct Keystore {
+    path: PathBuf,
+    file: KeystoreFile,
+    unlocked: Option<(DerivedKey, Wallet)>,
+}
+
+impl Keystore {
+    pub fn create(path: impl AsRef<Path>, wallet: Wallet, password: &str) -> Result<Self, KeystoreError> {
+        Self::create_with_iterations(path, wallet, password, DEFAULT_KDF_ITERATIONS)
+    }
+
+    pub fn create_with_iterations(
+        path: impl AsRef<Path>,
+        wallet: Wallet,
+        password: &str,
+        iterations: u32,
+    ) -> Result<Self, KeystoreError> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return Err(KeystoreError::AlreadyExists(path));
+        }
+        validate_password(password)?;
+        if iterations < MIN_KDF_ITERATIONS {
+            return Err(KeystoreError::WeakKdf);
+        }
+
+        let now = Utc::now();
+        let mut file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            kdf: KdfParams {
+                name: "pbkdf2-hmac-sha256".to_string(),
+                iterations,
+                salt: hex::encode(random_bytes(SALT_LENGTH)),
+            },
+            cipher: CipherParams {
+                name: "aes-256-gcm".to_string(),
+                nonce: String::new(),
+            },
+            ciphertext: String::new(),
+            password_check: String::new(),
+            created_at: now,
+            updated_at: now,
+        };
+
+        let key = derive_key(&file, password)?;
+        file.password_check = key.password_check();
+        encrypt_into(&mut file, &key, &wallet.snapshot())?;
+        write_atomically(&path, &file)?;
+        println!("🔐 Keystore created at {}", path.display());
+
+        Ok(Self {
+            path,
+            file,
+            unlocked: Some((key, wallet)),
+        })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, KeystoreError> {
+        let path = path.as_ref().to_path_buf();
+        let contents = fs::read_to_string(&path)?;
+        let file: KeystoreFile = serde_json::from_str(&contents)
+            .map_err(|e| KeystoreError::Corrupted(e.to_string()))?;
+
+        if file.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(file.version));
+        }
+        if file.kdf.name != "pbkdf2-hmac-sha256" || file.cipher.name != "aes-256-gcm" {
+            return Err(KeystoreError::Corrupted("unknown kdf or cipher".to_string()));
+        }
+
+        Ok(Self {
+            path,
+            file,
+            unlocked: None,
+        })
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Result<&Wallet, KeystoreError> {
+        let key = derive_key(&self.file, password)?;
+        if key.password_check() != self.file.password_check {
+            return Err(KeystoreError::WrongPassword);
+        }
+
+        let snapshot = decrypt(&self.file, &key)?;
+        let wallet = Wallet::restore(snapshot)?;
+        self.unlocked = Some((key, wallet));
+        println!("🔓 Keystore unlocked");
+
+        Ok(&self.unlocked.as_ref().unwrap().1)
+    }
+
+    pub fn lock(&mut self) {
+        if self.unlocked.take().is_some() {
+            println!("🔒 Keystore locked");
+        }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked.is_some()
+    }
+
+    pub fn wallet(&self) -> Result<&Wallet, KeystoreError> {
+        self.unlocked.as_ref().map(|(_, wallet)| wallet).ok_or(KeystoreError::Locked)
+    }
+
+    pub fn wallet_mut(&mut self) -> Result<&mut Wallet, KeystoreError> {
+        self.unlocked.as_mut().map(|(_, wallet)| wallet).ok_or(KeystoreError::Locked)
+    }
+
+    // Ghi lại các địa chỉ mới cấp vào file, dùng khóa đang mở
+    pub fn save(&mut self) -> Result<(), KeystoreError> {
+        let (key, wallet) = self.unlocked.as_ref().ok_or(KeystoreError::Locked)?;
+        let mut file = self.file.clone();
+        encrypt_into(&mut file, key, &wallet.snapshot())?;
+        write_atomically(&self.path, &file)?;
+        self.file = file;
+        Ok(())
+    }
+
+    pub fn change_password(&mut self, old_password: &str, new_password: &str) -> Result<(), KeystoreError> {
+        validate_password(new_password)?;
+        let old_key = derive_key(&self.file, old_password)?;
+        if old_key.password_check() != self.file.password_check {
+            return Err(KeystoreError::WrongPassword);
+        }
+
+        let snapshot = match &self.unlocked {
+            Some((_, wallet)) => wallet.snapshot(),
+            None => decrypt(&self.file, &old_key)?,
+        };
+
+        let mut file = self.file.clone();
+        file.kdf.salt = hex::encode(random_bytes(SALT_LENGTH));
+        let new_key = derive_key(&file, new_password)?;
+        file.password_check = new_key.password_check();
+        encrypt_into(&mut file, &new_key, &snapshot)?;
+        write_atomically(&self.path, &file)?;
+
+        self.file = file;
+        if let Some((key, _)) = self.unlocked.as_mut() {
+            *key = new_key;
+        }
+        println!("🔐 Keystore password changed");
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn validate_password(password: &str) -> Result<(), KeystoreError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(KeystoreError::WeakPassword);
+    }
+    Ok(())
+}
+
+fn derive_key(file: &KeystoreFile, password: &str) -> Result<DerivedKey, KeystoreError> {
+    let salt = hex::decode(&file.kdf.salt).map_err(|_| KeystoreError::Corrupted("invalid salt".to_string()))?;
+    if file.kdf.iterations < MIN_KDF_ITERATIONS {
+        return Err(KeystoreError::WeakKdf);
+    }
+    Ok(DerivedKey::derive(password, &salt, file.kdf.iterations))
+}
+
+fn encrypt_into(file: &mut KeystoreFile, key: &DerivedKey, snapshot: &WalletSnapshot) -> Result<(), KeystoreError> {
+    let mut plaintext = serde_json::to_vec(snapshot).map_err(|e| KeystoreError::Corrupted(e.to_string()))?;
+    let nonce = random_bytes(NONCE_LENGTH);
+    file.updated_at = Utc::now();
+    let aad = file.associated_data();
+
+    let cipher = Aes256Gcm::new_from_slice(&key.encryption_key).map_err(|_| KeystoreError::Encryption)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &aad })
+        .map_err(|_| KeystoreError::Encryption)?;
+    plaintext.fill(0);
+
+    file.cipher.nonce = hex::encode(nonce);
+    file.ciphertext = hex::encode(ciphertext);
+    Ok(())
+}
+
+fn decrypt(file: &KeystoreFile, key: &DerivedKey) -> Result<WalletSnapshot, KeystoreError> {
+    let nonce = hex::decode(&file.cipher.nonce)
+        .ok()
+        .filter(|nonce| nonce.len() == NONCE_LENGTH)
+        .ok_or_else(|| KeystoreError::Corrupted("invalid nonce".to_string()))?;
+    let ciphertext = hex::decode(&file.ciphertext)
+        .map_err(|_| KeystoreError::Corrupted("invalid ciphertext".to_string()))?;
+
+    let cipher = Aes256Gcm::new_from_slice(&key.encryption_key).map_err(|_| KeystoreError::Encryption)?;
+    let mut plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &file.associated_data() })
+        .map_err(|_| KeystoreError::Corrupted("authentication failed".to_string()))?;
+
+    let snapshot = serde_json::from_slice(&plaintext).map_err(|e| KeystoreError::Corrupted(e.to_string()));
+    plaintext.fill(0);
+    snapshot
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// Ghi ra file tạm rồi đổi tên để không bao giờ để lại keystore ghi dở
+fn write_atomically(path: &Path, file: &KeystoreFile) -> Result<(), KeystoreError> {
+    let contents = serde_json::to_string_pretty(file).map_err(|e| KeystoreError::Corrupted(e.to_string()))?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeystoreError {
+    #[error("Keystore I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Keystore already exists at {0}")]
+    AlreadyExists(PathBuf),
+    #[error("Keystore file is corrupted: {0}")]
+    Corrupted(String),
+    #[error("Unsupported keystore version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Wrong password")]
+    WrongPassword,
+    #[error("Password is too short")]
+    WeakPassword,
+    #[error("Key derivation parameters are too weak")]
+    WeakKdf,
+    #[error("Keystore is locked")]
+    Locked,
+    #[error("Encryption failed")]
+    Encryption,
+    #[error("Wallet error: {0}")]
+    Wallet(#[from] WalletError),
+}
@@ -0,0 +1,363 @@
+use crate::models::{Currency, Transaction};
+use bech32::{ToBase32, Variant};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use k256::elliptic_curve::PrimeField;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::{PublicKey, Scalar, SecretKey};
+use rand::RngCore;
+use ripemd::Ripemd160;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use sha3::Keccak256;
+use std::collections::HashMap;
+
+pub mod keystore;
+
+pub const HARDENED_OFFSET: u32 = 0x8000_0000;
+pub const BTC_ADDRESS_HRP: &str = "bc";
+pub const RETAIL_ADDRESS_HRP: &str = "rtl";
+// Coin type riêng cho token RETAIL trong đường dẫn BIP44
+pub const RETAIL_COIN_TYPE: u32 = 9_999;
+const SEED_LENGTH: usize = 32;
+
+type HmacSha512 = Hmac<Sha512>;
+
+// Khóa mở rộng theo BIP32
+#[derive(Clone)]
+struct ExtendedPrivateKey {
+    secret: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl ExtendedPrivateKey {
+    fn from_seed(seed: &[u8]) -> Result<Self, WalletError> {
+        let mut mac = HmacSha512::new_from_slice(b"Bitcoin seed")
+            .map_err(|_| WalletError::KeyDerivation)?;
+        mac.update(seed);
+        Self::from_hmac_output(&mac.finalize().into_bytes())
+    }
+
+    fn from_hmac_output(output: &[u8]) -> Result<Self, WalletError> {
+        let secret = SecretKey::from_slice(&output[..32]).map_err(|_| WalletError::KeyDerivation)?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&output[32..]);
+        Ok(Self { secret, chain_code })
+    }
+
+    fn derive_child(&self, index: u32) -> Result<Self, WalletError> {
+        let mut mac = HmacSha512::new_from_slice(&self.chain_code)
+            .map_err(|_| WalletError::KeyDerivation)?;
+        if index >= HARDENED_OFFSET {
+            mac.update(&[0u8]);
+            mac.update(&self.secret.to_bytes());
+        } else {
+            mac.update(self.secret.public_key().to_encoded_point(true).as_bytes());
+        }
+        mac.update(&index.to_be_bytes());
+        let output = mac.finalize().into_bytes();
+
+        let mut tweak_bytes = k256::FieldBytes::default();
+        tweak_bytes.copy_from_slice(&output[..32]);
+        let tweak = Option::<Scalar>::from(Scalar::from_repr(tweak_bytes))
+            .ok_or(WalletError::KeyDerivation)?;
+        let child = tweak + *self.secret.to_nonzero_scalar();
+        let secret = SecretKey::from_bytes(&child.to_bytes()).map_err(|_| WalletError::KeyDerivation)?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&output[32..]);
+        Ok(Self { secret, chain_code })
+    }
+
+    fn derive_path(&self, path: &[u32]) -> Result<Self, WalletError> {
+        path.iter().try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DerivedAddress {
+    pub address: String,
+    pub currency: Currency,
+    pub path: String,
+    pub index: u32,
+    pub order_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Trạng thái ví được mã hóa trong keystore: seed và các địa chỉ đã cấp
+#[derive(Serialize, Deserialize)]
+pub(crate) struct WalletSnapshot {
+    seed: String,
+    addresses: Vec<DerivedAddress>,
+}
+
+pub struct Wallet {
+    seed: Vec<u8>,
+    master: ExtendedPrivateKey,
+    next_indexes: HashMap<u32, u32>,
+    addresses: HashMap<String, DerivedAddress>,
+    order_addresses: HashMap<String, Vec<String>>,
+}
+
+impl Wallet {
+    pub fn generate() -> Result<Self, WalletError> {
+        let mut seed = vec![0u8; SEED_LENGTH];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        Self::from_seed(&seed)
+    }
+
+    pub fn from_seed(seed: &[u8]) -> Result<Self, WalletError> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(WalletError::InvalidSeed);
+        }
+
+        Ok(Self {
+            seed: seed.to_vec(),
+            master: ExtendedPrivateKey::from_seed(seed)?,
+            next_indexes: HashMap::new(),
+            addresses: HashMap::new(),
+            order_addresses: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn snapshot(&self) -> WalletSnapshot {
+        let mut addresses: Vec<DerivedAddress> = self.addresses.values().cloned().collect();
+        addresses.sort_by(|a, b| a.path.cmp(&b.path).then(a.address.cmp(&b.address)));
+
+        WalletSnapshot {
+            seed: hex::encode(&self.seed),
+            addresses,
+        }
+    }
+
+    pub(crate) fn restore(snapshot: WalletSnapshot) -> Result<Self, WalletError> {
+        let seed = hex::decode(&snapshot.seed).map_err(|_| WalletError::InvalidSeed)?;
+        let mut wallet = Self::from_seed(&seed)?;
+
+        for derived in snapshot.addresses {
+            let coin_type = coin_type(&derived.currency)?;
+            let next = wallet.next_indexes.entry(coin_type).or_insert(0);
+            *next = (*next).max(derived.index + 1);
+            if let Some(order_id) = &derived.order_id {
+                wallet.order_addresses
+                    .entry(order_id.clone())
+                    .or_default()
+                    .push(derived.address.clone());
+            }
+            wallet.addresses.insert(derived.address.clone(), derived);
+        }
+
+        Ok(wallet)
+    }
+
+    pub fn derive_address(&mut self, currency: &Currency) -> Result<DerivedAddress, WalletError> {
+        let coin_type = coin_type(currency)?;
+        let index = self.next_indexes.get(&coin_type).copied().unwrap_or(0);
+        let path = derivation_path(currency, index)?;
+        let key = self.master.derive_path(&path)?;
+
+        let derived = DerivedAddress {
+            address: encode_address(&key.secret.public_key(), currency)?,
+            currency: currency.clone(),
+            path: format_path(&path),
+            index,
+            order_id: None,
+            created_at: Utc::now(),
+        };
+
+        self.next_indexes.insert(coin_type, index + 1);
+        self.addresses.insert(derived.address.clone(), derived.clone());
+        Ok(derived)
+    }
+
+    // Mỗi đơn hàng nhận tiền vào một địa chỉ mới để dễ đối soát
+    pub fn new_order_address(&mut self, order_id: &str, currency: &Currency) -> Result<DerivedAddress, WalletError> {
+        if order_id.is_empty() {
+            return Err(WalletError::InvalidOrderId);
+        }
+
+        let mut derived = self.derive_address(currency)?;
+        derived.order_id = Some(order_id.to_string());
+        self.addresses.insert(derived.address.clone(), derived.clone());
+        self.order_addresses
+            .entry(order_id.to_string())
+            .or_default()
+            .push(derived.address.clone());
+
+        println!("🔑 Derived {} address {} for order {}", currency.symbol(), derived.address, order_id);
+        Ok(derived)
+    }
+
+    pub fn get_address(&self, address: &str) -> Option<&DerivedAddress> {
+        self.addresses.get(address)
+    }
+
+    pub fn get_order_for_address(&self, address: &str) -> Option<&str> {
+        self.addresses.get(address)?.order_id.as_deref()
+    }
+
+    pub fn get_order_addresses(&self, order_id: &str) -> Vec<&DerivedAddress> {
+        self.order_addresses.get(order_id)
+            .map(|addresses| addresses.iter().filter_map(|address| self.addresses.get(address)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn owns_address(&self, address: &str) -> bool {
+        self.addresses.contains_key(address)
+    }
+
+    pub fn get_all_addresses(&self) -> Vec<&DerivedAddress> {
+        self.addresses.values().collect()
+    }
+
+    // Ký giao dịch bằng khóa của địa chỉ gửi; chữ ký có recovery id để xác minh không cần public key
+    pub fn sign_transaction(&self, transaction: &mut Transaction) -> Result<(), WalletError> {
+        let derived = self.addresses.get(&transaction.from_address)
+            .ok_or_else(|| WalletError::UnknownAddress(transaction.from_address.clone()))?;
+        let path = derivation_path(&derived.currency, derived.index)?;
+        let key = self.master.derive_path(&path)?;
+
+        let signing_key = SigningKey::from(&key.secret);
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(&signing_hash(transaction))
+            .map_err(|_| WalletError::Signing)?;
+
+        let mut bytes = signature.to_bytes().to_vec();
+        bytes.push(recovery_id.to_byte());
+        transaction.signature = Some(hex::encode(bytes));
+        Ok(())
+    }
+}
+
+pub fn verify_transaction_signature(transaction: &Transaction) -> bool {
+    let Some(signature_hex) = &transaction.signature else {
+        return false;
+    };
+    let Ok(bytes) = hex::decode(signature_hex) else {
+        return false;
+    };
+    if bytes.len() != 65 {
+        return false;
+    }
+
+    let (Ok(signature), Some(recovery_id)) = (Signature::from_slice(&bytes[..64]), RecoveryId::from_byte(bytes[64])) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::recover_from_prehash(&signing_hash(transaction), &signature, recovery_id) else {
+        return false;
+    };
+
+    encode_address(&PublicKey::from(&verifying_key), &transaction.currency)
+        .map(|address| address == transaction.from_address)
+        .unwrap_or(false)
+}
+
+fn signing_hash(transaction: &Transaction) -> [u8; 32] {
+    let payload = serde_json::json!({
+        "id": transaction.id,
+        "from_address": transaction.from_address,
+        "to_address": transaction.to_address,
+        "amount": transaction.amount,
+        "currency": transaction.currency,
+        "timestamp": transaction.timestamp.to_rfc3339(),
+    });
+
+    Sha256::digest(payload.to_string().as_bytes()).into()
+}
+
+fn coin_type(currency: &Currency) -> Result<u32, WalletError> {
+    match currency {
+        Currency::BTC => Ok(0),
+        Currency::ETH | Currency::USDT => Ok(60),
+        Currency::RETAIL(_) => Ok(RETAIL_COIN_TYPE),
+        Currency::CASH => Err(WalletError::UnsupportedCurrency),
+    }
+}
+
+// BIP84 cho BTC (native segwit), BIP44 cho các loại còn lại
+fn derivation_path(currency: &Currency, index: u32) -> Result<Vec<u32>, WalletError> {
+    let purpose = if matches!(currency, Currency::BTC) { 84 } else { 44 };
+    Ok(vec![
+        purpose + HARDENED_OFFSET,
+        coin_type(currency)? + HARDENED_OFFSET,
+        HARDENED_OFFSET,
+        0,
+        index,
+    ])
+}
+
+fn format_path(path: &[u32]) -> String {
+    let mut formatted = String::from("m");
+    for index in path {
+        if *index >= HARDENED_OFFSET {
+            formatted.push_str(&format!("/{}'", index - HARDENED_OFFSET));
+        } else {
+            formatted.push_str(&format!("/{}", index));
+        }
+    }
+    formatted
+}
+
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+fn encode_address(public_key: &PublicKey, currency: &Currency) -> Result<String, WalletError> {
+    match currency {
+        Currency::BTC => {
+            let program = hash160(public_key.to_encoded_point(true).as_bytes());
+            let mut data = vec![bech32::u5::try_from_u8(0).map_err(|_| WalletError::AddressEncoding)?];
+            data.extend(program.to_base32());
+            bech32::encode(BTC_ADDRESS_HRP, data, Variant::Bech32).map_err(|_| WalletError::AddressEncoding)
+        }
+        Currency::ETH | Currency::USDT => {
+            let uncompressed = public_key.to_encoded_point(false);
+            let hash = Keccak256::digest(&uncompressed.as_bytes()[1..]);
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&hash[12..]);
+            Ok(to_checksum_address(&address))
+        }
+        Currency::RETAIL(_) => {
+            let program = hash160(public_key.to_encoded_point(true).as_bytes());
+            bech32::encode(RETAIL_ADDRESS_HRP, program.to_base32(), Variant::Bech32m)
+                .map_err(|_| WalletError::AddressEncoding)
+        }
+        Currency::CASH => Err(WalletError::UnsupportedCurrency),
+    }
+}
+
+// Địa chỉ Ethereum dạng EIP-55 (chữ hoa/thường mã hóa checksum)
+pub fn to_checksum_address(address: &[u8; 20]) -> String {
+    let lower = hex::encode(address);
+    let hash = Keccak256::digest(lower.as_bytes());
+
+    let mut checksummed = String::from("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        if c.is_ascii_alphabetic() && nibble >= 8 {
+            checksummed.push(c.to_ascii_uppercase());
+        } else {
+            checksummed.push(c);
+        }
+    }
+    checksummed
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WalletError {
+    #[error("Invalid wallet seed")]
+    InvalidSeed,
+    #[error("Key derivation failed")]
+    KeyDerivation,
+    #[error("Address encoding failed")]
+    AddressEncoding,
+    #[error("Currency has no on-chain address")]
+    UnsupportedCurrency,
+    #[error("Invalid order id")]
+    InvalidOrderId,
+    #[error("Address {0} does not belong to this wallet")]
+    UnknownAddress(String),
+    #[error("Signing failed")]
+    Signing,
+}